@@ -7,6 +7,13 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 // Create the app.
 #[wasm_bindgen]
-pub fn run() {
-    game::run();
+pub fn run() -> Result<(), JsValue> {
+    game::run().map_err(|err| JsValue::from_str(&err))
+}
+
+// Create the app using the given relay server (e.g. `ws://example.com:7100`) for online play.
+#[wasm_bindgen]
+pub fn run_with_relay(relay_url: String) -> Result<(), JsValue> {
+    game::run_with(game::NetplayOptions::with_relay(relay_url))
+        .map_err(|err| JsValue::from_str(&err))
 }
@@ -1,3 +1,7 @@
 fn main() {
-    game::run();
+    let result = game::NetplayOptions::from_args(std::env::args().skip(1)).and_then(game::run_with);
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,423 @@
+//! Arcade
+
+use crate::{
+    common::*, menu_background, menu_border, menu_button, menu_button_text, menu_root, time_text,
+    Character, FightClock, GameAssets, GameState, HighScores, MatchSettings, Player, Stages,
+    MAX_DIFFICULTY,
+};
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+
+/// Number of fights in the arcade ladder. Each one is harder than the last.
+const LADDER_FIGHTS: u8 = MAX_DIFFICULTY + 1;
+
+/// Number of opponents to beat in time attack.
+const TIME_ATTACK_FIGHTS: u8 = LADDER_FIGHTS;
+
+/// Share of the health lost in a survival fight that is recovered before the next one.
+const SURVIVAL_RECOVERY: f32 = 0.3;
+
+/// Seconds given to decide whether to continue after losing.
+const CONTINUE_SECS: f32 = 10.0;
+
+/// Handles the arcade mode screens.
+pub(crate) struct ArcadePlugin;
+
+impl Plugin for ArcadePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(HighScores::load())
+            .add_event::<ArcadeCommand>()
+            .add_system_set(SystemSet::on_exit(GameState::InGame).with_system(timer_system))
+            .add_system_set(
+                SystemSet::on_enter(GameState::ArcadeContinue).with_system(setup_continue),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::ArcadeContinue)
+                    .with_system(input_system.before(continue_system))
+                    .with_system(button_press_system.before(continue_system))
+                    .with_system(continue_system),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::ArcadeContinue).with_system(cleanup))
+            .add_system_set(SystemSet::on_enter(GameState::ArcadeEnding).with_system(setup_ending))
+            .add_system_set(
+                SystemSet::on_update(GameState::ArcadeEnding)
+                    .with_system(input_system.before(ending_system))
+                    .with_system(button_press_system.before(ending_system))
+                    .with_system(ending_system),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::ArcadeEnding).with_system(cleanup));
+    }
+}
+
+/// Single player modes against CPU opponents.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum ArcadeMode {
+    /// Beat a ladder of opponents, continuing as often as needed.
+    #[default]
+    Ladder,
+
+    /// Beat as many opponents in a row as possible. Health carries over between fights.
+    Survival,
+
+    /// Beat a number of opponents as fast as possible.
+    TimeAttack,
+}
+
+/// Single player run through CPU opponents. Only present during single player modes.
+#[derive(Resource, Default)]
+pub(crate) struct Arcade {
+    mode: ArcadeMode,
+
+    /// Fight being played. Also the number of fights won.
+    fight: u8,
+
+    /// Number of times the player continued after losing.
+    continues: u8,
+
+    /// Whether the last fight was lost.
+    lost: bool,
+
+    /// Health player one had left after winning the last survival fight.
+    health: Option<u8>,
+
+    /// Ticks simulated fighting in time attack.
+    ticks: u32,
+}
+
+impl Arcade {
+    pub(crate) fn new(mode: ArcadeMode) -> Self {
+        Self { mode, ..default() }
+    }
+
+    /// Set up the match for the current fight. Opponents, stages and difficulty change as the
+    /// player climbs the ladder.
+    pub(crate) fn apply(&self, settings: &mut MatchSettings, stages: &Stages) {
+        let n = Character::ALL.len();
+        let player = settings.characters[Player::One.index()];
+        let opponent = Character::ALL[(player.index() + 1 + self.fight as usize) % n];
+        settings.characters[Player::Two.index()] = opponent;
+        settings.palettes[Player::Two.index()] = self.fight / n as u8;
+        settings.stage = (0..self.fight).fold(0, |stage, _| stages.cycle(stage, true));
+        settings.cpu = Some(self.fight.min(MAX_DIFFICULTY));
+    }
+
+    /// Health player one starts a fight with. Survival carries health over between fights,
+    /// recovering some of what was lost.
+    pub(crate) fn starting_health(&self, max_health: u8) -> u8 {
+        match self.health {
+            Some(health) if self.mode == ArcadeMode::Survival => {
+                let recovered = ((max_health - health) as f32 * SURVIVAL_RECOVERY) as u8;
+                health.saturating_add(recovered).min(max_health)
+            }
+            _ => max_health,
+        }
+    }
+
+    /// Record how a fight ended along with player one's health left.
+    pub(crate) fn record(&mut self, winner: Option<Player>, health: u8) {
+        self.lost = winner != Some(Player::One);
+        if self.lost {
+            self.health = None;
+        } else {
+            self.fight = self.fight.saturating_add(1);
+            self.health = Some(health);
+        }
+    }
+
+    /// Whether the run is over.
+    fn finished(&self) -> bool {
+        match self.mode {
+            ArcadeMode::Ladder => !self.lost && self.fight == LADDER_FIGHTS,
+            ArcadeMode::Survival => self.lost,
+            ArcadeMode::TimeAttack => !self.lost && self.fight == TIME_ATTACK_FIGHTS,
+        }
+    }
+
+    /// Score of the run so far. Fights won in survival and time taken in time attack.
+    fn score(&self) -> u32 {
+        match self.mode {
+            ArcadeMode::TimeAttack => self.ticks.saturating_mul(100) / TICK_RATE,
+            ArcadeMode::Ladder | ArcadeMode::Survival => self.fight as u32,
+        }
+    }
+
+    /// Text for a score in the run's mode.
+    fn score_text(&self, score: u32) -> String {
+        match self.mode {
+            ArcadeMode::TimeAttack => time_text(score),
+            ArcadeMode::Ladder | ArcadeMode::Survival => format!("{} WINS", score),
+        }
+    }
+
+    /// Lines showing the progress of the run after a fight. Survival and time attack runs that
+    /// are over add their score to the high scores and show them.
+    pub(crate) fn results(
+        &self,
+        character: Character,
+        high_scores: &mut HighScores,
+    ) -> Vec<String> {
+        if self.mode == ArcadeMode::Ladder {
+            return Vec::new();
+        }
+
+        let score = self.score();
+        let mut lines = vec![format!("SCORE: {}", self.score_text(score))];
+        if !self.finished() {
+            return lines;
+        }
+
+        let (rank, scores) = if self.mode == ArcadeMode::Survival {
+            let rank = high_scores.add_survival(character.name(), score);
+            (rank, &high_scores.survival)
+        } else {
+            let rank = high_scores.add_time_attack(character.name(), score);
+            (rank, &high_scores.time_attack)
+        };
+
+        if rank.is_some() {
+            lines.push("NEW HIGH SCORE!".to_string());
+        }
+        lines.push("HIGH SCORES".to_string());
+        for (i, high_score) in scores.iter().enumerate() {
+            lines.push(format!(
+                "{}. {} {}",
+                i + 1,
+                high_score.character,
+                self.score_text(high_score.score)
+            ));
+        }
+        lines
+    }
+
+    /// Move on after a fight. Winning sets up the next fight while losing offers a continue
+    /// unless the run is over. Returns the state to switch to.
+    pub(crate) fn next(&mut self, settings: &mut MatchSettings, stages: &Stages) -> GameState {
+        if self.finished() {
+            match self.mode {
+                ArcadeMode::Ladder => GameState::ArcadeEnding,
+                ArcadeMode::Survival | ArcadeMode::TimeAttack => GameState::MainMenu,
+            }
+        } else if self.lost {
+            GameState::ArcadeContinue
+        } else {
+            self.apply(settings, stages);
+            GameState::InGame
+        }
+    }
+}
+
+/// Arcade screen entities.
+#[derive(Resource)]
+struct EntityData {
+    entities: Vec<Entity>,
+}
+
+/// Actions from keyboard and buttons.
+enum ArcadeCommand {
+    Confirm,
+    Quit,
+}
+
+/// Represents arcade screen buttons.
+#[derive(Component)]
+enum ArcadeButton {
+    Confirm,
+    Quit,
+}
+
+/// Time left to continue.
+#[derive(Component)]
+struct ContinueTimer(Timer);
+
+/// Setup the continue screen.
+fn setup_continue(mut commands: Commands, assets: Res<GameAssets>) {
+    let mut entities: Vec<Entity> = Vec::new();
+
+    entities.push(
+        commands
+            .spawn(menu_root())
+            .with_children(|parent| {
+                parent.spawn(menu_border()).with_children(|parent| {
+                    parent.spawn(menu_background()).with_children(|parent| {
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent.spawn(menu_button_text(&assets, "QUIT"));
+                                parent.spawn(ImageBundle {
+                                    image: UiImage(assets.escape_key_image.clone()),
+                                    transform: Transform::from_scale(Vec3::new(0.58, 0.58, 0.58)),
+                                    ..default()
+                                });
+                            })
+                            .insert(ArcadeButton::Quit);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent.spawn(menu_button_text(&assets, "CONTINUE"));
+                                parent.spawn(ImageBundle {
+                                    image: UiImage(assets.return_key_image.clone()),
+                                    transform: Transform::from_scale(Vec3::new(0.5, 0.5, 0.5)),
+                                    ..default()
+                                });
+                            })
+                            .insert(ArcadeButton::Confirm);
+
+                        parent
+                            .spawn(menu_button_text(&assets, ""))
+                            .insert(ContinueTimer(Timer::from_seconds(
+                                CONTINUE_SECS,
+                                TimerMode::Once,
+                            )));
+                    });
+                });
+            })
+            .id(),
+    );
+
+    commands.insert_resource(EntityData { entities });
+}
+
+/// Setup the ending shown after beating the whole ladder.
+fn setup_ending(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    audio: Res<Audio>,
+    arcade: Res<Arcade>,
+    settings: Res<MatchSettings>,
+) {
+    audio.play(assets.main_menu_audio.clone()).looped();
+
+    let champion = format!(
+        "{} IS THE CHAMPION!",
+        settings.characters[Player::One.index()].name()
+    );
+    let continues = format!("CONTINUES USED: {}", arcade.continues);
+
+    let mut entities: Vec<Entity> = Vec::new();
+
+    entities.push(
+        commands
+            .spawn(menu_root())
+            .with_children(|parent| {
+                parent.spawn(menu_border()).with_children(|parent| {
+                    parent.spawn(menu_background()).with_children(|parent| {
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent.spawn(menu_button_text(&assets, "MAIN MENU"));
+                                parent.spawn(ImageBundle {
+                                    image: UiImage(assets.return_key_image.clone()),
+                                    transform: Transform::from_scale(Vec3::new(0.5, 0.5, 0.5)),
+                                    ..default()
+                                });
+                            })
+                            .insert(ArcadeButton::Confirm);
+
+                        parent.spawn(menu_button_text(&assets, &continues));
+                        parent.spawn(menu_button_text(&assets, &champion));
+                        parent.spawn(menu_button_text(&assets, "CONGRATULATIONS"));
+                    });
+                });
+            })
+            .id(),
+    );
+
+    commands.insert_resource(EntityData { entities });
+}
+
+/// Count the time spent fighting in time attack once a fight ends. Only simulated ticks count so
+/// times don't depend on how fast the game runs.
+fn timer_system(clock: Res<FightClock>, arcade: Option<ResMut<Arcade>>) {
+    if let Some(mut arcade) = arcade {
+        if arcade.mode == ArcadeMode::TimeAttack {
+            arcade.ticks = arcade.ticks.saturating_add(clock.tick);
+        }
+    }
+}
+
+/// Handle keyboard input.
+fn input_system(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut arcade_commands: EventWriter<ArcadeCommand>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        arcade_commands.send(ArcadeCommand::Confirm);
+        keyboard_input.clear_just_pressed(KeyCode::Return);
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        arcade_commands.send(ArcadeCommand::Quit);
+        keyboard_input.clear_just_pressed(KeyCode::Escape);
+    }
+}
+
+/// Processes button press.
+#[allow(clippy::type_complexity)]
+fn button_press_system(
+    buttons: Query<(&Interaction, &ArcadeButton), (Changed<Interaction>, With<Button>)>,
+    mut arcade_commands: EventWriter<ArcadeCommand>,
+) {
+    for (interaction, button) in buttons.iter() {
+        if *interaction == Interaction::Clicked {
+            arcade_commands.send(match button {
+                ArcadeButton::Confirm => ArcadeCommand::Confirm,
+                ArcadeButton::Quit => ArcadeCommand::Quit,
+            });
+        }
+    }
+}
+
+/// Count down the time left to continue. Continuing fights the same opponent again.
+fn continue_system(
+    time: Res<Time>,
+    mut arcade_commands: EventReader<ArcadeCommand>,
+    mut arcade: ResMut<Arcade>,
+    mut timer_query: Query<(&mut ContinueTimer, &mut Text)>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let mut quit = false;
+    for (mut timer, mut text) in &mut timer_query {
+        timer.0.tick(time.delta());
+        let remaining = timer.0.remaining_secs().ceil() as u32;
+        text.sections[0].value = format!("CONTINUE? {}", remaining);
+        quit |= timer.0.finished();
+    }
+
+    for command in arcade_commands.iter() {
+        match command {
+            ArcadeCommand::Confirm if !quit => {
+                arcade.continues = arcade.continues.saturating_add(1);
+                state
+                    .set(GameState::InGame)
+                    .expect("Couldn't switch state to InGame");
+                return;
+            }
+            _ => quit = true,
+        }
+    }
+
+    if quit {
+        state
+            .set(GameState::MainMenu)
+            .expect("Couldn't switch state to MainMenu");
+    }
+}
+
+/// Return to the main menu once the player is done with the ending.
+fn ending_system(
+    mut arcade_commands: EventReader<ArcadeCommand>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if arcade_commands.iter().next().is_some() {
+        state
+            .set(GameState::MainMenu)
+            .expect("Couldn't switch state to MainMenu");
+    }
+}
+
+/// Cleanup resources.
+fn cleanup(mut commands: Commands, entity_data: Res<EntityData>, audio: Res<Audio>) {
+    for entity in entity_data.entities.iter() {
+        commands.entity(*entity).despawn_recursive();
+    }
+    audio.stop();
+}
@@ -0,0 +1,165 @@
+//! Hit Callouts
+
+use crate::{common::*, GameAssets, GameState, Player};
+use bevy::prelude::*;
+
+/// How long a callout is shown for.
+const CALLOUT_SECS: f32 = 0.8;
+
+/// Height above the hit a callout is shown at.
+const CALLOUT_Y: f32 = 60.0;
+
+/// How far a callout rises while it's shown.
+const CALLOUT_RISE: f32 = 30.0;
+
+/// Handles the text called out for special kinds of hits.
+pub(crate) struct CalloutPlugin;
+
+impl Plugin for CalloutPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CalloutEvent>()
+            .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(setup))
+            .add_system_set(SystemSet::on_update(GameState::InGame).with_system(callout_system))
+            // Let the callout for the final blow play out.
+            .add_system_set(SystemSet::on_update(GameState::GameOver).with_system(callout_system))
+            .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(cleanup));
+    }
+}
+
+/// Special kinds of hits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Callout {
+    /// Hitting an opponent during the startup of their move.
+    Counter,
+
+    /// Hitting an opponent while they recover from their move.
+    Punish,
+}
+
+impl Callout {
+    fn text(&self) -> &'static str {
+        match self {
+            Self::Counter => "COUNTER",
+            Self::Punish => "PUNISH",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Self::Counter => Color::rgb(1.0, 0.3, 0.2),
+            Self::Punish => Color::rgb(1.0, 0.85, 0.2),
+        }
+    }
+}
+
+/// Call out a special kind of hit near the player who landed it. Not sent while resimulating
+/// ticks.
+pub(crate) struct CalloutEvent {
+    pub(crate) player: Player,
+    pub(crate) callout: Callout,
+    pub(crate) position: Vec2,
+}
+
+impl CalloutEvent {
+    pub(crate) fn new(player: Player, callout: Callout, position: Vec2) -> Self {
+        Self {
+            player,
+            callout,
+            position,
+        }
+    }
+}
+
+/// Callout entities.
+#[derive(Resource)]
+struct EntityData {
+    entities: Vec<Entity>,
+}
+
+/// Text called out for a player. Each player has one which is hidden until used.
+#[derive(Component)]
+struct CalloutText {
+    player: Player,
+    timer: Timer,
+    position: Vec2,
+}
+
+/// Setup a callout for each player.
+fn setup(mut commands: Commands, assets: Res<GameAssets>) {
+    let mut entities: Vec<Entity> = Vec::new();
+
+    for player in [Player::One, Player::Two] {
+        let mut timer = Timer::from_seconds(CALLOUT_SECS, TimerMode::Once);
+        timer.tick(timer.duration());
+
+        entities.push(
+            commands
+                .spawn(Text2dBundle {
+                    text: Text::from_section(
+                        "",
+                        TextStyle {
+                            font: assets.font.clone(),
+                            font_size: 32.0,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_alignment(TextAlignment::CENTER),
+                    transform: Transform::from_xyz(0.0, 0.0, EFFECT_Z + 0.05),
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                })
+                .insert(CalloutText {
+                    player,
+                    timer,
+                    position: Vec2::ZERO,
+                })
+                .id(),
+        );
+    }
+
+    commands.insert_resource(EntityData { entities });
+}
+
+/// Show callouts, rising and fading them out over time.
+fn callout_system(
+    time: Res<Time>,
+    mut callout_events: EventReader<CalloutEvent>,
+    mut callout_query: Query<(&mut CalloutText, &mut Text, &mut Transform, &mut Visibility)>,
+) {
+    for event in callout_events.iter() {
+        for (mut callout_text, mut text, _, mut visibility) in &mut callout_query {
+            if callout_text.player != event.player {
+                continue;
+            }
+            callout_text.timer.reset();
+            callout_text.position = event.position + Vec2::new(0.0, CALLOUT_Y);
+            text.sections[0].value = event.callout.text().to_string();
+            text.sections[0].style.color = event.callout.color();
+            visibility.is_visible = true;
+        }
+    }
+
+    for (mut callout_text, mut text, mut transform, mut visibility) in &mut callout_query {
+        if !visibility.is_visible {
+            continue;
+        }
+
+        callout_text.timer.tick(time.delta());
+        if callout_text.timer.finished() {
+            visibility.is_visible = false;
+            continue;
+        }
+
+        let percent = callout_text.timer.percent();
+        transform.translation.x = callout_text.position.x;
+        transform.translation.y = callout_text.position.y + CALLOUT_RISE * percent;
+        text.sections[0].style.color.set_a(1.0 - percent);
+    }
+}
+
+/// Cleanup resources.
+fn cleanup(mut commands: Commands, entity_data: Res<EntityData>) {
+    for entity in entity_data.entities.iter() {
+        commands.entity(*entity).despawn_recursive();
+    }
+}
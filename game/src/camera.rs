@@ -0,0 +1,154 @@
+//! Camera
+
+use crate::{common::*, Health, KnockOut, MatchSettings, Player, Stage, Stages};
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    prelude::*,
+    render::{camera::ScalingMode, view::RenderLayers},
+};
+
+/// How far the camera zooms out when the fighters separate.
+const MAX_ZOOM: f32 = 1.25;
+
+/// Space the camera tries to keep between the fighters and the edges of the view.
+const CAMERA_MARGIN: f32 = 150.0;
+
+/// Closest the fighters can get to the edges of the view.
+const EDGE_MARGIN: f32 = 25.0;
+
+/// Zoom when closing in on the winner of a knock out.
+const KNOCK_OUT_ZOOM: f32 = 0.7;
+
+/// How quickly the camera catches up with the fighters.
+const CAMERA_SPEED: f32 = 8.0;
+
+/// Handles the cameras.
+pub(crate) struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup).add_system(follow_system);
+    }
+}
+
+/// Camera looking at the stage and the fighters.
+#[derive(Component)]
+pub(crate) struct StageCamera;
+
+/// Widest view of a stage. Stages no wider than the window don't zoom.
+fn max_view_width(stage: &Stage) -> f32 {
+    (VIEW_WIDTH * MAX_ZOOM).min(stage.width).max(VIEW_WIDTH)
+}
+
+/// Furthest apart the fighters can get while both stay in view.
+pub(crate) fn max_player_distance(stage: &Stage) -> f32 {
+    max_view_width(stage) - 2.0 * EDGE_MARGIN
+}
+
+/// Camera drawing the UI over the whole window, letterbox bars included.
+#[derive(Component)]
+pub(crate) struct UiCamera;
+
+/// 2D camera that always shows the whole virtual resolution.
+fn view_camera() -> Camera2dBundle {
+    let mut camera = Camera2dBundle::default();
+    camera.projection.scaling_mode = ScalingMode::Auto {
+        min_width: VIEW_WIDTH,
+        min_height: VIEW_HEIGHT,
+    };
+    camera
+}
+
+/// Setup the stage camera, the HUD camera drawn over it and the UI camera drawn over both.
+fn setup(mut commands: Commands) {
+    commands
+        .spawn(view_camera())
+        .insert(StageCamera)
+        .insert(UiCameraConfig { show_ui: false });
+
+    // The HUD doesn't move or zoom.
+    commands
+        .spawn(Camera2dBundle {
+            camera: Camera {
+                priority: 1,
+                ..default()
+            },
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::None,
+            },
+            ..view_camera()
+        })
+        .insert(UiCameraConfig { show_ui: false })
+        .insert(RenderLayers::layer(HUD_LAYER));
+
+    // UI is laid out for the whole window so its camera isn't letterboxed. It doesn't draw any
+    // sprites.
+    commands
+        .spawn(Camera2dBundle {
+            camera: Camera {
+                priority: 2,
+                ..default()
+            },
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::None,
+            },
+            ..default()
+        })
+        .insert(UiCamera)
+        .insert(RenderLayers::layer(UI_LAYER));
+}
+
+/// Follow the midpoint of the fighters and zoom out as they separate without showing anything
+/// past the edges of the stage. The camera closes in on the winner of a knock out and is centered
+/// when nobody is fighting.
+#[allow(clippy::type_complexity)]
+fn follow_system(
+    time: Res<Time>,
+    settings: Res<MatchSettings>,
+    stages: Option<Res<Stages>>,
+    knock_out: Option<Res<KnockOut>>,
+    player_query: Query<(&Player, &Transform), (With<Health>, Without<StageCamera>)>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), With<StageCamera>>,
+) {
+    let (mut transform, mut projection) = match camera_query.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
+    let mut positions = [None; 2];
+    for (player, player_transform) in &player_query {
+        positions[player.index()] = Some(player_transform.translation.x);
+    }
+    let (stages, positions) = match (stages, positions) {
+        (Some(stages), [Some(one), Some(two)]) => (stages, [one, two]),
+        _ => {
+            transform.translation.x = 0.0;
+            transform.translation.y = 0.0;
+            projection.scale = 1.0;
+            return;
+        }
+    };
+    let stage = stages.get(settings.stage);
+
+    let (target_x, view_width) = match knock_out.and_then(|knock_out| knock_out.posing()) {
+        Some(winner) => (positions[winner.index()], VIEW_WIDTH * KNOCK_OUT_ZOOM),
+        None => {
+            let min = positions[0].min(positions[1]);
+            let max = positions[0].max(positions[1]);
+            let view_width =
+                (max - min + 2.0 * CAMERA_MARGIN).clamp(VIEW_WIDTH, max_view_width(stage));
+            ((min + max) / 2.0, view_width)
+        }
+    };
+
+    let t = (CAMERA_SPEED * time.delta_seconds()).min(1.0);
+    projection.scale += (view_width / VIEW_WIDTH - projection.scale) * t;
+
+    // Keep the view inside the stage.
+    let limit = ((stage.width - projection.scale * VIEW_WIDTH) / 2.0).max(0.0);
+    let x = transform.translation.x + (target_x - transform.translation.x) * t;
+    transform.translation.x = x.clamp(-limit, limit);
+
+    // Keep the bottom of the view on the ground while zooming.
+    transform.translation.y = (projection.scale - 1.0) * VIEW_HEIGHT / 2.0;
+}
@@ -0,0 +1,105 @@
+//! Character
+
+use crate::GameAssets;
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+
+/// Tints applied to Mack's sprite sheet. The first one keeps the original look.
+const MACK_PALETTES: [Color; 3] = [
+    Color::WHITE,
+    Color::rgb(0.55, 0.75, 1.0),
+    Color::rgb(1.0, 0.6, 0.6),
+];
+
+/// Tints applied to Kenji's sprite sheet. The first one keeps the original look.
+const KENJI_PALETTES: [Color; 3] = [
+    Color::WHITE,
+    Color::rgb(1.0, 0.85, 0.45),
+    Color::rgb(0.6, 1.0, 0.6),
+];
+
+/// Playable fighters.
+#[derive(Component, Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) enum Character {
+    #[default]
+    Mack,
+    Kenji,
+}
+
+impl Character {
+    /// Roster in the order shown on the character select screen.
+    pub(crate) const ALL: [Character; 2] = [Self::Mack, Self::Kenji];
+
+    /// Index into per-character data.
+    pub(crate) fn index(&self) -> usize {
+        match self {
+            Self::Mack => 0,
+            Self::Kenji => 1,
+        }
+    }
+
+    /// Character at an index into the roster.
+    pub(crate) fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+
+    /// Name shown on screen.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Mack => "MACK",
+            Self::Kenji => "KENJI",
+        }
+    }
+
+    /// Next or previous character in the roster.
+    pub(crate) fn cycle(&self, forward: bool) -> Self {
+        let n = Self::ALL.len();
+        let i = self.index();
+        Self::ALL[if forward {
+            (i + 1) % n
+        } else {
+            (i + n - 1) % n
+        }]
+    }
+
+    /// Sprite sheet for the character.
+    pub(crate) fn texture_atlas(&self, assets: &GameAssets) -> Handle<TextureAtlas> {
+        match self {
+            Self::Mack => assets.player_one_texture_atlas.clone(),
+            Self::Kenji => assets.player_two_texture_atlas.clone(),
+        }
+    }
+
+    /// Color palettes the character can be drawn with.
+    pub(crate) fn palettes(&self) -> &'static [Color] {
+        match self {
+            Self::Mack => &MACK_PALETTES,
+            Self::Kenji => &KENJI_PALETTES,
+        }
+    }
+
+    /// Tint for one of the character's palettes. Out of range palettes wrap around.
+    pub(crate) fn palette(&self, palette: u8) -> Color {
+        let palettes = self.palettes();
+        palettes[palette as usize % palettes.len()]
+    }
+
+    /// Next or previous palette of the character.
+    pub(crate) fn cycle_palette(&self, palette: u8, forward: bool) -> u8 {
+        let n = self.palettes().len() as u8;
+        let i = palette % n;
+        if forward {
+            (i + 1) % n
+        } else {
+            (i + n - 1) % n
+        }
+    }
+
+    /// Sound played when the character attacks.
+    pub(crate) fn attack_audio(&self, assets: &GameAssets) -> Handle<AudioSource> {
+        match self {
+            Self::Mack => assets.player_one_attack_audio.clone(),
+            Self::Kenji => assets.player_two_attack_audio.clone(),
+        }
+    }
+}
@@ -0,0 +1,395 @@
+//! Character Select
+
+use crate::{
+    common::*, next_idle_frame, Arcade, Character, GameAssets, GameState, MatchSettings, Player,
+    Stages, IDLE_FRAME_START,
+};
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+
+/// Scaling factor for roster portraits.
+const PORTRAIT_SCALE: f32 = 1.5;
+
+/// Horizontal distance between roster portraits.
+const PORTRAIT_SPACING: f32 = 150.0;
+
+/// Location of the roster grid.
+const ROSTER_Y: f32 = 140.0;
+
+/// Location of each side's preview of the highlighted character.
+const PREVIEW_X: f32 = 300.0;
+const PREVIEW_Y: f32 = -60.0;
+
+/// Scaling factor for previews.
+const PREVIEW_SCALE: f32 = 2.5;
+
+/// Colors used to mark each player's cursor.
+const CURSOR_COLORS: [Color; 2] = [Color::RED, Color::rgb(0.2, 0.5, 1.0)];
+
+/// Handles picking the characters before a match.
+pub(crate) struct CharacterSelectPlugin;
+
+impl Plugin for CharacterSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(GameState::CharacterSelect).with_system(setup))
+            .add_system_set(
+                SystemSet::on_update(GameState::CharacterSelect)
+                    .with_system(input_system)
+                    .with_system(cursor_system.after(input_system))
+                    .with_system(animation_system),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::CharacterSelect).with_system(cleanup));
+    }
+}
+
+/// Character select entities.
+#[derive(Resource)]
+struct EntityData {
+    entities: Vec<Entity>,
+}
+
+/// Highlighted character, its palette and whether it's been picked for each player.
+#[derive(Resource)]
+struct Selection {
+    cursors: [Character; 2],
+    palettes: [u8; 2],
+    confirmed: [bool; 2],
+}
+
+impl Selection {
+    /// Palettes the previews are drawn with. Same as they would be in the match.
+    fn match_palettes(&self) -> [u8; 2] {
+        MatchSettings {
+            characters: self.cursors,
+            palettes: self.palettes,
+            ..default()
+        }
+        .match_palettes()
+    }
+}
+
+/// Marks a player's cursor in the roster.
+#[derive(Component)]
+struct RosterCursor(Player);
+
+/// Large animated sprite of the character highlighted by a player.
+#[derive(Component)]
+struct Preview(Player);
+
+/// Name of the character highlighted by a player.
+#[derive(Component)]
+struct PreviewName(Player);
+
+/// Controls for moving a player's cursor (previous, next, confirm, palette). The palette key
+/// undoes a pick once it's been made.
+fn select_keys(player: Player) -> [KeyCode; 4] {
+    match player {
+        Player::One => [KeyCode::A, KeyCode::D, KeyCode::S, KeyCode::W],
+        Player::Two => [KeyCode::Left, KeyCode::Right, KeyCode::Down, KeyCode::Up],
+    }
+}
+
+/// Position of a character in the roster grid.
+fn roster_position(character: Character) -> Vec2 {
+    let n = Character::ALL.len() as f32;
+    let x = (character.index() as f32 - (n - 1.0) / 2.0) * PORTRAIT_SPACING;
+    Vec2::new(x, ROSTER_Y)
+}
+
+/// Offset of a player's cursor from the portrait so both can sit on the same character.
+fn cursor_offset(player: Player) -> Vec2 {
+    match player {
+        Player::One => Vec2::new(-30.0, -70.0),
+        Player::Two => Vec2::new(30.0, -70.0),
+    }
+}
+
+/// Setup the character select screen.
+fn setup(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    audio: Res<Audio>,
+    settings: Res<MatchSettings>,
+) {
+    audio.play(assets.main_menu_audio.clone()).looped();
+
+    // Start from the characters picked last time.
+    let selection = Selection {
+        cursors: settings.characters,
+        palettes: settings.palettes,
+        confirmed: [false; 2],
+    };
+    let palettes = selection.match_palettes();
+
+    let text_style = |color: Color, font_size: f32| TextStyle {
+        font: assets.font.clone(),
+        font_size,
+        color,
+    };
+
+    let mut entities: Vec<Entity> = Vec::new();
+
+    entities.push(
+        commands
+            .spawn(Text2dBundle {
+                text: Text::from_section("SELECT FIGHTER", text_style(Color::WHITE, 32.0))
+                    .with_alignment(TextAlignment::CENTER),
+                transform: Transform::from_xyz(0.0, VIEW_HEIGHT / 2.0 - 30.0, PLAYER_Z),
+                ..default()
+            })
+            .id(),
+    );
+
+    // Roster grid.
+    for character in Character::ALL {
+        let pos = roster_position(character);
+
+        entities.push(
+            commands
+                .spawn(SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::rgba(0.05, 0.05, 0.05, 0.8),
+                        custom_size: Some(Vec2::new(110.0, 110.0)),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(pos.extend(PLAYER_Z)),
+                    ..default()
+                })
+                .id(),
+        );
+
+        entities.push(
+            commands
+                .spawn(SpriteSheetBundle {
+                    texture_atlas: character.texture_atlas(&assets),
+                    sprite: TextureAtlasSprite {
+                        index: IDLE_FRAME_START,
+                        ..default()
+                    },
+                    transform: Transform {
+                        translation: pos.extend(PLAYER_Z + 0.01),
+                        scale: Vec3::new(PORTRAIT_SCALE, PORTRAIT_SCALE, 1.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .id(),
+        );
+
+        entities.push(
+            commands
+                .spawn(Text2dBundle {
+                    text: Text::from_section(character.name(), text_style(Color::WHITE, 16.0))
+                        .with_alignment(TextAlignment::CENTER),
+                    transform: Transform::from_translation(
+                        (pos + Vec2::new(0.0, 45.0)).extend(PLAYER_Z + 0.02),
+                    ),
+                    ..default()
+                })
+                .id(),
+        );
+    }
+
+    for player in [Player::One, Player::Two] {
+        let color = CURSOR_COLORS[player.index()];
+        let character = selection.cursors[player.index()];
+        let label = match player {
+            Player::One => "1P",
+            Player::Two => "2P",
+        };
+
+        entities.push(
+            commands
+                .spawn(Text2dBundle {
+                    text: Text::from_section(label, text_style(color, 24.0))
+                        .with_alignment(TextAlignment::CENTER),
+                    transform: Transform::from_translation(
+                        (roster_position(character) + cursor_offset(player))
+                            .extend(PLAYER_Z + 0.02),
+                    ),
+                    ..default()
+                })
+                .insert(RosterCursor(player))
+                .id(),
+        );
+
+        // Previews stand on each player's side facing the opponent.
+        let facing = player.facing();
+
+        entities.push(
+            commands
+                .spawn(SpriteSheetBundle {
+                    texture_atlas: character.texture_atlas(&assets),
+                    sprite: TextureAtlasSprite {
+                        index: IDLE_FRAME_START,
+                        color: character.palette(palettes[player.index()]),
+                        ..default()
+                    },
+                    transform: Transform {
+                        translation: Vec3::new(-PREVIEW_X * facing, PREVIEW_Y, PLAYER_Z),
+                        scale: Vec3::new(PREVIEW_SCALE * facing, PREVIEW_SCALE, 1.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(Preview(player))
+                .insert(character)
+                .insert(AnimationTimer(Timer::from_seconds(
+                    0.1,
+                    TimerMode::Repeating,
+                )))
+                .id(),
+        );
+
+        entities.push(
+            commands
+                .spawn(Text2dBundle {
+                    text: Text::from_section(character.name(), text_style(color, 24.0))
+                        .with_alignment(TextAlignment::CENTER),
+                    transform: Transform::from_xyz(
+                        -PREVIEW_X * facing,
+                        PREVIEW_Y - 150.0,
+                        PLAYER_Z + 0.02,
+                    ),
+                    ..default()
+                })
+                .insert(PreviewName(player))
+                .id(),
+        );
+    }
+
+    commands.insert_resource(selection);
+    commands.insert_resource(EntityData { entities });
+}
+
+/// Handle keyboard input.
+fn input_system(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut selection: ResMut<Selection>,
+    mut settings: ResMut<MatchSettings>,
+    arcade: Option<Res<Arcade>>,
+    stages: Res<Stages>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        state
+            .set(GameState::MainMenu)
+            .expect("Couldn't switch state to MainMenu");
+        keyboard_input.clear_just_pressed(KeyCode::Escape);
+        return;
+    }
+
+    // Only player one picks in arcade mode. The CPU's fighters are picked by the ladder.
+    let players = if arcade.is_some() {
+        vec![Player::One]
+    } else {
+        vec![Player::One, Player::Two]
+    };
+
+    for player in players {
+        let i = player.index();
+        let [previous, next, confirm, palette] = select_keys(player);
+
+        if selection.confirmed[i] {
+            if keyboard_input.just_pressed(palette) {
+                selection.confirmed[i] = false;
+            }
+        } else if keyboard_input.just_pressed(palette) {
+            selection.palettes[i] = selection.cursors[i].cycle_palette(selection.palettes[i], true);
+        } else if keyboard_input.just_pressed(previous) {
+            selection.cursors[i] = selection.cursors[i].cycle(false);
+        } else if keyboard_input.just_pressed(next) {
+            selection.cursors[i] = selection.cursors[i].cycle(true);
+        } else if keyboard_input.just_pressed(confirm) {
+            selection.confirmed[i] = true;
+        }
+    }
+
+    if let Some(arcade) = arcade {
+        if selection.confirmed[Player::One.index()] {
+            settings.characters = selection.cursors;
+            settings.palettes = selection.palettes;
+            arcade.apply(&mut settings, &stages);
+            state
+                .set(GameState::InGame)
+                .expect("Couldn't switch state to InGame");
+        }
+        return;
+    }
+
+    // Both players may pick the same character.
+    if selection.confirmed.iter().all(|c| *c) {
+        settings.characters = selection.cursors;
+        settings.palettes = selection.palettes;
+        state
+            .set(GameState::StageSelect)
+            .expect("Couldn't switch state to StageSelect");
+    }
+}
+
+/// Move cursors and update previews to the highlighted characters.
+fn cursor_system(
+    selection: Res<Selection>,
+    assets: Res<GameAssets>,
+    mut cursor_query: Query<(&RosterCursor, &mut Transform)>,
+    mut preview_query: Query<(
+        &Preview,
+        &mut Character,
+        &mut Handle<TextureAtlas>,
+        &mut TextureAtlasSprite,
+    )>,
+    mut name_query: Query<(&PreviewName, &mut Text)>,
+) {
+    if !selection.is_changed() {
+        return;
+    }
+
+    for (cursor, mut transform) in &mut cursor_query {
+        let character = selection.cursors[cursor.0.index()];
+        let pos = roster_position(character) + cursor_offset(cursor.0);
+        transform.translation = pos.extend(transform.translation.z);
+    }
+
+    let palettes = selection.match_palettes();
+    for (preview, mut character, mut atlas, mut sprite) in &mut preview_query {
+        let selected = selection.cursors[preview.0.index()];
+        if *character != selected {
+            *character = selected;
+            *atlas = selected.texture_atlas(&assets);
+            sprite.index = IDLE_FRAME_START;
+        }
+        sprite.color = selected.palette(palettes[preview.0.index()]);
+    }
+
+    for (name, mut text) in &mut name_query {
+        let i = name.0.index();
+        let character = selection.cursors[i];
+        text.sections[0].value = if selection.confirmed[i] {
+            format!("{} READY", character.name())
+        } else {
+            character.name().to_string()
+        };
+    }
+}
+
+/// Animate the previews.
+fn animation_system(
+    time: Res<Time>,
+    mut query: Query<(&Character, &mut AnimationTimer, &mut TextureAtlasSprite), With<Preview>>,
+) {
+    for (character, mut timer, mut sprite) in &mut query {
+        timer.tick(time.delta());
+        if timer.just_finished() {
+            sprite.index = next_idle_frame(character, sprite.index);
+        }
+    }
+}
+
+/// Cleanup resources.
+fn cleanup(mut commands: Commands, entity_data: Res<EntityData>, audio: Res<Audio>) {
+    for entity in entity_data.entities.iter() {
+        commands.entity(*entity).despawn_recursive();
+    }
+    commands.remove_resource::<Selection>();
+    audio.stop();
+}
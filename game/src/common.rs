@@ -1,18 +1,36 @@
 //! Common
 
 use bevy::prelude::*;
+use std::time::Duration;
 
-/// Window width.
-pub(crate) const WINDOW_WIDTH: f32 = 1024.0;
+/// Width of the virtual resolution everything is laid out in. It's scaled to fit the window.
+pub(crate) const VIEW_WIDTH: f32 = 1024.0;
 
-/// Window height.
-pub(crate) const WINDOW_HEIGHT: f32 = 576.0;
+/// Height of the virtual resolution.
+pub(crate) const VIEW_HEIGHT: f32 = 576.0;
+
+/// Vertical position of the health bars and timer.
+pub(crate) const HUD_Y: f32 = VIEW_HEIGHT / 2.0 - 63.0;
 
 /* Define z-coordinates for images/sprites so we can control draw order */
 pub(crate) const BG_Z: f32 = 0.0;
 pub(crate) const PLAYER_Z: f32 = 0.2;
+pub(crate) const EFFECT_Z: f32 = 0.3;
 pub(crate) const HEALTH_BAR_Z: f32 = 0.4;
 pub(crate) const COUNTDOWN_TIMER_Z: f32 = 0.6;
+pub(crate) const FLASH_Z: f32 = 0.8;
+
+/// Render layer for the HUD. It has its own camera so it stays put while the stage camera moves.
+pub(crate) const HUD_LAYER: u8 = 1;
+
+/// Render layer nothing is drawn on. The UI camera uses it so it only draws the UI.
+pub(crate) const UI_LAYER: u8 = 2;
+
+/// Number of fight simulation ticks per second.
+pub(crate) const TICK_RATE: u32 = 60;
+
+/// Duration of a single fight simulation tick.
+pub(crate) const TICK_DURATION: Duration = Duration::from_nanos(1_000_000_000 / TICK_RATE as u64);
 
 /// Timer for animating sprites.
 #[derive(Component, Deref, DerefMut)]
@@ -1,10 +1,11 @@
 //! Countdown Timer
 
-use crate::{common::*, GameAssets, GameState};
-use bevy::prelude::*;
-
-/// Starting value for countdown timer.
-const COUNTDOWN_TIMER_START: u16 = 30;
+use crate::{
+    common::*, max_health, Character, FightStage, GameAssets, GameState, Health, HealthUpdateEvent,
+    MatchSettings, Player, RollbackStage, StateReader, StateWriter, TimeOver,
+};
+use bevy::{prelude::*, render::view::RenderLayers};
+use std::time::Duration;
 
 /// Handles the countdown timer.
 pub struct CountdownTimerPlugin;
@@ -13,7 +14,11 @@ impl Plugin for CountdownTimerPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<CountdownCompleteEvent>()
             .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(setup))
-            .add_system_set(SystemSet::on_update(GameState::InGame).with_system(countdown_system))
+            .stage(FightStage, |stage: &mut RollbackStage| {
+                stage.add_system_set(
+                    SystemSet::on_update(GameState::InGame).with_system(countdown_system),
+                )
+            })
             .add_system_set(SystemSet::on_exit(GameState::InGame).with_system(cleanup));
     }
 }
@@ -24,34 +29,114 @@ struct EntityData {
     entities: Vec<Entity>,
 }
 
+/// Text shown instead of the time during sudden death.
+const SUDDEN_DEATH_TEXT: &str = "SD";
+
+/// Text shown instead of the time when rounds have no time limit.
+const INFINITE_TIME_TEXT: &str = "--";
+
 /// Represents the countdown timer.
 #[derive(Component)]
-struct CountdownTimer {
+pub(crate) struct CountdownTimer {
     remaining: u16,
     done: bool,
+
+    /// Time ran out with the players tied and the next hit wins.
+    overtime: bool,
 }
 
-impl Default for CountdownTimer {
-    fn default() -> Self {
+impl CountdownTimer {
+    fn new(remaining: u16) -> Self {
         Self {
-            remaining: COUNTDOWN_TIMER_START,
+            remaining,
             done: false,
+            overtime: false,
         }
     }
 }
 
+/// Text shown on the countdown timer.
+fn countdown_text(remaining: u16, overtime: bool, settings: &MatchSettings) -> String {
+    if overtime {
+        SUDDEN_DEATH_TEXT.to_string()
+    } else if settings.infinite_time() {
+        INFINITE_TIME_TEXT.to_string()
+    } else {
+        format!("{}", remaining)
+    }
+}
+
 /// Used to communicate end of countdown.
 pub struct CountdownCompleteEvent;
 
+/// Snapshot of the countdown timer's fight state.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct CountdownSnapshot {
+    pub(crate) remaining: u16,
+    pub(crate) done: bool,
+    pub(crate) overtime: bool,
+    pub(crate) elapsed: Duration,
+}
+
+impl CountdownSnapshot {
+    /// Encode the snapshot.
+    pub(crate) fn encode(&self, writer: &mut StateWriter) {
+        writer.u16(self.remaining);
+        writer.bool(self.done);
+        writer.bool(self.overtime);
+        writer.duration(self.elapsed);
+    }
+
+    /// Decode a snapshot.
+    pub(crate) fn decode(reader: &mut StateReader) -> Option<Self> {
+        Some(Self {
+            remaining: reader.u16()?,
+            done: reader.bool()?,
+            overtime: reader.bool()?,
+            elapsed: reader.duration()?,
+        })
+    }
+}
+
+/// Save the countdown timer state.
+pub(crate) fn save_countdown(world: &mut World) -> CountdownSnapshot {
+    let mut query = world.query::<(&CountdownTimer, &AnimationTimer)>();
+    query
+        .iter(world)
+        .next()
+        .map(|(countdown_timer, animation_timer)| CountdownSnapshot {
+            remaining: countdown_timer.remaining,
+            done: countdown_timer.done,
+            overtime: countdown_timer.overtime,
+            elapsed: animation_timer.elapsed(),
+        })
+        .unwrap_or_default()
+}
+
+/// Restore the countdown timer state.
+pub(crate) fn restore_countdown(world: &mut World, snapshot: &CountdownSnapshot) {
+    let value = countdown_text(
+        snapshot.remaining,
+        snapshot.overtime,
+        world.resource::<MatchSettings>(),
+    );
+    let mut query = world.query::<(&mut CountdownTimer, &mut AnimationTimer, &mut Text)>();
+    for (mut countdown_timer, mut animation_timer, mut text) in query.iter_mut(world) {
+        countdown_timer.remaining = snapshot.remaining;
+        countdown_timer.done = snapshot.done;
+        countdown_timer.overtime = snapshot.overtime;
+        animation_timer.set_elapsed(snapshot.elapsed);
+        text.sections[0].value = value.clone();
+    }
+}
+
 /// Setup the countdown timer.
-fn setup(mut commands: Commands, assets: Res<GameAssets>) {
-    let timer_pos = Vec3::new(0.0, 225.0, COUNTDOWN_TIMER_Z);
+fn setup(mut commands: Commands, assets: Res<GameAssets>, settings: Res<MatchSettings>) {
+    let timer_pos = Vec3::new(0.0, HUD_Y, COUNTDOWN_TIMER_Z);
     let timer_size = Vec3::new(95.0, 40.0, 1.0);
 
-    let mut entities: Vec<Entity> = Vec::new();
-
-    // Background of countdown timer.
-    entities.push(
+    let entities = vec![
+        // Background of countdown timer.
         commands
             .spawn(SpriteBundle {
                 sprite: Sprite {
@@ -65,10 +150,8 @@ fn setup(mut commands: Commands, assets: Res<GameAssets>) {
                 },
                 ..default()
             })
+            .insert(RenderLayers::layer(HUD_LAYER))
             .id(),
-    );
-
-    entities.push(
         commands
             .spawn(SpriteBundle {
                 sprite: Sprite {
@@ -82,73 +165,66 @@ fn setup(mut commands: Commands, assets: Res<GameAssets>) {
                 },
                 ..default()
             })
+            .insert(RenderLayers::layer(HUD_LAYER))
             .id(),
-    );
-
-    // The timer.
-    entities.push(
+        // The timer.
         commands
-            .spawn(NodeBundle {
-                style: Style {
-                    size: Size::new(Val::Percent(100.0), Val::Percent(10.0)),
-                    position: UiRect {
-                        top: Val::Px(35.0),
-                        left: Val::Px(0.0),
-                        ..default()
+            .spawn(Text2dBundle {
+                text: Text::from_section(
+                    countdown_text(settings.round_time, false, &settings),
+                    TextStyle {
+                        font: assets.font.clone(),
+                        font_size: 24.0,
+                        color: Color::WHITE,
                     },
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
-                    ..Default::default()
-                },
-                background_color: BackgroundColor(Color::NONE),
+                )
+                .with_alignment(TextAlignment::CENTER),
+                transform: Transform::from_translation(timer_pos + Vec3::new(0.0, 0.0, 0.02)),
                 ..default()
             })
-            .with_children(|timer| {
-                timer
-                    .spawn(TextBundle {
-                        text: Text::from_section(
-                            format!("{}", COUNTDOWN_TIMER_START),
-                            TextStyle {
-                                font: assets.font.clone(),
-                                font_size: 24.0,
-                                color: Color::WHITE,
-                            },
-                        )
-                        .with_alignment(TextAlignment::CENTER),
-                        style: Style {
-                            align_self: AlignSelf::Center,
-                            ..default()
-                        },
-                        ..default()
-                    })
-                    .insert(AnimationTimer(Timer::from_seconds(
-                        1.0,
-                        TimerMode::Repeating,
-                    )))
-                    .insert(CountdownTimer::default());
-            })
+            .insert(RenderLayers::layer(HUD_LAYER))
+            .insert(AnimationTimer(Timer::from_seconds(
+                1.0,
+                TimerMode::Repeating,
+            )))
+            .insert(CountdownTimer::new(settings.round_time))
             .id(),
-    );
+    ];
 
     commands.insert_resource(EntityData { entities });
 }
 
-/// Update the timer.
-fn countdown_system(
-    time: Res<Time>,
+/// Update the timer. When time runs out with the players tied under sudden death rules, both
+/// players are left with a sliver of health so the next hit wins.
+pub(crate) fn countdown_system(
     mut countdown_timer_query: Query<&mut CountdownTimer>,
     mut text_query: Query<(&mut Text, &mut AnimationTimer), With<CountdownTimer>>,
+    mut player_query: Query<(&Player, &mut Health, &Character)>,
+    settings: Res<MatchSettings>,
     mut countdown_complete_events: EventWriter<CountdownCompleteEvent>,
+    mut health_update_events: EventWriter<HealthUpdateEvent>,
 ) {
     let (mut text, mut animation_timer) = text_query.single_mut();
     let mut countdown_timer = countdown_timer_query.single_mut();
 
-    animation_timer.tick(time.delta());
+    if settings.infinite_time() || countdown_timer.overtime {
+        return;
+    }
+
+    animation_timer.tick(TICK_DURATION);
     if animation_timer.just_finished() {
         if !countdown_timer.done {
             if countdown_timer.remaining > 0 {
                 countdown_timer.remaining -= 1;
-                text.sections[0].value = format!("{}", countdown_timer.remaining);
+                text.sections[0].value =
+                    countdown_text(countdown_timer.remaining, false, &settings);
+            } else if settings.time_over == TimeOver::SuddenDeath && tied(&player_query) {
+                countdown_timer.overtime = true;
+                text.sections[0].value = countdown_text(0, true, &settings);
+                for (player, mut health, character) in &mut player_query {
+                    health.0 = 1;
+                    health_update_events.send(HealthUpdateEvent::new(*player, 1, *character));
+                }
             } else {
                 countdown_complete_events.send(CountdownCompleteEvent);
                 countdown_timer.done = true;
@@ -157,6 +233,15 @@ fn countdown_system(
     }
 }
 
+/// Returns true if both players have the same share of their health left.
+fn tied(player_query: &Query<(&Player, &mut Health, &Character)>) -> bool {
+    let mut percents = [0.0; 2];
+    for (player, health, character) in player_query {
+        percents[player.index()] = health.0 as f32 / max_health(*character) as f32;
+    }
+    percents[0] == percents[1]
+}
+
 /// Cleanup resources.
 fn cleanup(mut commands: Commands, entity_data: Res<EntityData>) {
     for entity in entity_data.entities.iter() {
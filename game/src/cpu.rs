@@ -0,0 +1,150 @@
+//! CPU
+
+use crate::{local_input_system, GameState, LocalInputs, MatchSettings, Player, PlayerInput};
+use bevy::prelude::*;
+
+/// Player controlled by the CPU when playing against one.
+pub(crate) const CPU_PLAYER: Player = Player::Two;
+
+/// Highest CPU difficulty.
+pub(crate) const MAX_DIFFICULTY: u8 = 4;
+
+/// Seconds the CPU takes to react at each difficulty.
+const REACTION_SECS: [f32; MAX_DIFFICULTY as usize + 1] = [0.6, 0.45, 0.35, 0.25, 0.18];
+
+/// Chance out of 100 of the CPU attacking when close at each difficulty.
+const AGGRESSION: [u32; MAX_DIFFICULTY as usize + 1] = [30, 40, 50, 60, 70];
+
+/// Seconds the CPU holds buttons for.
+const PRESS_SECS: f32 = 0.1;
+
+/// Distance between the players at which the CPU throws.
+const THROW_RANGE: f32 = 110.0;
+
+/// Distance between the players at which the CPU attacks.
+const ATTACK_RANGE: f32 = 160.0;
+
+/// Distance between the players beyond which the CPU throws projectiles.
+const FAR_RANGE: f32 = 400.0;
+
+/// Handles the CPU opponent.
+pub(crate) struct CpuPlugin;
+
+impl Plugin for CpuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(GameState::InGame).with_system(setup))
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(cpu_input_system.after(local_input_system)),
+            );
+    }
+}
+
+/// What the CPU is doing.
+#[derive(Resource)]
+struct Brain {
+    /// Time until the CPU decides what to do next.
+    reaction: Timer,
+
+    /// Time left holding buttons.
+    press: Timer,
+
+    /// Directions held until the next decision.
+    movement: u8,
+
+    /// Buttons pressed on the last decision.
+    buttons: u8,
+
+    /// State of the random number generator.
+    seed: u32,
+}
+
+impl Brain {
+    /// Random number from 0 to 99.
+    fn roll(&mut self) -> u32 {
+        // Xorshift is plenty for picking moves.
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed % 100
+    }
+
+    /// Decide what to do next based on how far away the opponent is.
+    fn decide(&mut self, difficulty: usize, distance: f32, toward: u8, away: u8) {
+        let roll = self.roll();
+        let (movement, buttons) = if distance <= THROW_RANGE && roll < 15 {
+            (0, PlayerInput::THROW)
+        } else if distance <= ATTACK_RANGE && roll < AGGRESSION[difficulty] {
+            // Spend meter once in a while. Nothing happens if there isn't enough.
+            if roll.is_multiple_of(5) {
+                (0, PlayerInput::SUPER)
+            } else {
+                (0, PlayerInput::ATTACK)
+            }
+        } else if distance <= ATTACK_RANGE && roll < 85 {
+            (away, 0)
+        } else if distance > FAR_RANGE && roll < 25 {
+            (0, PlayerInput::SPECIAL)
+        } else if roll < 15 {
+            (toward, PlayerInput::JUMP)
+        } else if roll < 90 {
+            (toward, 0)
+        } else {
+            (0, 0)
+        };
+
+        self.movement = movement;
+        self.buttons = buttons;
+        self.press.reset();
+    }
+}
+
+/// Get the CPU ready for a new fight.
+fn setup(mut commands: Commands, settings: Res<MatchSettings>) {
+    let difficulty = settings.cpu.unwrap_or_default().min(MAX_DIFFICULTY) as usize;
+
+    commands.insert_resource(Brain {
+        reaction: Timer::from_seconds(REACTION_SECS[difficulty], TimerMode::Repeating),
+        press: Timer::from_seconds(PRESS_SECS, TimerMode::Once),
+        movement: 0,
+        buttons: 0,
+        seed: 0x9e37_79b9,
+    });
+}
+
+/// Replace the keyboard input of the CPU player with the CPU's decisions.
+fn cpu_input_system(
+    time: Res<Time>,
+    settings: Res<MatchSettings>,
+    mut brain: ResMut<Brain>,
+    player_query: Query<(&Player, &Transform)>,
+    mut local_inputs: ResMut<LocalInputs>,
+) {
+    let difficulty = match settings.cpu {
+        Some(difficulty) => difficulty.min(MAX_DIFFICULTY) as usize,
+        None => return,
+    };
+
+    let mut positions = [0.0; 2];
+    for (player, transform) in &player_query {
+        positions[player.index()] = transform.translation.x;
+    }
+    let offset = positions[CPU_PLAYER.opponent().index()] - positions[CPU_PLAYER.index()];
+    let (toward, away) = if offset > 0.0 {
+        (PlayerInput::RIGHT, PlayerInput::LEFT)
+    } else {
+        (PlayerInput::LEFT, PlayerInput::RIGHT)
+    };
+
+    brain.press.tick(time.delta());
+    brain.reaction.tick(time.delta());
+    if brain.reaction.just_finished() {
+        brain.decide(difficulty, offset.abs(), toward, away);
+    }
+
+    let mut input = PlayerInput(brain.movement);
+    if !brain.press.finished() {
+        input.press(brain.buttons);
+    }
+    local_inputs.0[CPU_PLAYER.index()] = input;
+}
@@ -0,0 +1,77 @@
+//! Display
+
+use crate::{common::*, UiCamera};
+use bevy::{prelude::*, render::camera::Viewport, ui::UiScale, window::WindowMode};
+
+/// Handles fitting the game to the window.
+pub(crate) struct DisplayPlugin;
+
+impl Plugin for DisplayPlugin {
+    fn build(&self, app: &mut App) {
+        // Color of the letterbox bars.
+        app.insert_resource(ClearColor(Color::BLACK))
+            .add_system(letterbox_system);
+
+        if cfg!(feature = "desktop") {
+            app.add_system(fullscreen_system);
+        }
+    }
+}
+
+/// Scale the virtual resolution to fit the window keeping its aspect ratio. Whatever is left of
+/// the window is filled with bars.
+fn letterbox_system(
+    windows: Res<Windows>,
+    mut ui_scale: ResMut<UiScale>,
+    mut camera_query: Query<&mut Camera, Without<UiCamera>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let window_size = UVec2::new(window.physical_width(), window.physical_height());
+
+    // Minimised windows have no size.
+    if window_size.x == 0 || window_size.y == 0 {
+        return;
+    }
+
+    let scale = (window_size.x as f32 / VIEW_WIDTH).min(window_size.y as f32 / VIEW_HEIGHT);
+    let size = Vec2::new(VIEW_WIDTH * scale, VIEW_HEIGHT * scale)
+        .as_uvec2()
+        .max(UVec2::ONE);
+    let position = (window_size - size) / 2;
+
+    for mut camera in &mut camera_query {
+        let viewport = camera
+            .viewport
+            .as_ref()
+            .map(|viewport| (viewport.physical_position, viewport.physical_size));
+        if viewport != Some((position, size)) {
+            camera.viewport = Some(Viewport {
+                physical_position: position,
+                physical_size: size,
+                ..default()
+            });
+        }
+    }
+
+    // UI grows and shrinks with the view.
+    let ui = scale as f64 / window.scale_factor();
+    if (ui_scale.scale - ui).abs() > f64::EPSILON {
+        ui_scale.scale = ui;
+    }
+}
+
+/// Toggle fullscreen.
+fn fullscreen_system(mut keyboard_input: ResMut<Input<KeyCode>>, mut windows: ResMut<Windows>) {
+    if keyboard_input.just_pressed(KeyCode::F11) {
+        if let Some(window) = windows.get_primary_mut() {
+            window.set_mode(match window.mode() {
+                WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+                _ => WindowMode::Windowed,
+            });
+        }
+        keyboard_input.clear_just_pressed(KeyCode::F11);
+    }
+}
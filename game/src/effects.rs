@@ -0,0 +1,229 @@
+//! Effects
+
+use crate::{common::*, GameAssets, GameState};
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+/// Number of particles that can be shown at once. The oldest particle is reused when they run out.
+const POOL_SIZE: usize = 128;
+
+/// Number of animation frames for each effect in the effects sprite sheet.
+const EFFECT_FRAMES: usize = 6;
+
+/// First sprite sheet frame of the spark animation.
+const SPARK_FRAME: usize = 0;
+
+/// First sprite sheet frame of the dust animation.
+const DUST_FRAME: usize = EFFECT_FRAMES;
+
+/// Handles visual effects like hit sparks and dust.
+pub(crate) struct EffectsPlugin;
+
+impl Plugin for EffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<EffectEvent>()
+            // The pool uses the effects sprite sheet so it's set up once assets are loaded.
+            .add_system_set(SystemSet::on_exit(GameState::AssetLoading).with_system(setup))
+            .add_system(spawn_system)
+            .add_system(particle_system.after(spawn_system));
+    }
+}
+
+/// Effects that can be shown.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Effect {
+    /// An attack connecting.
+    HitSpark,
+
+    /// Landing after a jump.
+    LandingDust,
+
+    /// Running along the ground. Dust is kicked up behind the player.
+    RunningDust { direction: f32 },
+}
+
+/// Show an effect at a position. Sent by the fight simulation, except while resimulating ticks so
+/// effects aren't shown twice.
+pub(crate) struct EffectEvent {
+    pub(crate) effect: Effect,
+    pub(crate) position: Vec2,
+}
+
+impl EffectEvent {
+    pub(crate) fn new(effect: Effect, position: Vec2) -> Self {
+        Self { effect, position }
+    }
+}
+
+/// Particles waiting to be reused.
+#[derive(Resource)]
+struct ParticlePool {
+    entities: Vec<Entity>,
+
+    /// Next particle to use.
+    next: usize,
+}
+
+/// A single particle of an effect. Plays through its animation over its lifetime.
+#[derive(Component, Default)]
+struct Particle {
+    velocity: Vec2,
+    gravity: f32,
+    lifetime: Timer,
+    size: f32,
+    color: Color,
+
+    /// First sprite sheet frame of the particle's animation.
+    frame: usize,
+}
+
+/// Spawn the particles once. They are hidden until used.
+fn setup(mut commands: Commands, assets: Res<GameAssets>) {
+    let entities = (0..POOL_SIZE)
+        .map(|_| {
+            commands
+                .spawn(SpriteSheetBundle {
+                    texture_atlas: assets.effects_texture_atlas.clone(),
+                    visibility: Visibility { is_visible: false },
+                    transform: Transform::from_xyz(0.0, 0.0, EFFECT_Z),
+                    ..default()
+                })
+                .insert(Particle::default())
+                .id()
+        })
+        .collect();
+
+    commands.insert_resource(ParticlePool { entities, next: 0 });
+}
+
+/// Particles making up an effect.
+fn particles(effect: Effect) -> Vec<Particle> {
+    let particle =
+        |velocity: Vec2, gravity: f32, seconds: f32, size: f32, color: Color, frame: usize| {
+            Particle {
+                velocity,
+                gravity,
+                lifetime: Timer::from_seconds(seconds, TimerMode::Once),
+                size,
+                color,
+                frame,
+            }
+        };
+
+    match effect {
+        Effect::HitSpark => (0..8)
+            .map(|i| {
+                let angle = TAU * i as f32 / 8.0;
+                let speed = if i % 2 == 0 { 420.0 } else { 260.0 };
+                particle(
+                    Vec2::from_angle(angle) * speed,
+                    0.0,
+                    0.2,
+                    24.0,
+                    Color::rgb(1.0, 0.9, 0.4),
+                    SPARK_FRAME,
+                )
+            })
+            .chain([particle(
+                Vec2::ZERO,
+                0.0,
+                0.2,
+                96.0,
+                Color::WHITE,
+                SPARK_FRAME,
+            )])
+            .collect(),
+        Effect::LandingDust => [-1.0, 1.0]
+            .into_iter()
+            .flat_map(|side| {
+                [(150.0, 30.0), (90.0, 50.0), (40.0, 20.0)].map(|(x, y)| Vec2::new(x * side, y))
+            })
+            .map(|velocity| {
+                particle(
+                    velocity,
+                    -120.0,
+                    0.4,
+                    36.0,
+                    Color::rgb(0.6, 0.55, 0.5),
+                    DUST_FRAME,
+                )
+            })
+            .collect(),
+        Effect::RunningDust { direction } => [(60.0, 40.0), (30.0, 70.0)]
+            .into_iter()
+            .map(|(x, y)| {
+                particle(
+                    Vec2::new(-x * direction, y),
+                    -150.0,
+                    0.3,
+                    24.0,
+                    Color::rgb(0.6, 0.55, 0.5),
+                    DUST_FRAME,
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Show requested effects using particles from the pool.
+fn spawn_system(
+    mut effect_events: EventReader<EffectEvent>,
+    pool: Option<ResMut<ParticlePool>>,
+    mut particle_query: Query<(&mut Particle, &mut Transform, &mut Visibility)>,
+) {
+    let mut pool = match pool {
+        Some(pool) => pool,
+        None => return,
+    };
+
+    for event in effect_events.iter() {
+        for particle in particles(event.effect) {
+            let entity = pool.entities[pool.next];
+            pool.next = (pool.next + 1) % pool.entities.len();
+
+            if let Ok((mut current, mut transform, mut visibility)) = particle_query.get_mut(entity)
+            {
+                *current = particle;
+                transform.translation.x = event.position.x;
+                transform.translation.y = event.position.y;
+                visibility.is_visible = true;
+            }
+        }
+    }
+}
+
+/// Move and animate particles, fading them out over their lifetime.
+fn particle_system(
+    time: Res<Time>,
+    mut particle_query: Query<(
+        &mut Particle,
+        &mut Transform,
+        &mut TextureAtlasSprite,
+        &mut Visibility,
+    )>,
+) {
+    let delta = time.delta_seconds();
+
+    for (mut particle, mut transform, mut sprite, mut visibility) in &mut particle_query {
+        if !visibility.is_visible {
+            continue;
+        }
+
+        particle.lifetime.tick(time.delta());
+        if particle.lifetime.finished() {
+            visibility.is_visible = false;
+            continue;
+        }
+
+        let gravity = particle.gravity;
+        particle.velocity.y += gravity * delta;
+        transform.translation += (particle.velocity * delta).extend(0.0);
+
+        let percent = particle.lifetime.percent();
+        let frame = ((percent * EFFECT_FRAMES as f32) as usize).min(EFFECT_FRAMES - 1);
+        sprite.index = particle.frame + frame;
+        sprite.custom_size = Some(Vec2::splat(particle.size));
+        sprite.color = particle.color;
+        sprite.color.set_a(1.0 - percent * percent);
+    }
+}
@@ -0,0 +1,315 @@
+//! Fight State
+
+use crate::{
+    rebuild_scene, restore_countdown, restore_players, restore_projectiles, save_countdown,
+    save_players, save_projectiles, CountdownSnapshot, FightInputs, Freeze, MatchSettings,
+    PlayerSnapshot, ProjectileSnapshot, Stages, SuperMeters,
+};
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+use std::time::Duration;
+
+/// Version of the encoded fight state. Bump whenever the encoding changes so old snapshots are
+/// rejected instead of restored incorrectly.
+const FIGHT_STATE_VERSION: u8 = 11;
+
+/// Rounds won by each player since leaving the main menu.
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct RoundScore(pub(crate) [u8; 2]);
+
+/// Damage dealt by each player this round. Judges decide time overs with it.
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct DamageDealt(pub(crate) [u16; 2]);
+
+impl DamageDealt {
+    /// Count damage dealt by the attacker.
+    pub(crate) fn deal(&mut self, attacker: usize, damage: u8) {
+        self.0[attacker] = self.0[attacker].saturating_add(damage as u16);
+    }
+}
+
+/// Complete state of a fight at the start of a simulation tick.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct FightState {
+    pub(crate) tick: u32,
+    pub(crate) stage: u8,
+    pub(crate) players: [PlayerSnapshot; 2],
+    pub(crate) projectiles: [ProjectileSnapshot; 2],
+    pub(crate) countdown: CountdownSnapshot,
+    pub(crate) inputs: FightInputs,
+    pub(crate) round_score: RoundScore,
+    pub(crate) damage_dealt: DamageDealt,
+    pub(crate) super_meters: SuperMeters,
+    pub(crate) freeze: Freeze,
+}
+
+impl FightState {
+    /// Save the fight state from the world.
+    pub(crate) fn save(world: &mut World, tick: u32) -> Self {
+        Self {
+            tick,
+            stage: world.resource::<MatchSettings>().stage,
+            players: save_players(world),
+            projectiles: save_projectiles(world),
+            countdown: save_countdown(world),
+            inputs: world.resource::<FightInputs>().clone(),
+            round_score: *world.resource::<RoundScore>(),
+            damage_dealt: *world.resource::<DamageDealt>(),
+            super_meters: *world.resource::<SuperMeters>(),
+            freeze: *world.resource::<Freeze>(),
+        }
+    }
+
+    /// Restore the fight state into the world.
+    pub(crate) fn restore(&self, world: &mut World) {
+        // Spectators may have joined without knowing the stage. Players are placed relative to
+        // the stage's ground so it's restored first, along with its scene and music.
+        if world.resource::<MatchSettings>().stage != self.stage {
+            world.resource_mut::<MatchSettings>().stage = self.stage;
+            rebuild_scene(world);
+
+            let music = world.resource::<Stages>().get(self.stage).music.clone();
+            let audio = world.resource::<Audio>();
+            audio.stop();
+            audio.play(music).looped();
+        }
+        restore_players(world, &self.players);
+        restore_projectiles(world, &self.projectiles);
+        restore_countdown(world, &self.countdown);
+        world.insert_resource(self.inputs.clone());
+        world.insert_resource(self.round_score);
+        world.insert_resource(self.damage_dealt);
+        world.insert_resource(self.super_meters);
+        world.insert_resource(self.freeze);
+    }
+
+    /// Returns true if the fight has ended by knock out or time over.
+    pub(crate) fn is_over(&self) -> bool {
+        self.players.iter().any(|player| player.health == 0) || self.countdown.done
+    }
+
+    /// Encode the fight state into a compact, versioned byte format. Used for sending the state
+    /// over the network and for keeping save states.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        writer.u8(FIGHT_STATE_VERSION);
+        writer.u32(self.tick);
+        writer.u8(self.stage);
+        for player in self.players.iter() {
+            player.encode(&mut writer);
+        }
+        for projectile in self.projectiles.iter() {
+            projectile.encode(&mut writer);
+        }
+        self.countdown.encode(&mut writer);
+        self.inputs.encode(&mut writer);
+        writer.u8(self.round_score.0[0]);
+        writer.u8(self.round_score.0[1]);
+        writer.u16(self.damage_dealt.0[0]);
+        writer.u16(self.damage_dealt.0[1]);
+        self.super_meters.encode(&mut writer);
+        writer.u8(self.freeze.0);
+        writer.into_bytes()
+    }
+
+    /// Decode a fight state. Returns `None` if the bytes are malformed or were encoded by a
+    /// different version.
+    pub(crate) fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = StateReader::new(bytes);
+        if reader.u8()? != FIGHT_STATE_VERSION {
+            return None;
+        }
+
+        let state = Self {
+            tick: reader.u32()?,
+            stage: reader.u8()?,
+            players: [
+                PlayerSnapshot::decode(&mut reader)?,
+                PlayerSnapshot::decode(&mut reader)?,
+            ],
+            projectiles: [
+                ProjectileSnapshot::decode(&mut reader)?,
+                ProjectileSnapshot::decode(&mut reader)?,
+            ],
+            countdown: CountdownSnapshot::decode(&mut reader)?,
+            inputs: FightInputs::decode(&mut reader)?,
+            round_score: RoundScore([reader.u8()?, reader.u8()?]),
+            damage_dealt: DamageDealt([reader.u16()?, reader.u16()?]),
+            super_meters: SuperMeters::decode(&mut reader)?,
+            freeze: Freeze(reader.u8()?),
+        };
+
+        // Anything left over means the bytes aren't what we think they are.
+        reader.is_empty().then_some(state)
+    }
+}
+
+/// Writes fight state values as little endian bytes.
+#[derive(Default)]
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn vec3(&mut self, value: Vec3) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+    }
+
+    /// Durations are written in nanoseconds so they are restored exactly.
+    pub(crate) fn duration(&mut self, value: Duration) {
+        self.u64(value.as_nanos() as u64);
+    }
+}
+
+/// Reads fight state values written by `StateWriter`.
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Returns true if all bytes have been read.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Take the next `N` bytes.
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.bytes.get(..N)?.try_into().ok()?;
+        self.bytes = &self.bytes[N..];
+        Some(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|b| b[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Option<bool> {
+        self.u8().map(|b| b != 0)
+    }
+
+    pub(crate) fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    pub(crate) fn f32(&mut self) -> Option<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+
+    pub(crate) fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    pub(crate) fn duration(&mut self) -> Option<Duration> {
+        self.u64().map(Duration::from_nanos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlayerInput;
+
+    fn fight_state() -> FightState {
+        FightState {
+            tick: 1234,
+            players: [
+                PlayerSnapshot {
+                    translation: Vec3::new(-120.0, -40.0, 1.0),
+                    health: 70,
+                    ..default()
+                },
+                PlayerSnapshot {
+                    translation: Vec3::new(80.5, 12.25, 2.0),
+                    velocity: Vec3::new(-2.0, 6.5, 0.0),
+                    health: 25,
+                    ..default()
+                },
+            ],
+            inputs: FightInputs::new(
+                [
+                    PlayerInput(PlayerInput::RIGHT),
+                    PlayerInput(PlayerInput::ATTACK),
+                ],
+                [PlayerInput(PlayerInput::RIGHT), PlayerInput::default()],
+            ),
+            round_score: RoundScore([1, 0]),
+            damage_dealt: DamageDealt([30, 75]),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = fight_state();
+        assert_eq!(FightState::decode(&state.encode()), Some(state));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let bytes = fight_state().encode();
+        for len in 0..bytes.len() {
+            assert_eq!(
+                FightState::decode(&bytes[..len]),
+                None,
+                "decoded {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = fight_state().encode();
+        bytes.push(0);
+        assert_eq!(FightState::decode(&bytes), None);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = fight_state().encode();
+        bytes[0] = FIGHT_STATE_VERSION.wrapping_add(1);
+        assert_eq!(FightState::decode(&bytes), None);
+    }
+}
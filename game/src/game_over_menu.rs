@@ -1,8 +1,10 @@
 //! Gamr Over Menu
 
 use crate::{
-    menu_background, menu_border, menu_button, menu_button_interaction_system, menu_button_text,
-    menu_root, GameAssets, GameState, Health, Player,
+    max_health, menu_background, menu_border, menu_button, menu_button_interaction_system,
+    menu_button_text, menu_root, Arcade, Character, DamageDealt, GameAssets, GameState, Health,
+    HighScores, KnockOut, MatchSettings, NetStats, NetplayConfig, Player, RoundScore, Spectating,
+    Stages, TimeOver,
 };
 use bevy::prelude::*;
 use bevy_kira_audio::prelude::*;
@@ -19,9 +21,13 @@ pub struct GameOverPlugin;
 impl Plugin for GameOverPlugin {
     fn build(&self, app: &mut App) {
         app.add_system(menu_button_interaction_system)
-            .add_system(menu_button_press_system)
-            .add_system_set(SystemSet::on_enter(GameState::GameOver).with_system(setup))
-            .add_system_set(SystemSet::on_update(GameState::GameOver).with_system(input_system))
+            // The menu is shown once any knock out has played out.
+            .add_system_set(
+                SystemSet::on_update(GameState::GameOver)
+                    .with_system(setup)
+                    .with_system(input_system)
+                    .with_system(menu_button_press_system),
+            )
             .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(cleanup));
     }
 }
@@ -38,24 +44,79 @@ enum MenuButton {
     Continue,
 }
 
-/// Setup the players.
-fn setup(mut commands: Commands, assets: Res<GameAssets>, health_query: Query<(&Player, &Health)>) {
-    let mut entities: Vec<Entity> = Vec::new();
+/// Setup the game over menu.
+#[allow(clippy::too_many_arguments)]
+fn setup(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    health_query: Query<(&Player, &Health, &Character)>,
+    settings: Res<MatchSettings>,
+    damage_dealt: Res<DamageDealt>,
+    net_stats: Option<Res<NetStats>>,
+    spectating: Option<Res<Spectating>>,
+    mut round_score: ResMut<RoundScore>,
+    entity_data: Option<Res<EntityData>>,
+    knock_out: Option<Res<KnockOut>>,
+    arcade: Option<ResMut<Arcade>>,
+    mut high_scores: ResMut<HighScores>,
+) {
+    if entity_data.is_some() {
+        return;
+    }
 
     // Retrieve health of both players to determine weather there is a clear winner or a draw.
     let mut healths = [0_u8; 2];
-    for (player, health) in health_query.iter() {
+    let mut max_healths = [0_u8; 2];
+    for (player, health, character) in health_query.iter() {
         healths[player.index()] = health.0;
+        max_healths[player.index()] = max_health(*character);
     }
 
-    let msg = if healths[0] > healths[1] {
+    // Wait for the knock out to be played out.
+    let disconnected = net_stats.is_some_and(|stats| stats.disconnected);
+    let knocked_out = !disconnected && healths.contains(&0);
+    if knocked_out && !knock_out.is_some_and(|knock_out| knock_out.finished()) {
+        return;
+    }
+
+    // Knock outs and time overs are decided by the share of health each player has left unless
+    // the judges decide time overs by damage dealt.
+    let scores: [f32; 2] = if !knocked_out && settings.time_over == TimeOver::Judges {
+        [damage_dealt.0[0] as f32, damage_dealt.0[1] as f32]
+    } else {
+        [
+            healths[0] as f32 / max_healths[0] as f32,
+            healths[1] as f32 / max_healths[1] as f32,
+        ]
+    };
+
+    let mut entities: Vec<Entity> = Vec::new();
+    let mut winner = None;
+    let msg = if disconnected && spectating.is_some() {
+        "CONNECTION LOST"
+    } else if disconnected {
+        "OPPONENT DISCONNECTED"
+    } else if scores[0] > scores[1] {
+        round_score.0[0] = round_score.0[0].saturating_add(1);
+        winner = Some(Player::One);
         "PLAYER 1 WINS"
-    } else if healths[1] > healths[0] {
+    } else if scores[1] > scores[0] {
+        round_score.0[1] = round_score.0[1].saturating_add(1);
+        winner = Some(Player::Two);
         "PLAYER 2 WINS"
+    } else if knocked_out {
+        // Players that knock each other out on the same tick draw the round.
+        "DOUBLE KO"
     } else {
         "DRAW"
     };
 
+    // Single player modes show how the run is going.
+    let results = arcade.map_or(Vec::new(), |mut arcade| {
+        arcade.record(winner, healths[Player::One.index()]);
+        arcade.results(settings.characters[Player::One.index()], &mut high_scores)
+    });
+
     entities.push(
         commands
             .spawn(menu_root())
@@ -76,6 +137,10 @@ fn setup(mut commands: Commands, assets: Res<GameAssets>, health_query: Query<(&
                             })
                             .insert(MenuButton::Continue);
 
+                        for line in results.iter().rev() {
+                            parent.spawn(menu_button_text(&assets, line));
+                        }
+
                         parent.spawn(message()).with_children(|parent| {
                             parent.spawn(message_text(&assets, msg));
                         });
@@ -126,32 +191,85 @@ fn message_text(assets: &Res<GameAssets>, label: &str) -> TextBundle {
 fn menu_button_press_system(
     buttons: Query<(&Interaction, &MenuButton), (Changed<Interaction>, With<Button>)>,
     mut state: ResMut<State<GameState>>,
+    netplay_config: Option<Res<NetplayConfig>>,
+    mut arcade: Option<ResMut<Arcade>>,
+    mut settings: ResMut<MatchSettings>,
+    stages: Res<Stages>,
 ) {
     for (interaction, button) in buttons.iter() {
         if *interaction == Interaction::Clicked {
             match button {
-                MenuButton::Continue => state
-                    .set(GameState::MainMenu)
-                    .expect("Couldn't switch state to MainMenu"),
+                MenuButton::Continue => continue_to(
+                    &mut state,
+                    netplay_config.as_deref(),
+                    arcade.as_deref_mut(),
+                    &mut settings,
+                    &stages,
+                ),
             };
         }
     }
 }
 
 /// Handle keyboard input.
-fn input_system(mut keyboard_input: ResMut<Input<KeyCode>>, mut state: ResMut<State<GameState>>) {
+fn input_system(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    netplay_config: Option<Res<NetplayConfig>>,
+    mut arcade: Option<ResMut<Arcade>>,
+    mut settings: ResMut<MatchSettings>,
+    stages: Res<Stages>,
+    entity_data: Option<Res<EntityData>>,
+) {
+    // Wait for the menu.
+    if entity_data.is_none() {
+        return;
+    }
+
     if keyboard_input.just_pressed(KeyCode::Return) {
+        continue_to(
+            &mut state,
+            netplay_config.as_deref(),
+            arcade.as_deref_mut(),
+            &mut settings,
+            &stages,
+        );
+        keyboard_input.clear_just_pressed(KeyCode::Return);
+    }
+}
+
+/// Leave the game over screen. Online matches set up in the lobby return there and arcade mode
+/// moves on through its ladder.
+fn continue_to(
+    state: &mut State<GameState>,
+    netplay_config: Option<&NetplayConfig>,
+    arcade: Option<&mut Arcade>,
+    settings: &mut MatchSettings,
+    stages: &Stages,
+) {
+    if let Some(arcade) = arcade {
+        let next = arcade.next(settings, stages);
+        state
+            .set(next)
+            .expect("Couldn't switch state after arcade fight");
+    } else if netplay_config.is_some_and(|config| config.from_lobby) {
+        state
+            .set(GameState::Lobby)
+            .expect("Couldn't switch state to Lobby");
+    } else {
         state
             .set(GameState::MainMenu)
             .expect("Couldn't switch state to MainMenu");
-        keyboard_input.clear_just_pressed(KeyCode::Return);
     }
 }
 
 /// Cleanup resources.
-fn cleanup(mut commands: Commands, entity_data: Res<EntityData>, audio: Res<Audio>) {
-    for entity in entity_data.entities.iter() {
-        commands.entity(*entity).despawn_recursive();
+fn cleanup(mut commands: Commands, entity_data: Option<Res<EntityData>>, audio: Res<Audio>) {
+    if let Some(entity_data) = entity_data {
+        for entity in entity_data.entities.iter() {
+            commands.entity(*entity).despawn_recursive();
+        }
     }
+    commands.remove_resource::<EntityData>();
     audio.stop();
 }
@@ -1,8 +1,10 @@
 //! Health
 
-use crate::{GameState, HealthUpdateEvent, Player, HEALTH_BAR_Z};
-use bevy::prelude::*;
-use std::collections::HashMap;
+use crate::{
+    GameState, HealthUpdateEvent, Player, SuperMeters, ENHANCED_COST, HEALTH_BAR_Z, HUD_LAYER,
+    HUD_Y, MAX_METER,
+};
+use bevy::{prelude::*, render::view::RenderLayers};
 
 /// Health bar maximum width for 100% health.
 const HEALTH_BAR_MAX_WIDTH: f32 = 400.0;
@@ -12,18 +14,65 @@ const HEALTH_BAR_SIZE: Vec3 = Vec3::new(HEALTH_BAR_MAX_WIDTH, 30.0, 1.0);
 
 /// Health bar positions.
 const HEALTH_BAR_POS: [Vec3; 2] = [
-    Vec3::new(-250.0, 225.0, HEALTH_BAR_Z),
-    Vec3::new(250.0, 225.0, HEALTH_BAR_Z),
+    Vec3::new(-250.0, HUD_Y, HEALTH_BAR_Z),
+    Vec3::new(250.0, HUD_Y, HEALTH_BAR_Z),
 ];
 
+/// Meter bar maximum width for a full meter.
+const METER_BAR_MAX_WIDTH: f32 = 250.0;
+
+/// Meter bar size.
+const METER_BAR_SIZE: Vec3 = Vec3::new(METER_BAR_MAX_WIDTH, 10.0, 1.0);
+
+/// Meter bar positions. Lined up with the inner edge of the health bars.
+const METER_BAR_POS: [Vec3; 2] = [
+    Vec3::new(-175.0, HUD_Y - 27.0, HEALTH_BAR_Z),
+    Vec3::new(175.0, HUD_Y - 27.0, HEALTH_BAR_Z),
+];
+
+/// Health bar colors.
+const HEALTH_COLOR: Color = Color::rgb(0.502, 0.549, 0.984);
+const LOW_HEALTH_COLOR: Color = Color::rgb(0.984, 0.757, 0.251);
+const FLASH_COLOR: Color = Color::WHITE;
+const DAMAGE_TRAIL_COLOR: Color = Color::RED;
+const EMPTY_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+
+/// Meter bar colors.
+const METER_COLOR: Color = Color::rgb(0.3, 0.6, 0.9);
+const ENHANCED_METER_COLOR: Color = Color::rgb(0.2, 0.8, 1.0);
+const SUPER_METER_COLOR: Color = Color::rgb(1.0, 0.85, 0.2);
+
+/// Health below which the bar changes color.
+const LOW_HEALTH_PERCENT: f32 = 0.3;
+
+/// How long the bar flashes when hit.
+const FLASH_SECS: f32 = 0.15;
+
+/// How long the damage trail waits before draining.
+const DAMAGE_TRAIL_DELAY_SECS: f32 = 0.5;
+
+/// How much of the bar the damage trail drains per second.
+const DAMAGE_TRAIL_SPEED: f32 = 0.6;
+
 /// Handles the health.
 pub(crate) struct HealthPlugin;
 
 impl Plugin for HealthPlugin {
     fn build(&self, app: &mut App) {
         app.add_system_set(SystemSet::on_enter(GameState::InGame).with_system(setup))
-            .add_system_set(SystemSet::on_update(GameState::InGame).with_system(update_system))
-            .add_system_set(SystemSet::on_exit(GameState::InGame).with_system(cleanup));
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(update_system)
+                    .with_system(animation_system.after(update_system))
+                    .with_system(meter_system),
+            )
+            // Health bars stay up while the round ends.
+            .add_system_set(
+                SystemSet::on_update(GameState::GameOver)
+                    .with_system(update_system)
+                    .with_system(animation_system.after(update_system)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(cleanup));
     }
 }
 
@@ -35,19 +84,50 @@ struct EntityData {
 
 /// Represents the health bar of a player storing percent health.
 #[derive(Component)]
-struct HealthBar(u8);
+struct HealthBar {
+    percent: f32,
+
+    /// Time left flashing after a hit.
+    flash: f32,
+}
+
 impl Default for HealthBar {
     fn default() -> Self {
-        Self(100)
+        Self {
+            percent: 1.0,
+            flash: 0.0,
+        }
     }
 }
 
+/// Shows recent damage behind the health bar. Drains down to the health bar after a delay.
+#[derive(Component)]
+struct DamageTrail {
+    percent: f32,
+
+    /// Time left before draining.
+    delay: f32,
+}
+
+impl Default for DamageTrail {
+    fn default() -> Self {
+        Self {
+            percent: 1.0,
+            delay: 0.0,
+        }
+    }
+}
+
+/// Represents the super meter bar of a player.
+#[derive(Component)]
+struct MeterBar;
+
 /// Setup.
 fn setup(mut commands: Commands) {
     let mut entities: Vec<Entity> = Vec::new();
 
     for player in [Player::One, Player::Two] {
-        // Background of health bar.
+        // Border of health bar.
         entities.push(
             commands
                 .spawn(SpriteBundle {
@@ -62,15 +142,16 @@ fn setup(mut commands: Commands) {
                     },
                     ..default()
                 })
+                .insert(RenderLayers::layer(HUD_LAYER))
                 .id(),
         );
 
-        // Damage reveal for health bar.
+        // Background of health bar.
         entities.push(
             commands
                 .spawn(SpriteBundle {
                     sprite: Sprite {
-                        color: Color::RED,
+                        color: EMPTY_COLOR,
                         ..default()
                     },
                     transform: Transform {
@@ -80,6 +161,28 @@ fn setup(mut commands: Commands) {
                     },
                     ..default()
                 })
+                .insert(RenderLayers::layer(HUD_LAYER))
+                .id(),
+        );
+
+        // Damage trail for health bar.
+        entities.push(
+            commands
+                .spawn(DamageTrail::default())
+                .insert(player)
+                .insert(SpriteBundle {
+                    sprite: Sprite {
+                        color: DAMAGE_TRAIL_COLOR,
+                        ..default()
+                    },
+                    transform: Transform {
+                        translation: HEALTH_BAR_POS[player.index()] + Vec3::new(0.0, 0.0, 0.015),
+                        scale: HEALTH_BAR_SIZE,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(RenderLayers::layer(HUD_LAYER))
                 .id(),
         );
 
@@ -90,7 +193,7 @@ fn setup(mut commands: Commands) {
                 .insert(player)
                 .insert(SpriteBundle {
                     sprite: Sprite {
-                        color: Color::rgb(0.502, 0.549, 0.984),
+                        color: HEALTH_COLOR,
                         ..default()
                     },
                     transform: Transform {
@@ -100,6 +203,66 @@ fn setup(mut commands: Commands) {
                     },
                     ..default()
                 })
+                .insert(RenderLayers::layer(HUD_LAYER))
+                .id(),
+        );
+
+        // Border of meter bar.
+        entities.push(
+            commands
+                .spawn(SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                    transform: Transform {
+                        translation: METER_BAR_POS[player.index()],
+                        scale: METER_BAR_SIZE + Vec3::new(4.0, 4.0, 0.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(RenderLayers::layer(HUD_LAYER))
+                .id(),
+        );
+
+        // Background of meter bar.
+        entities.push(
+            commands
+                .spawn(SpriteBundle {
+                    sprite: Sprite {
+                        color: EMPTY_COLOR,
+                        ..default()
+                    },
+                    transform: Transform {
+                        translation: METER_BAR_POS[player.index()] + Vec3::new(0.0, 0.0, 0.01),
+                        scale: METER_BAR_SIZE,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(RenderLayers::layer(HUD_LAYER))
+                .id(),
+        );
+
+        // Meter bar.
+        entities.push(
+            commands
+                .spawn(MeterBar)
+                .insert(player)
+                .insert(SpriteBundle {
+                    sprite: Sprite {
+                        color: METER_COLOR,
+                        ..default()
+                    },
+                    transform: Transform {
+                        translation: METER_BAR_POS[player.index()] + Vec3::new(0.0, 0.0, 0.02),
+                        scale: METER_BAR_SIZE,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(RenderLayers::layer(HUD_LAYER))
                 .id(),
         );
     }
@@ -107,37 +270,121 @@ fn setup(mut commands: Commands) {
     commands.insert_resource(EntityData { entities });
 }
 
-/// Process player health updates.
+/// Process player health updates. Damage flashes the health bar and holds the damage trail
+/// before it drains. Health going back up after a rollback is shown right away.
 fn update_system(
     mut health_update_events: EventReader<HealthUpdateEvent>,
-    mut health_bar_query: Query<(&Player, &mut HealthBar, &mut Transform), With<Sprite>>,
+    mut health_bar_query: Query<(&Player, &mut HealthBar)>,
+    mut damage_trail_query: Query<(&Player, &mut DamageTrail)>,
 ) {
-    let mut current_health: HashMap<Player, u8> = HashMap::new();
+    let mut percents: [Option<f32>; 2] = [None; 2];
+    for event in health_update_events.iter() {
+        percents[event.player.index()] = Some(event.percent());
+    }
 
-    if !health_update_events.is_empty() {
-        for event in health_update_events.iter() {
-            if let Some(health) = current_health.get_mut(&event.player) {
-                *health = event.health;
-            } else {
-                current_health.insert(event.player, event.health);
+    for (player, mut health_bar) in &mut health_bar_query {
+        if let Some(percent) = percents[player.index()] {
+            if percent < health_bar.percent {
+                health_bar.flash = FLASH_SECS;
             }
+            health_bar.percent = percent;
         }
     }
 
-    for (player, mut health_bar, mut transform) in &mut health_bar_query {
-        if let Some(health) = current_health.get(player) {
-            let diff = (health_bar.0 - health) as f32;
-            let hp = diff / 100.0;
-            match player {
-                Player::One => transform.translation.x += hp * HEALTH_BAR_MAX_WIDTH / 2.0,
-                Player::Two => transform.translation.x -= hp * HEALTH_BAR_MAX_WIDTH / 2.0,
+    for (player, mut damage_trail) in &mut damage_trail_query {
+        if let Some(percent) = percents[player.index()] {
+            if percent < damage_trail.percent {
+                damage_trail.delay = DAMAGE_TRAIL_DELAY_SECS;
+            } else {
+                damage_trail.percent = percent;
             }
-            transform.scale.x -= hp * HEALTH_BAR_MAX_WIDTH;
-            health_bar.0 = *health;
         }
     }
 }
 
+/// Animate the health bars and drain the damage trails.
+fn animation_system(
+    time: Res<Time>,
+    mut health_bar_query: Query<
+        (&Player, &mut HealthBar, &mut Sprite, &mut Transform),
+        Without<DamageTrail>,
+    >,
+    mut damage_trail_query: Query<(&Player, &mut DamageTrail, &mut Transform), Without<HealthBar>>,
+) {
+    let delta = time.delta_seconds();
+    let mut health_percents = [1.0; 2];
+
+    for (player, mut health_bar, mut sprite, mut transform) in &mut health_bar_query {
+        health_bar.flash = (health_bar.flash - delta).max(0.0);
+        sprite.color = if health_bar.flash > 0.0 {
+            FLASH_COLOR
+        } else if health_bar.percent < LOW_HEALTH_PERCENT {
+            LOW_HEALTH_COLOR
+        } else {
+            HEALTH_COLOR
+        };
+        fill(
+            &mut transform,
+            *player,
+            HEALTH_BAR_POS[player.index()].x,
+            HEALTH_BAR_MAX_WIDTH,
+            health_bar.percent,
+        );
+        health_percents[player.index()] = health_bar.percent;
+    }
+
+    for (player, mut damage_trail, mut transform) in &mut damage_trail_query {
+        let health_percent = health_percents[player.index()];
+        if damage_trail.delay > 0.0 {
+            damage_trail.delay = (damage_trail.delay - delta).max(0.0);
+        } else if damage_trail.percent > health_percent {
+            damage_trail.percent =
+                (damage_trail.percent - DAMAGE_TRAIL_SPEED * delta).max(health_percent);
+        }
+        fill(
+            &mut transform,
+            *player,
+            HEALTH_BAR_POS[player.index()].x,
+            HEALTH_BAR_MAX_WIDTH,
+            damage_trail.percent,
+        );
+    }
+}
+
+/// Show each player's super meter. The bar changes color once there's enough for an enhanced
+/// attack and again when it's full.
+fn meter_system(
+    super_meters: Res<SuperMeters>,
+    mut meter_bar_query: Query<(&Player, &mut Sprite, &mut Transform), With<MeterBar>>,
+) {
+    for (player, mut sprite, mut transform) in &mut meter_bar_query {
+        let meter = super_meters.meters[player.index()];
+        sprite.color = if meter >= MAX_METER {
+            SUPER_METER_COLOR
+        } else if meter >= ENHANCED_COST {
+            ENHANCED_METER_COLOR
+        } else {
+            METER_COLOR
+        };
+        fill(
+            &mut transform,
+            *player,
+            METER_BAR_POS[player.index()].x,
+            METER_BAR_MAX_WIDTH,
+            meter as f32 / MAX_METER as f32,
+        );
+    }
+}
+
+/// Size a part of a bar centered at `x` to a percentage of the full bar. Bars stay anchored on the
+/// side closest to the middle of the screen.
+fn fill(transform: &mut Transform, player: Player, x: f32, max_width: f32, percent: f32) {
+    let width = max_width * percent.clamp(0.0, 1.0);
+    let inner_edge = x + player.facing() * max_width / 2.0;
+    transform.translation.x = inner_edge - player.facing() * width / 2.0;
+    transform.scale.x = width;
+}
+
 /// Cleanup resources.
 fn cleanup(mut commands: Commands, entity_data: Res<EntityData>) {
     for entity in entity_data.entities.iter() {
@@ -0,0 +1,125 @@
+//! High Scores
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Number of scores kept for each mode.
+const HIGH_SCORE_COUNT: usize = 5;
+
+/// File the high scores are kept in on desktop.
+#[cfg(not(target_arch = "wasm32"))]
+const HIGH_SCORES_FILE: &str = "high_scores.ron";
+
+/// Local storage key the high scores are kept under in browsers.
+#[cfg(target_arch = "wasm32")]
+const HIGH_SCORES_KEY: &str = "fighter.high_scores";
+
+/// A score set by a character.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct HighScore {
+    pub(crate) character: String,
+    pub(crate) score: u32,
+}
+
+/// Best scores in the single player modes. Only kept on this machine.
+#[derive(Resource, Default, Deserialize, Serialize)]
+pub(crate) struct HighScores {
+    /// Most fights won in a row in survival, highest first.
+    pub(crate) survival: Vec<HighScore>,
+
+    /// Fastest times in hundredths of a second to clear time attack, lowest first.
+    pub(crate) time_attack: Vec<HighScore>,
+}
+
+impl HighScores {
+    /// Load the saved high scores. Missing or unreadable scores start over.
+    pub(crate) fn load() -> Self {
+        read()
+            .and_then(|text| ron::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    /// Save the high scores.
+    fn save(&self) {
+        match ron::to_string(self) {
+            Ok(text) => write(&text),
+            Err(err) => warn!("Couldn't save high scores: {}", err),
+        }
+    }
+
+    /// Add a survival score. Returns its rank if it made the table.
+    pub(crate) fn add_survival(&mut self, character: &str, wins: u32) -> Option<usize> {
+        let rank = insert(&mut self.survival, character, wins, |a, b| a > b);
+        self.save();
+        rank
+    }
+
+    /// Add a time attack score. Returns its rank if it made the table.
+    pub(crate) fn add_time_attack(&mut self, character: &str, time: u32) -> Option<usize> {
+        let rank = insert(&mut self.time_attack, character, time, |a, b| a < b);
+        self.save();
+        rank
+    }
+}
+
+/// Insert a score into a table kept in order. Ties go after existing scores.
+fn insert(
+    scores: &mut Vec<HighScore>,
+    character: &str,
+    score: u32,
+    better: fn(u32, u32) -> bool,
+) -> Option<usize> {
+    let rank = scores
+        .iter()
+        .position(|high_score| better(score, high_score.score))
+        .unwrap_or(scores.len());
+    if rank >= HIGH_SCORE_COUNT {
+        return None;
+    }
+
+    scores.insert(
+        rank,
+        HighScore {
+            character: character.to_string(),
+            score,
+        },
+    );
+    scores.truncate(HIGH_SCORE_COUNT);
+    Some(rank)
+}
+
+/// Format a time attack score as minutes, seconds and hundredths.
+pub(crate) fn time_text(time: u32) -> String {
+    format!("{}:{:02}.{:02}", time / 6000, time / 100 % 60, time % 100)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read() -> Option<String> {
+    std::fs::read_to_string(HIGH_SCORES_FILE).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write(text: &str) {
+    if let Err(err) = std::fs::write(HIGH_SCORES_FILE, text) {
+        warn!("Couldn't save high scores: {}", err);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read() -> Option<String> {
+    local_storage()?.get_item(HIGH_SCORES_KEY).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write(text: &str) {
+    if let Some(storage) = local_storage() {
+        if let Err(err) = storage.set_item(HIGH_SCORES_KEY, text) {
+            warn!("Couldn't save high scores: {:?}", err);
+        }
+    }
+}
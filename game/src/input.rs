@@ -0,0 +1,131 @@
+//! Input
+
+use crate::{Player, StateReader, StateWriter};
+use bevy::prelude::*;
+
+/// Represents the buttons held by a player during a single simulation tick. This is what gets
+/// exchanged between peers during online play so it is kept as a single byte.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct PlayerInput(pub(crate) u8);
+
+impl PlayerInput {
+    pub(crate) const LEFT: u8 = 1 << 0;
+    pub(crate) const RIGHT: u8 = 1 << 1;
+    pub(crate) const JUMP: u8 = 1 << 2;
+    pub(crate) const ATTACK: u8 = 1 << 3;
+    pub(crate) const SUPER: u8 = 1 << 4;
+    pub(crate) const THROW: u8 = 1 << 5;
+    pub(crate) const SPECIAL: u8 = 1 << 6;
+
+    /// Returns true if the given button is held.
+    pub(crate) fn pressed(&self, button: u8) -> bool {
+        self.0 & button != 0
+    }
+
+    /// Marks the given button as held.
+    pub(crate) fn press(&mut self, button: u8) {
+        self.0 |= button;
+    }
+}
+
+/// Represents player action keys.
+#[derive(Component)]
+pub(crate) struct Keys {
+    pub(crate) left: KeyCode,
+    pub(crate) right: KeyCode,
+    pub(crate) jump: KeyCode,
+    pub(crate) attack: KeyCode,
+    pub(crate) super_attack: KeyCode,
+    pub(crate) throw: KeyCode,
+    pub(crate) special: KeyCode,
+}
+
+/// Inputs sampled from the local keyboard for each player on the current frame.
+#[derive(Resource, Default)]
+pub(crate) struct LocalInputs(pub(crate) [PlayerInput; 2]);
+
+/// Inputs used by the fight simulation for the tick being simulated.
+#[derive(Resource, Clone, Debug, Default, PartialEq)]
+pub(crate) struct FightInputs {
+    current: [PlayerInput; 2],
+    previous: [PlayerInput; 2],
+}
+
+impl FightInputs {
+    /// Creates the inputs for a tick given the inputs of the tick before it.
+    pub(crate) fn new(current: [PlayerInput; 2], previous: [PlayerInput; 2]) -> Self {
+        Self { current, previous }
+    }
+
+    /// Inputs for the tick that follows this one.
+    pub(crate) fn next(&self, inputs: [PlayerInput; 2]) -> Self {
+        Self::new(inputs, self.current)
+    }
+
+    /// Returns true if the button is held by the player.
+    pub(crate) fn pressed(&self, player: Player, button: u8) -> bool {
+        self.current[player.index()].pressed(button)
+    }
+
+    /// Returns true if the button was pressed by the player on this tick.
+    pub(crate) fn just_pressed(&self, player: Player, button: u8) -> bool {
+        !self.previous[player.index()].pressed(button)
+            && self.current[player.index()].pressed(button)
+    }
+
+    /// Returns true if the button was released by the player on this tick.
+    pub(crate) fn just_released(&self, player: Player, button: u8) -> bool {
+        self.previous[player.index()].pressed(button)
+            && !self.current[player.index()].pressed(button)
+    }
+
+    /// Encode the inputs.
+    pub(crate) fn encode(&self, writer: &mut StateWriter) {
+        for input in self.current.iter().chain(self.previous.iter()) {
+            writer.u8(input.0);
+        }
+    }
+
+    /// Decode inputs.
+    pub(crate) fn decode(reader: &mut StateReader) -> Option<Self> {
+        let current = [PlayerInput(reader.u8()?), PlayerInput(reader.u8()?)];
+        let previous = [PlayerInput(reader.u8()?), PlayerInput(reader.u8()?)];
+        Some(Self::new(current, previous))
+    }
+}
+
+/// Sample the keyboard for each player's keys.
+pub(crate) fn local_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    player_query: Query<(&Player, &Keys)>,
+    mut local_inputs: ResMut<LocalInputs>,
+) {
+    let mut inputs = [PlayerInput::default(); 2];
+
+    for (player, keys) in &player_query {
+        let input = &mut inputs[player.index()];
+        if keyboard_input.pressed(keys.left) {
+            input.press(PlayerInput::LEFT);
+        }
+        if keyboard_input.pressed(keys.right) {
+            input.press(PlayerInput::RIGHT);
+        }
+        if keyboard_input.pressed(keys.jump) {
+            input.press(PlayerInput::JUMP);
+        }
+        if keyboard_input.pressed(keys.attack) {
+            input.press(PlayerInput::ATTACK);
+        }
+        if keyboard_input.pressed(keys.super_attack) {
+            input.press(PlayerInput::SUPER);
+        }
+        if keyboard_input.pressed(keys.throw) {
+            input.press(PlayerInput::THROW);
+        }
+        if keyboard_input.pressed(keys.special) {
+            input.press(PlayerInput::SPECIAL);
+        }
+    }
+
+    local_inputs.0 = inputs;
+}
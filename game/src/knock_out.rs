@@ -0,0 +1,170 @@
+//! Knock Out
+
+use crate::{common::*, GameState, Health, HealthUpdateEvent, Player, TimeScale};
+use bevy::{prelude::*, render::view::RenderLayers};
+
+/// Speed of the fight while the final blow lands.
+const SLOW_MOTION_SPEED: f32 = 0.25;
+
+/// How long the fight is slowed down for.
+const SLOW_MOTION_SECS: f32 = 1.2;
+
+/// How long the flash takes to fade.
+const FLASH_SECS: f32 = 0.3;
+
+/// When the winner strikes a victory pose.
+const VICTORY_SECS: f32 = 2.0;
+
+/// Length of the knock out before the game over menu is shown.
+const KNOCK_OUT_SECS: f32 = 3.5;
+
+/// Handles the knock out played when a round ends with a player losing all their health.
+pub(crate) struct KnockOutPlugin;
+
+impl Plugin for KnockOutPlugin {
+    fn build(&self, app: &mut App) {
+        // The final blow lands during the fight but the round only ends once it's over, so the
+        // knock out is played in both states.
+        app.add_system_set(
+            SystemSet::on_update(GameState::InGame)
+                .with_system(setup)
+                .with_system(knock_out_system.after(setup)),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::GameOver)
+                .with_system(setup)
+                .with_system(knock_out_system.after(setup)),
+        )
+        .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(cleanup));
+    }
+}
+
+/// Knock out entities.
+#[derive(Resource)]
+struct EntityData {
+    entities: Vec<Entity>,
+}
+
+/// Knock out being played.
+#[derive(Resource)]
+pub(crate) struct KnockOut {
+    timer: Timer,
+
+    /// Player left standing. Nobody wins when both players are knocked out.
+    winner: Option<Player>,
+}
+
+impl KnockOut {
+    /// Winner once it's time for their victory pose.
+    pub(crate) fn posing(&self) -> Option<Player> {
+        self.winner
+            .filter(|_| self.timer.elapsed_secs() >= VICTORY_SECS)
+    }
+
+    /// Returns true once the knock out has been played out.
+    pub(crate) fn finished(&self) -> bool {
+        self.timer.finished()
+    }
+}
+
+/// Flash shown when the final blow lands.
+#[derive(Component)]
+struct Flash;
+
+/// Start the knock out as soon as the final blow takes a player's health to 0. A rollback can take
+/// the blow back, in which case the knock out is called off.
+fn setup(
+    mut commands: Commands,
+    health_update_events: EventReader<HealthUpdateEvent>,
+    health_query: Query<(&Player, &Health)>,
+    knock_out: Option<Res<KnockOut>>,
+    entity_data: Option<Res<EntityData>>,
+    mut time_scale: ResMut<TimeScale>,
+) {
+    // Only health changes can start or call off a knock out.
+    if health_update_events.is_empty() {
+        return;
+    }
+    health_update_events.clear();
+
+    let mut healths = [0_u8; 2];
+    for (player, health) in health_query.iter() {
+        healths[player.index()] = health.0;
+    }
+
+    if healths.iter().all(|health| *health > 0) {
+        if knock_out.is_some() {
+            cleanup(commands, entity_data, time_scale);
+        }
+        return;
+    }
+    if knock_out.is_some() {
+        return;
+    }
+
+    let winner = [Player::One, Player::Two]
+        .into_iter()
+        .find(|player| healths[player.index()] > 0);
+
+    time_scale.0 = SLOW_MOTION_SPEED;
+
+    let entities = vec![commands
+        .spawn(SpriteBundle {
+            sprite: Sprite {
+                color: Color::WHITE,
+                custom_size: Some(Vec2::new(VIEW_WIDTH, VIEW_HEIGHT)),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, FLASH_Z),
+            ..default()
+        })
+        .insert(Flash)
+        .insert(RenderLayers::layer(HUD_LAYER))
+        .id()];
+
+    commands.insert_resource(KnockOut {
+        timer: Timer::from_seconds(KNOCK_OUT_SECS, TimerMode::Once),
+        winner,
+    });
+    commands.insert_resource(EntityData { entities });
+}
+
+/// Play out the knock out. The fight is slowed down for the final blow while the screen flashes.
+fn knock_out_system(
+    time: Res<Time>,
+    knock_out: Option<ResMut<KnockOut>>,
+    mut time_scale: ResMut<TimeScale>,
+    mut flash_query: Query<&mut Sprite, With<Flash>>,
+) {
+    let mut knock_out = match knock_out {
+        Some(knock_out) => knock_out,
+        None => return,
+    };
+
+    knock_out.timer.tick(time.delta());
+    let elapsed = knock_out.timer.elapsed_secs();
+
+    if elapsed >= SLOW_MOTION_SECS {
+        time_scale.0 = 1.0;
+    }
+
+    for mut sprite in &mut flash_query {
+        sprite.color.set_a((1.0 - elapsed / FLASH_SECS).max(0.0));
+    }
+}
+
+/// Cleanup resources.
+fn cleanup(
+    mut commands: Commands,
+    entity_data: Option<Res<EntityData>>,
+    mut time_scale: ResMut<TimeScale>,
+) {
+    if let Some(entity_data) = entity_data {
+        for entity in entity_data.entities.iter() {
+            commands.entity(*entity).despawn_recursive();
+        }
+    }
+    commands.remove_resource::<EntityData>();
+    commands.remove_resource::<KnockOut>();
+    time_scale.0 = 1.0;
+}
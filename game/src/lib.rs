@@ -1,30 +1,99 @@
 //! Figher
 
+mod arcade;
+mod callout;
+mod camera;
+mod character;
+mod character_select;
 mod common;
 mod countdown_timer;
+mod cpu;
+mod display;
+mod effects;
+mod fight_state;
 mod game_over_menu;
 mod health;
+mod high_scores;
+mod input;
+mod knock_out;
+mod lobby;
 mod main_menu;
+mod match_settings;
 mod menu;
+mod meter;
+mod netplay;
+mod network;
 mod player;
+mod projectile;
+mod rollback;
 mod scene;
+mod spectator;
+mod stage;
+mod stage_select;
 mod utils;
 
+use arcade::*;
 use bevy::{prelude::*, window::PresentMode};
 use bevy_asset_loader::prelude::*;
 use bevy_kira_audio::prelude::*;
+use callout::*;
+use camera::*;
+use character::*;
+use character_select::*;
 use common::*;
 use countdown_timer::*;
+use cpu::*;
+use display::*;
+use effects::*;
+use fight_state::*;
 use game_over_menu::*;
 use health::*;
+use high_scores::*;
+use input::*;
+use knock_out::*;
+use lobby::*;
 use main_menu::*;
+use match_settings::*;
 use menu::*;
+use meter::*;
+pub use netplay::NetplayOptions;
+use netplay::*;
+use network::*;
 use player::*;
+use projectile::*;
+use rollback::*;
 use scene::*;
+use spectator::*;
+use stage::*;
+// Bevy's prelude has a `Stage` trait too.
+use stage::Stage;
+use stage_select::*;
 
 // Create the app.
-pub fn run() {
-    App::new()
+pub fn run() -> Result<(), String> {
+    run_with(NetplayOptions::default())
+}
+
+// Create the app with the given online play options. Fails if the options can't be used.
+pub fn run_with(netplay_options: NetplayOptions) -> Result<(), String> {
+    let mut app = App::new();
+
+    // Playing directly against a peer skips the lobby and goes online from the main menu.
+    if let Some(transport) = netplay_options.bind_direct() {
+        let transport =
+            transport.map_err(|err| format!("Couldn't open netplay socket: {}", err))?;
+        let direct = netplay_options.direct.as_ref().unwrap();
+        app.insert_resource(NetTransport(transport))
+            .insert_resource(NetplayConfig {
+                local_player: direct.local_player,
+                input_delay: netplay_options.input_delay,
+                match_id: 0,
+                from_lobby: false,
+            });
+    }
+
+    app.insert_resource(netplay_options)
+        .init_resource::<MatchSettings>()
         .add_loading_state(
             LoadingState::new(GameState::AssetLoading)
                 .continue_to_state(GameState::MainMenu)
@@ -37,9 +106,11 @@ pub fn run() {
                 .set(WindowPlugin {
                     window: WindowDescriptor {
                         title: "Fighter".to_string(),
-                        width: WINDOW_WIDTH,
-                        height: WINDOW_HEIGHT,
+                        width: VIEW_WIDTH,
+                        height: VIEW_HEIGHT,
                         present_mode: PresentMode::AutoNoVsync,
+                        // Fill the page in browsers.
+                        fit_canvas_to_parent: true,
                         ..default()
                     },
                     ..default()
@@ -50,13 +121,28 @@ pub fn run() {
                 }),
         )
         .add_plugin(AudioPlugin)
+        .add_plugin(StagePlugin)
+        .add_plugin(RollbackPlugin)
         .add_plugin(MainMenuPlugin)
+        .add_plugin(LobbyPlugin)
+        .add_plugin(CharacterSelectPlugin)
+        .add_plugin(StageSelectPlugin)
+        .add_plugin(CameraPlugin)
+        .add_plugin(DisplayPlugin)
         .add_plugin(ScenePlugin)
         .add_plugin(PlayerPlugin)
+        .add_plugin(EffectsPlugin)
+        .add_plugin(CalloutPlugin)
+        .add_plugin(CpuPlugin)
         .add_plugin(HealthPlugin)
+        .add_plugin(MeterPlugin)
         .add_plugin(CountdownTimerPlugin)
+        .add_plugin(KnockOutPlugin)
         .add_plugin(GameOverPlugin)
+        .add_plugin(ArcadePlugin)
         .run();
+
+    Ok(())
 }
 
 /// Game assets
@@ -65,13 +151,6 @@ struct GameAssets {
     #[asset(path = "fonts/m6x11.ttf")]
     pub(crate) font: Handle<Font>,
 
-    #[asset(path = "images/background_composite.png")]
-    pub(crate) background_image: Handle<Image>,
-
-    #[asset(texture_atlas(tile_size_x = 118.0, tile_size_y = 128.0, columns = 6, rows = 1))]
-    #[asset(path = "images/shop_anim.png")]
-    pub(crate) shop_texture_atlas: Handle<TextureAtlas>,
-
     #[asset(texture_atlas(tile_size_x = 200.0, tile_size_y = 200.0, columns = 8, rows = 9))]
     #[asset(path = "images/player_one.png")]
     pub(crate) player_one_texture_atlas: Handle<TextureAtlas>,
@@ -80,6 +159,10 @@ struct GameAssets {
     #[asset(path = "images/player_two.png")]
     pub(crate) player_two_texture_atlas: Handle<TextureAtlas>,
 
+    #[asset(texture_atlas(tile_size_x = 32.0, tile_size_y = 32.0, columns = 6, rows = 2))]
+    #[asset(path = "images/effects.png")]
+    pub(crate) effects_texture_atlas: Handle<TextureAtlas>,
+
     #[asset(path = "images/return_key.png")]
     pub(crate) return_key_image: Handle<Image>,
 
@@ -94,9 +177,6 @@ struct GameAssets {
 
     #[asset(path = "audio/Adventure Theme Intro.wav")]
     pub(crate) main_menu_audio: Handle<AudioSource>,
-
-    #[asset(path = "audio/Boss Battle 6 Metal V1.wav")]
-    pub(crate) in_game_audio: Handle<AudioSource>,
 }
 
 /// Game states.
@@ -104,6 +184,11 @@ struct GameAssets {
 enum GameState {
     AssetLoading,
     MainMenu,
+    Lobby,
+    CharacterSelect,
+    StageSelect,
     InGame,
     GameOver,
+    ArcadeContinue,
+    ArcadeEnding,
 }
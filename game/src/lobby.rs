@@ -0,0 +1,858 @@
+//! Lobby
+
+use crate::{
+    menu_background, menu_border, menu_button, menu_button_text, menu_root, Character, GameAssets,
+    GameState, MatchSettings, Message, NetStats, NetTransport, NetplayConfig, NetplayOptions,
+    PingTracker, Player, Spectating, Stages, Transport, UdpTransport, DISCONNECT_TIMEOUT,
+};
+use bevy::{prelude::*, utils::Instant};
+use std::{net::ToSocketAddrs, time::Duration};
+
+/// Default port for hosting a match.
+const DEFAULT_PORT: u16 = 7000;
+
+/// Default room to meet in on the relay server.
+const DEFAULT_ROOM: &str = "fighter";
+
+/// How often a guest asks to join and a host repeats the start of a match.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Maximum length of the address field.
+const MAX_ADDRESS_LEN: usize = 64;
+
+/// Handles setting up online matches.
+pub(crate) struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<LobbyCommand>()
+            .init_resource::<Lobby>()
+            .add_system(button_press_system)
+            .add_system_set(SystemSet::on_enter(GameState::Lobby).with_system(setup))
+            .add_system_set(
+                SystemSet::on_update(GameState::Lobby)
+                    .with_system(input_system.before(lobby_system))
+                    .with_system(lobby_system)
+                    .with_system(display_system.after(lobby_system)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Lobby).with_system(cleanup))
+            // Connection status while playing online.
+            .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(net_status_setup))
+            .add_system_set(SystemSet::on_update(GameState::InGame).with_system(net_status_system))
+            .add_system_set(SystemSet::on_exit(GameState::InGame).with_system(net_status_cleanup));
+    }
+}
+
+/// Lobby entities.
+#[derive(Resource)]
+struct EntityData {
+    entities: Vec<Entity>,
+}
+
+/// Net status entities.
+#[derive(Resource)]
+struct NetStatusEntityData {
+    entities: Vec<Entity>,
+}
+
+/// Whether we host a match or join one.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum LobbyMode {
+    Host,
+    Join,
+}
+
+/// Connection status in the lobby.
+#[derive(Clone, Debug, PartialEq)]
+enum LobbyStatus {
+    /// Entering the port, address or relay room.
+    Editing,
+
+    /// Host is waiting for a guest or guest is waiting for the host to answer.
+    Waiting,
+
+    /// Both peers are connected.
+    Connected,
+
+    /// Host started the match and is waiting for the guest to follow.
+    Starting { match_id: u8 },
+
+    /// Something went wrong. Address can be edited again.
+    Error(String),
+}
+
+/// Lobby state. Kept while moving between the lobby and online matches.
+#[derive(Resource)]
+pub(crate) struct Lobby {
+    mode: LobbyMode,
+    relay: bool,
+    address: String,
+    status: LobbyStatus,
+    ping: PingTracker,
+    last_sent: Option<Instant>,
+    next_match_id: u8,
+    last_match_id: Option<u8>,
+
+    /// Character and palette picked on this side.
+    character: Character,
+    palette: u8,
+}
+
+impl Default for Lobby {
+    fn default() -> Self {
+        Self::new(LobbyMode::Host, false)
+    }
+}
+
+impl Lobby {
+    /// Create a lobby. Through a relay both peers enter the same room name instead of a port and
+    /// address.
+    pub(crate) fn new(mode: LobbyMode, relay: bool) -> Self {
+        Self {
+            mode,
+            relay,
+            address: match (mode, relay) {
+                (_, true) => DEFAULT_ROOM.to_string(),
+                (LobbyMode::Host, false) => format!("{}", DEFAULT_PORT),
+                (LobbyMode::Join, false) => format!("127.0.0.1:{}", DEFAULT_PORT),
+            },
+            status: LobbyStatus::Editing,
+            ping: PingTracker::new(),
+            last_sent: None,
+            next_match_id: 0,
+            last_match_id: None,
+            character: MatchSettings::default().characters[Self::local_player(mode).index()],
+            palette: 0,
+        }
+    }
+
+    /// Pick the next or previous fighter look. Goes through a character's palettes before moving
+    /// on to the next character.
+    fn cycle_fighter(&mut self, forward: bool) {
+        let last_palette = self.character.palettes().len() as u8 - 1;
+        match (forward, self.palette) {
+            (true, p) if p >= last_palette => {
+                self.character = self.character.cycle(true);
+                self.palette = 0;
+            }
+            (true, p) => self.palette = p + 1,
+            (false, 0) => {
+                self.character = self.character.cycle(false);
+                self.palette = self.character.palettes().len() as u8 - 1;
+            }
+            (false, p) => self.palette = p - 1,
+        }
+    }
+
+    /// Store the pick made on this side in the match settings.
+    fn apply_pick(&self, settings: &mut MatchSettings) {
+        let i = Self::local_player(self.mode).index();
+        settings.characters[i] = self.character;
+        settings.palettes[i] = self.palette;
+    }
+
+    /// Host plays as player one and the guest as player two.
+    fn local_player(mode: LobbyMode) -> Player {
+        match mode {
+            LobbyMode::Host => Player::One,
+            LobbyMode::Join => Player::Two,
+        }
+    }
+
+    /// Open the socket for hosting or joining.
+    fn open(&self, options: &NetplayOptions) -> Result<Box<dyn Transport>, String> {
+        if self.relay {
+            if self.address.is_empty() {
+                return Err("ENTER A ROOM".to_string());
+            }
+            return options.connect_relay(&self.address);
+        }
+
+        match self.mode {
+            LobbyMode::Host => {
+                let port: u16 = self
+                    .address
+                    .parse()
+                    .map_err(|_| format!("INVALID PORT {}", self.address))?;
+                let udp = UdpTransport::listen(port).map_err(|e| e.to_string())?;
+                Ok(options.condition(udp, port as u32))
+            }
+            LobbyMode::Join => {
+                let remote_addr = self
+                    .address
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .ok_or_else(|| format!("INVALID ADDRESS {}", self.address))?;
+                let udp = UdpTransport::bind(0, remote_addr).map_err(|e| e.to_string())?;
+                Ok(options.condition(udp, remote_addr.port() as u32))
+            }
+        }
+    }
+
+    /// Returns true if it's time to resend a message.
+    fn should_send(&mut self, interval: Duration) -> bool {
+        let now = Instant::now();
+        if self
+            .last_sent
+            .is_none_or(|t| now.duration_since(t) >= interval)
+        {
+            self.last_sent = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Peer left or stopped responding. Wait for it (or another guest) to come back.
+    fn peer_lost(&mut self, transport: &mut dyn Transport) {
+        warn!("Lost connection to peer");
+        transport.disconnect();
+        self.status = LobbyStatus::Waiting;
+        self.ping = PingTracker::new();
+    }
+
+    /// Text describing the connection.
+    fn status_text(&self) -> String {
+        match (&self.status, self.mode) {
+            (LobbyStatus::Editing, _) if self.relay => "ENTER ROOM TO MEET IN".to_string(),
+            (LobbyStatus::Editing, LobbyMode::Host) => "ENTER PORT TO HOST ON".to_string(),
+            (LobbyStatus::Editing, LobbyMode::Join) => "ENTER HOST ADDRESS".to_string(),
+            (LobbyStatus::Waiting, LobbyMode::Host) => "WAITING FOR OPPONENT".to_string(),
+            (LobbyStatus::Waiting, LobbyMode::Join) => "CONNECTING...".to_string(),
+            (LobbyStatus::Connected, _) | (LobbyStatus::Starting { .. }, _) => {
+                match self.ping.rtt() {
+                    Some(rtt) => format!("CONNECTED - PING {} MS", rtt.as_millis()),
+                    None => "CONNECTED".to_string(),
+                }
+            }
+            (LobbyStatus::Error(err), _) => format!("ERROR: {}", err.to_uppercase()),
+        }
+    }
+}
+
+/// Actions from keyboard and buttons.
+enum LobbyCommand {
+    Confirm,
+    Back,
+    RoundTime { forward: bool },
+    TimeOver { forward: bool },
+    Fighter { forward: bool },
+    Stage { forward: bool },
+}
+
+/// Represents lobby buttons.
+#[derive(Component)]
+enum LobbyButton {
+    Confirm,
+    RoundTime,
+    TimeOver,
+    Fighter,
+    Stage,
+    Back,
+}
+
+/// Text showing the port/address.
+#[derive(Component)]
+struct AddressText;
+
+/// Text showing the connection status.
+#[derive(Component)]
+struct StatusText;
+
+/// Text showing the round time.
+#[derive(Component)]
+struct RoundTimeText;
+
+/// Text showing how the winner is decided when time runs out.
+#[derive(Component)]
+struct TimeOverText;
+
+/// Text showing the stage.
+#[derive(Component)]
+struct StageText;
+
+/// Text showing the character picked on this side.
+#[derive(Component)]
+struct FighterText;
+
+/// Text of the confirm button.
+#[derive(Component)]
+struct ConfirmText;
+
+/// Text showing network stats during a match.
+#[derive(Component)]
+struct NetStatusText;
+
+/// Setup the lobby.
+fn setup(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    mut lobby: ResMut<Lobby>,
+    mut settings: ResMut<MatchSettings>,
+) {
+    // Matches are only online while set up by the lobby.
+    commands.remove_resource::<NetplayConfig>();
+
+    lobby.apply_pick(&mut settings);
+
+    // Coming back from a match keeps the connection.
+    if matches!(
+        lobby.status,
+        LobbyStatus::Connected | LobbyStatus::Starting { .. }
+    ) {
+        lobby.status = LobbyStatus::Connected;
+        lobby.ping = PingTracker::new();
+    }
+
+    let title = match lobby.mode {
+        LobbyMode::Host => "HOST MATCH",
+        LobbyMode::Join => "JOIN MATCH",
+    };
+
+    let mut entities: Vec<Entity> = Vec::new();
+
+    entities.push(
+        commands
+            .spawn(menu_root())
+            .with_children(|parent| {
+                parent.spawn(menu_border()).with_children(|parent| {
+                    parent.spawn(menu_background()).with_children(|parent| {
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent.spawn(menu_button_text(&assets, "BACK"));
+                                parent.spawn(ImageBundle {
+                                    image: UiImage(assets.escape_key_image.clone()),
+                                    transform: Transform::from_scale(Vec3::new(0.58, 0.58, 0.58)),
+                                    ..default()
+                                });
+                            })
+                            .insert(LobbyButton::Back);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent
+                                    .spawn(menu_button_text(&assets, ""))
+                                    .insert(ConfirmText);
+                                parent.spawn(ImageBundle {
+                                    image: UiImage(assets.return_key_image.clone()),
+                                    transform: Transform::from_scale(Vec3::new(0.5, 0.5, 0.5)),
+                                    ..default()
+                                });
+                            })
+                            .insert(LobbyButton::Confirm);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent
+                                    .spawn(menu_button_text(&assets, ""))
+                                    .insert(RoundTimeText);
+                            })
+                            .insert(LobbyButton::RoundTime);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent
+                                    .spawn(menu_button_text(&assets, ""))
+                                    .insert(TimeOverText);
+                            })
+                            .insert(LobbyButton::TimeOver);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent
+                                    .spawn(menu_button_text(&assets, ""))
+                                    .insert(FighterText);
+                            })
+                            .insert(LobbyButton::Fighter);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent
+                                    .spawn(menu_button_text(&assets, ""))
+                                    .insert(StageText);
+                            })
+                            .insert(LobbyButton::Stage);
+
+                        parent
+                            .spawn(menu_button_text(&assets, ""))
+                            .insert(StatusText);
+                        parent
+                            .spawn(menu_button_text(&assets, ""))
+                            .insert(AddressText);
+                        parent.spawn(menu_button_text(&assets, title));
+                    });
+                });
+            })
+            .id(),
+    );
+
+    commands.insert_resource(EntityData { entities });
+}
+
+/// Handle keyboard input.
+fn input_system(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut received_characters: EventReader<ReceivedCharacter>,
+    mut lobby: ResMut<Lobby>,
+    mut lobby_commands: EventWriter<LobbyCommand>,
+) {
+    let editing = matches!(lobby.status, LobbyStatus::Editing | LobbyStatus::Error(_));
+
+    for event in received_characters.iter() {
+        let c = event.char;
+        let allowed = match (lobby.mode, lobby.relay) {
+            (_, true) => c.is_ascii_alphanumeric() || c == '-' || c == '_',
+            (LobbyMode::Host, false) => c.is_ascii_digit(),
+            (LobbyMode::Join, false) => {
+                c.is_ascii_alphanumeric() || c == '.' || c == ':' || c == '-'
+            }
+        };
+        if editing && allowed && lobby.address.len() < MAX_ADDRESS_LEN {
+            lobby.address.push(c);
+            lobby.status = LobbyStatus::Editing;
+        }
+    }
+
+    if editing && keyboard_input.just_pressed(KeyCode::Back) {
+        lobby.address.pop();
+        lobby.status = LobbyStatus::Editing;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        lobby_commands.send(LobbyCommand::Confirm);
+        keyboard_input.clear_just_pressed(KeyCode::Return);
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        lobby_commands.send(LobbyCommand::Back);
+        keyboard_input.clear_just_pressed(KeyCode::Escape);
+    } else if keyboard_input.just_pressed(KeyCode::Left) {
+        lobby_commands.send(LobbyCommand::RoundTime { forward: false });
+    } else if keyboard_input.just_pressed(KeyCode::Right) {
+        lobby_commands.send(LobbyCommand::RoundTime { forward: true });
+    } else if keyboard_input.just_pressed(KeyCode::Up) {
+        lobby_commands.send(LobbyCommand::Fighter { forward: false });
+    } else if keyboard_input.just_pressed(KeyCode::Down) {
+        lobby_commands.send(LobbyCommand::Fighter { forward: true });
+    } else if keyboard_input.just_pressed(KeyCode::Tab) {
+        lobby_commands.send(LobbyCommand::Stage { forward: true });
+    }
+}
+
+/// Processes button press.
+#[allow(clippy::type_complexity)]
+fn button_press_system(
+    buttons: Query<(&Interaction, &LobbyButton), (Changed<Interaction>, With<Button>)>,
+    mut lobby_commands: EventWriter<LobbyCommand>,
+) {
+    for (interaction, button) in buttons.iter() {
+        if *interaction == Interaction::Clicked {
+            lobby_commands.send(match button {
+                LobbyButton::Confirm => LobbyCommand::Confirm,
+                LobbyButton::RoundTime => LobbyCommand::RoundTime { forward: true },
+                LobbyButton::TimeOver => LobbyCommand::TimeOver { forward: true },
+                LobbyButton::Fighter => LobbyCommand::Fighter { forward: true },
+                LobbyButton::Stage => LobbyCommand::Stage { forward: true },
+                LobbyButton::Back => LobbyCommand::Back,
+            });
+        }
+    }
+}
+
+/// Handle lobby commands and talk to the peer.
+#[allow(clippy::too_many_arguments)]
+fn lobby_system(
+    mut commands: Commands,
+    mut lobby: ResMut<Lobby>,
+    mut lobby_commands: EventReader<LobbyCommand>,
+    mut transport: Option<ResMut<NetTransport>>,
+    options: Res<NetplayOptions>,
+    mut settings: ResMut<MatchSettings>,
+    stages: Res<Stages>,
+    mut state: ResMut<State<GameState>>,
+) {
+    for command in lobby_commands.iter() {
+        match command {
+            LobbyCommand::Confirm => match lobby.status {
+                LobbyStatus::Editing | LobbyStatus::Error(_) => match lobby.open(&options) {
+                    Ok(t) => {
+                        commands.insert_resource(NetTransport(t));
+                        lobby.status = LobbyStatus::Waiting;
+                        lobby.ping = PingTracker::new();
+                    }
+                    Err(err) => lobby.status = LobbyStatus::Error(err),
+                },
+                LobbyStatus::Connected if lobby.mode == LobbyMode::Host => {
+                    let match_id = lobby.next_match_id;
+                    lobby.next_match_id = match_id.wrapping_add(1);
+                    lobby.status = LobbyStatus::Starting { match_id };
+                    lobby.last_sent = None;
+                }
+                _ => (),
+            },
+            LobbyCommand::Back => {
+                if let Some(transport) = transport.as_mut() {
+                    transport.0.send(&Message::Leave.encode());
+                }
+                commands.remove_resource::<NetTransport>();
+                state
+                    .set(GameState::MainMenu)
+                    .expect("Couldn't switch state to MainMenu");
+                return;
+            }
+            LobbyCommand::RoundTime { .. }
+            | LobbyCommand::TimeOver { .. }
+            | LobbyCommand::Stage { .. } => {
+                if lobby.mode == LobbyMode::Host
+                    && !matches!(lobby.status, LobbyStatus::Starting { .. })
+                {
+                    match command {
+                        LobbyCommand::RoundTime { forward } => settings.cycle_round_time(*forward),
+                        LobbyCommand::TimeOver { forward } => settings.cycle_time_over(*forward),
+                        LobbyCommand::Stage { forward } => {
+                            settings.stage = stages.cycle(settings.stage, *forward)
+                        }
+                        _ => (),
+                    }
+                    if let Some(transport) = transport.as_mut() {
+                        transport.0.send(
+                            &Message::Settings {
+                                settings: settings.encode(),
+                            }
+                            .encode(),
+                        );
+                    }
+                }
+            }
+            LobbyCommand::Fighter { forward } => {
+                if !matches!(lobby.status, LobbyStatus::Starting { .. }) {
+                    lobby.cycle_fighter(*forward);
+                    lobby.apply_pick(&mut settings);
+
+                    // The host decides the settings so the guest tells it about its pick.
+                    if let Some(transport) = transport.as_mut() {
+                        let message = match lobby.mode {
+                            LobbyMode::Host => Message::Settings {
+                                settings: settings.encode(),
+                            },
+                            LobbyMode::Join => Message::Pick {
+                                character: lobby.character,
+                                palette: lobby.palette,
+                            },
+                        };
+                        transport.0.send(&message.encode());
+                    }
+                }
+            }
+        }
+    }
+
+    let transport = match transport.as_mut() {
+        Some(transport) => &mut transport.0,
+        None => return,
+    };
+
+    while let Some(packet) = transport.receive() {
+        let message = match Message::decode(&packet) {
+            Some(message) => message,
+            None => continue,
+        };
+        lobby.ping.received();
+
+        match (lobby.mode, message) {
+            (_, Message::Ping { time }) => transport.send(&Message::Pong { time }.encode()),
+            (_, Message::Pong { time }) => lobby.ping.pong(time),
+            (_, Message::Leave) => lobby.peer_lost(transport.as_mut()),
+            (LobbyMode::Host, Message::Join { stages: checksum }) => {
+                // Stages are picked by index so both peers need the same ones.
+                if checksum != stages.checksum() {
+                    warn!("Guest has different stages");
+                    transport.send(&Message::StageMismatch.encode());
+                    continue;
+                }
+                if lobby.status == LobbyStatus::Waiting {
+                    info!("Guest joined");
+                    lobby.status = LobbyStatus::Connected;
+                }
+                transport.send(
+                    &Message::Settings {
+                        settings: settings.encode(),
+                    }
+                    .encode(),
+                );
+            }
+            // Guest is already in the match.
+            (LobbyMode::Host, Message::Hello { match_id, .. })
+                if lobby.status == (LobbyStatus::Starting { match_id }) =>
+            {
+                commands.insert_resource(NetplayConfig {
+                    local_player: Player::One,
+                    input_delay: options.input_delay,
+                    match_id,
+                    from_lobby: true,
+                });
+                state
+                    .set(GameState::InGame)
+                    .expect("Couldn't switch state to InGame");
+                return;
+            }
+            (LobbyMode::Host, Message::Pick { character, palette }) => {
+                let i = Player::Two.index();
+                if !matches!(lobby.status, LobbyStatus::Starting { .. })
+                    && (settings.characters[i], settings.palettes[i]) != (character, palette)
+                {
+                    settings.characters[i] = character;
+                    settings.palettes[i] = palette;
+                    transport.send(
+                        &Message::Settings {
+                            settings: settings.encode(),
+                        }
+                        .encode(),
+                    );
+                }
+            }
+            (LobbyMode::Join, Message::StageMismatch) if lobby.status == LobbyStatus::Waiting => {
+                transport.disconnect();
+                lobby.status = LobbyStatus::Error("Host has different stages".to_string());
+                return;
+            }
+            (LobbyMode::Join, Message::Settings { settings: bytes }) => {
+                if let Some(s) = MatchSettings::decode(&bytes) {
+                    *settings = s;
+                    // The host may not have heard about our latest pick yet.
+                    lobby.apply_pick(&mut settings);
+                }
+                if lobby.status == LobbyStatus::Waiting {
+                    info!("Joined host");
+                    lobby.status = LobbyStatus::Connected;
+                }
+            }
+            (
+                LobbyMode::Join,
+                Message::Start {
+                    match_id,
+                    settings: bytes,
+                },
+            ) => {
+                // Host repeats the start until we answer so ignore the one we already played.
+                if lobby.last_match_id == Some(match_id) {
+                    continue;
+                }
+                if let Some(s) = MatchSettings::decode(&bytes) {
+                    *settings = s;
+                }
+                lobby.last_match_id = Some(match_id);
+                commands.insert_resource(NetplayConfig {
+                    local_player: Player::Two,
+                    input_delay: options.input_delay,
+                    match_id,
+                    from_lobby: true,
+                });
+                state
+                    .set(GameState::InGame)
+                    .expect("Couldn't switch state to InGame");
+                return;
+            }
+            _ => (),
+        }
+    }
+
+    match lobby.status.clone() {
+        LobbyStatus::Waiting => {
+            if lobby.mode == LobbyMode::Join && lobby.should_send(RETRY_INTERVAL) {
+                let message = Message::Join {
+                    stages: stages.checksum(),
+                };
+                transport.send(&message.encode());
+            }
+        }
+        LobbyStatus::Connected | LobbyStatus::Starting { .. } => {
+            if lobby.ping.silence() > DISCONNECT_TIMEOUT {
+                lobby.peer_lost(transport.as_mut());
+                return;
+            }
+
+            if let Some(ping) = lobby.ping.ping() {
+                transport.send(&ping.encode());
+                // Settings and picks ride along with pings in case a packet was lost.
+                let message = match lobby.mode {
+                    LobbyMode::Host => Message::Settings {
+                        settings: settings.encode(),
+                    },
+                    LobbyMode::Join => Message::Pick {
+                        character: lobby.character,
+                        palette: lobby.palette,
+                    },
+                };
+                transport.send(&message.encode());
+            }
+
+            if let LobbyStatus::Starting { match_id } = lobby.status {
+                if lobby.should_send(RETRY_INTERVAL) {
+                    transport.send(
+                        &Message::Start {
+                            match_id,
+                            settings: settings.encode(),
+                        }
+                        .encode(),
+                    );
+                }
+            }
+        }
+        LobbyStatus::Editing | LobbyStatus::Error(_) => (),
+    }
+}
+
+/// Update lobby text.
+#[allow(clippy::type_complexity)]
+fn display_system(
+    lobby: Res<Lobby>,
+    settings: Res<MatchSettings>,
+    stages: Res<Stages>,
+    mut text_query: Query<(
+        &mut Text,
+        Option<&AddressText>,
+        Option<&StatusText>,
+        Option<&RoundTimeText>,
+        Option<&TimeOverText>,
+        Option<&FighterText>,
+        Option<&StageText>,
+        Option<&ConfirmText>,
+    )>,
+) {
+    let editing = matches!(lobby.status, LobbyStatus::Editing | LobbyStatus::Error(_));
+
+    for (mut text, address, status, round_time, time_over, fighter, stage, confirm) in
+        &mut text_query
+    {
+        let value = if address.is_some() {
+            let label = match (lobby.mode, lobby.relay) {
+                (_, true) => "ROOM",
+                (LobbyMode::Host, false) => "PORT",
+                (LobbyMode::Join, false) => "ADDRESS",
+            };
+            let cursor = if editing { "_" } else { "" };
+            format!("{}: {}{}", label, lobby.address, cursor)
+        } else if status.is_some() {
+            lobby.status_text()
+        } else if round_time.is_some() {
+            format!("ROUND TIME: {}", settings.round_time_text())
+        } else if time_over.is_some() {
+            format!("TIME OVER: {}", settings.time_over.name())
+        } else if fighter.is_some() {
+            format!("FIGHTER: {} {}", lobby.character.name(), lobby.palette + 1)
+        } else if stage.is_some() {
+            format!("STAGE: {}", stages.get(settings.stage).name)
+        } else if confirm.is_some() {
+            match (&lobby.status, lobby.mode) {
+                (LobbyStatus::Editing | LobbyStatus::Error(_), LobbyMode::Host) => "HOST",
+                (LobbyStatus::Editing | LobbyStatus::Error(_), LobbyMode::Join) => "JOIN",
+                (LobbyStatus::Connected, LobbyMode::Host) => "START",
+                (LobbyStatus::Starting { .. }, _) => "STARTING...",
+                (_, LobbyMode::Host) => "WAITING...",
+                (_, LobbyMode::Join) => "WAITING FOR HOST",
+            }
+            .to_string()
+        } else {
+            continue;
+        };
+
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+/// Cleanup resources.
+fn cleanup(mut commands: Commands, entity_data: Res<EntityData>) {
+    for entity in entity_data.entities.iter() {
+        commands.entity(*entity).despawn_recursive();
+    }
+}
+
+/// Show network status during online matches.
+fn net_status_setup(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    config: Option<Res<NetplayConfig>>,
+    spectating: Option<Res<Spectating>>,
+) {
+    let mut entities: Vec<Entity> = Vec::new();
+
+    if config.is_some() || spectating.is_some() {
+        entities.push(
+            commands
+                .spawn(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.0), Val::Percent(10.0)),
+                        position: UiRect {
+                            top: Val::Px(70.0),
+                            left: Val::Px(0.0),
+                            ..default()
+                        },
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: BackgroundColor(Color::NONE),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn(TextBundle {
+                            text: Text::from_section(
+                                "CONNECTING...",
+                                TextStyle {
+                                    font: assets.font.clone(),
+                                    font_size: 16.0,
+                                    color: Color::WHITE,
+                                },
+                            )
+                            .with_alignment(TextAlignment::CENTER),
+                            ..default()
+                        })
+                        .insert(NetStatusText);
+                })
+                .id(),
+        );
+    }
+
+    commands.insert_resource(NetStatusEntityData { entities });
+}
+
+/// Update network status text.
+fn net_status_system(
+    stats: Option<Res<NetStats>>,
+    spectating: Option<Res<Spectating>>,
+    mut text_query: Query<&mut Text, With<NetStatusText>>,
+) {
+    let stats = match stats {
+        Some(stats) => stats,
+        None => return,
+    };
+
+    for mut text in &mut text_query {
+        text.sections[0].value = if !stats.synchronized {
+            "CONNECTING...".to_string()
+        } else if spectating.is_some() {
+            "SPECTATING".to_string()
+        } else {
+            match stats.ping {
+                Some(ping) => format!("PING {} MS", ping.as_millis()),
+                None => String::new(),
+            }
+        };
+    }
+}
+
+/// Cleanup network status.
+fn net_status_cleanup(mut commands: Commands, entity_data: Res<NetStatusEntityData>) {
+    for entity in entity_data.entities.iter() {
+        commands.entity(*entity).despawn_recursive();
+    }
+}
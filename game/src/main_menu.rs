@@ -2,7 +2,8 @@
 
 use crate::{
     menu_background, menu_border, menu_button, menu_button_interaction_system, menu_button_text,
-    menu_root, GameAssets, GameState,
+    menu_root, Arcade, ArcadeMode, GameAssets, GameState, Lobby, LobbyMode, MatchSettings,
+    NetplayConfig, NetplayOptions, RoundScore, Spectating, Stages, SuperMeters,
 };
 use bevy::{app::AppExit, prelude::*};
 use bevy_kira_audio::prelude::*;
@@ -30,13 +31,32 @@ struct EntityData {
 #[derive(Component)]
 enum MenuButton {
     Play,
+    Arcade(ArcadeMode),
+    Host,
+    Join,
+    Watch,
     Quit,
 }
 
 /// Setup the main menu.
-fn setup(mut commands: Commands, assets: Res<GameAssets>, audio: Res<Audio>) {
+fn setup(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    audio: Res<Audio>,
+    netplay_options: Res<NetplayOptions>,
+    mut settings: ResMut<MatchSettings>,
+) {
     audio.play(assets.main_menu_audio.clone()).looped();
 
+    // Only spectate or play against the CPU when asked to from this menu.
+    commands.remove_resource::<Spectating>();
+    commands.remove_resource::<Arcade>();
+    settings.cpu = None;
+
+    // Rounds are counted and meter is kept until returning to the main menu.
+    commands.insert_resource(RoundScore::default());
+    commands.insert_resource(SuperMeters::default());
+
     let mut entities: Vec<Entity> = Vec::new();
 
     entities.push(
@@ -65,6 +85,46 @@ fn setup(mut commands: Commands, assets: Res<GameAssets>, audio: Res<Audio>) {
                                 .insert(MenuButton::Quit);
                         }
 
+                        if netplay_options.watch.is_some() {
+                            parent
+                                .spawn(menu_button())
+                                .with_children(|parent| {
+                                    parent.spawn(menu_button_text(&assets, "WATCH MATCH"));
+                                })
+                                .insert(MenuButton::Watch);
+                        }
+
+                        // Playing directly against a peer given on the command line doesn't
+                        // use the lobby.
+                        if netplay_options.direct.is_none() {
+                            parent
+                                .spawn(menu_button())
+                                .with_children(|parent| {
+                                    parent.spawn(menu_button_text(&assets, "JOIN ONLINE"));
+                                })
+                                .insert(MenuButton::Join);
+
+                            parent
+                                .spawn(menu_button())
+                                .with_children(|parent| {
+                                    parent.spawn(menu_button_text(&assets, "HOST ONLINE"));
+                                })
+                                .insert(MenuButton::Host);
+
+                            for (label, mode) in [
+                                ("TIME ATTACK", ArcadeMode::TimeAttack),
+                                ("SURVIVAL", ArcadeMode::Survival),
+                                ("ARCADE", ArcadeMode::Ladder),
+                            ] {
+                                parent
+                                    .spawn(menu_button())
+                                    .with_children(|parent| {
+                                        parent.spawn(menu_button_text(&assets, label));
+                                    })
+                                    .insert(MenuButton::Arcade(mode));
+                            }
+                        }
+
                         parent
                             .spawn(menu_button())
                             .with_children(|parent| {
@@ -86,33 +146,81 @@ fn setup(mut commands: Commands, assets: Res<GameAssets>, audio: Res<Audio>) {
 }
 
 /// Processes button press.
+#[allow(clippy::too_many_arguments)]
 fn menu_button_press_system(
+    mut commands: Commands,
     buttons: Query<(&Interaction, &MenuButton), (Changed<Interaction>, With<Button>)>,
     mut state: ResMut<State<GameState>>,
     mut exit: EventWriter<AppExit>,
+    mut lobby: ResMut<Lobby>,
+    netplay_options: Res<NetplayOptions>,
+    netplay_config: Option<Res<NetplayConfig>>,
+    stages: Option<Res<Stages>>,
 ) {
     for (interaction, button) in buttons.iter() {
-        if *interaction == Interaction::Clicked {
+        // Everything past this menu needs the stages, which can still be loading when the menu is
+        // first shown.
+        let loading = stages.is_none() && !matches!(button, MenuButton::Quit);
+        if *interaction == Interaction::Clicked && !loading {
             match button {
-                MenuButton::Play => state
-                    .set(GameState::InGame)
-                    .expect("Couldn't switch state to InGame"),
+                MenuButton::Play => new_game(&mut state, netplay_config.is_some()),
+                MenuButton::Arcade(mode) => {
+                    commands.insert_resource(Arcade::new(*mode));
+                    state
+                        .set(GameState::CharacterSelect)
+                        .expect("Couldn't switch state to CharacterSelect");
+                }
+                MenuButton::Host => {
+                    *lobby = Lobby::new(LobbyMode::Host, netplay_options.relay.is_some());
+                    state
+                        .set(GameState::Lobby)
+                        .expect("Couldn't switch state to Lobby");
+                }
+                MenuButton::Join => {
+                    *lobby = Lobby::new(LobbyMode::Join, netplay_options.relay.is_some());
+                    state
+                        .set(GameState::Lobby)
+                        .expect("Couldn't switch state to Lobby");
+                }
+                MenuButton::Watch => {
+                    if let Some(host) = netplay_options.watch {
+                        commands.insert_resource(Spectating { host });
+                        state
+                            .set(GameState::InGame)
+                            .expect("Couldn't switch state to InGame");
+                    }
+                }
                 MenuButton::Quit => exit.send(AppExit),
             };
         }
     }
 }
 
+/// Start a new game. Playing directly against a peer uses the default characters since there is
+/// no lobby to agree on them.
+fn new_game(state: &mut State<GameState>, direct: bool) {
+    if direct {
+        state
+            .set(GameState::InGame)
+            .expect("Couldn't switch state to InGame");
+    } else {
+        state
+            .set(GameState::CharacterSelect)
+            .expect("Couldn't switch state to CharacterSelect");
+    }
+}
+
 /// Handle keyboard input.
 fn input_system(
     mut keyboard_input: ResMut<Input<KeyCode>>,
     mut state: ResMut<State<GameState>>,
     mut exit: EventWriter<AppExit>,
+    netplay_config: Option<Res<NetplayConfig>>,
+    stages: Option<Res<Stages>>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Return) {
-        state
-            .set(GameState::InGame)
-            .expect("Couldn't switch state to InGame");
+    // Wait for the stages like the menu buttons do.
+    if keyboard_input.just_pressed(KeyCode::Return) && stages.is_some() {
+        new_game(&mut state, netplay_config.is_some());
         keyboard_input.clear_just_pressed(KeyCode::Return);
     } else if cfg!(feature = "desktop") && keyboard_input.just_pressed(KeyCode::Escape) {
         exit.send(AppExit);
@@ -0,0 +1,198 @@
+//! Match Settings
+
+use crate::Character;
+use bevy::prelude::*;
+
+/// Round time used for rounds without a time limit.
+const INFINITE_ROUND_TIME: u16 = 0;
+
+/// Round time choices in seconds.
+const ROUND_TIMES: [u16; 4] = [30, 60, 99, INFINITE_ROUND_TIME];
+
+/// How the winner is decided when time runs out.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum TimeOver {
+    /// Player with the most health left, relative to their character's maximum, wins.
+    Health,
+
+    /// Decided by health but a tie goes to sudden death where the next hit wins.
+    SuddenDeath,
+
+    /// Judges award the round to the player who dealt the most damage.
+    Judges,
+}
+
+impl TimeOver {
+    /// All time over rules in the order used for encoding.
+    const ALL: [TimeOver; 3] = [Self::Health, Self::SuddenDeath, Self::Judges];
+
+    fn to_u8(self) -> u8 {
+        Self::ALL
+            .iter()
+            .position(|t| *t == self)
+            .unwrap_or_default() as u8
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Health => "HEALTH",
+            Self::SuddenDeath => "SUDDEN DEATH",
+            Self::Judges => "JUDGES",
+        }
+    }
+}
+
+/// Settings agreed on before a match starts. For online matches these are chosen by the host.
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub(crate) struct MatchSettings {
+    /// Starting value for the countdown timer.
+    pub(crate) round_time: u16,
+
+    /// How the winner is decided when time runs out.
+    pub(crate) time_over: TimeOver,
+
+    /// Fighters picked by each player.
+    pub(crate) characters: [Character; 2],
+
+    /// Palettes picked by each player.
+    pub(crate) palettes: [u8; 2],
+
+    /// Index of the stage to fight on.
+    pub(crate) stage: u8,
+
+    /// Difficulty of the CPU controlling player two. Not sent to the peer since online matches
+    /// are always between two people.
+    pub(crate) cpu: Option<u8>,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        Self {
+            round_time: ROUND_TIMES[0],
+            time_over: TimeOver::Health,
+            characters: [Character::Mack, Character::Kenji],
+            palettes: [0, 0],
+            stage: 0,
+            cpu: None,
+        }
+    }
+}
+
+impl MatchSettings {
+    /// Cycle through round time choices.
+    pub(crate) fn cycle_round_time(&mut self, forward: bool) {
+        let i = ROUND_TIMES
+            .iter()
+            .position(|t| *t == self.round_time)
+            .unwrap_or(0);
+        let n = ROUND_TIMES.len();
+        self.round_time = ROUND_TIMES[if forward {
+            (i + 1) % n
+        } else {
+            (i + n - 1) % n
+        }];
+    }
+
+    /// Cycle through time over rules.
+    pub(crate) fn cycle_time_over(&mut self, forward: bool) {
+        let i = TimeOver::ALL
+            .iter()
+            .position(|t| *t == self.time_over)
+            .unwrap_or(0);
+        let n = TimeOver::ALL.len();
+        self.time_over = TimeOver::ALL[if forward {
+            (i + 1) % n
+        } else {
+            (i + n - 1) % n
+        }];
+    }
+
+    /// Returns true if rounds have no time limit.
+    pub(crate) fn infinite_time(&self) -> bool {
+        self.round_time == INFINITE_ROUND_TIME
+    }
+
+    /// Round time as shown in menus.
+    pub(crate) fn round_time_text(&self) -> String {
+        if self.infinite_time() {
+            "INFINITE".to_string()
+        } else {
+            format!("{}", self.round_time)
+        }
+    }
+
+    /// Palettes the players are drawn with. When both picked the same character and palette,
+    /// player two switches to the next palette so the players can always be told apart.
+    pub(crate) fn match_palettes(&self) -> [u8; 2] {
+        let [p1, mut p2] = self.palettes;
+        let [c1, c2] = self.characters;
+        if c1 == c2 && c1.palette(p1) == c2.palette(p2) {
+            p2 = c2.cycle_palette(p1, true);
+        }
+        [p1, p2]
+    }
+
+    /// Encode the settings for sending to the peer.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.round_time.to_le_bytes().to_vec();
+        bytes.extend(self.characters.iter().map(|c| c.index() as u8));
+        bytes.extend(self.palettes);
+        bytes.push(self.stage);
+        bytes.push(self.time_over.to_u8());
+        bytes
+    }
+
+    /// Decode settings received from the peer.
+    pub(crate) fn decode(bytes: &[u8]) -> Option<Self> {
+        let round_time = u16::from_le_bytes(bytes.get(0..2)?.try_into().ok()?);
+        let characters = [
+            Character::from_index(*bytes.get(2)? as usize)?,
+            Character::from_index(*bytes.get(3)? as usize)?,
+        ];
+        let palettes = [*bytes.get(4)?, *bytes.get(5)?];
+        let stage = *bytes.get(6)?;
+        let time_over = TimeOver::from_u8(*bytes.get(7)?)?;
+        Some(Self {
+            round_time,
+            time_over,
+            characters,
+            palettes,
+            stage,
+            cpu: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_round_time_survives_encoding() {
+        let mut settings = MatchSettings::default();
+        for _ in 0..ROUND_TIMES.len() {
+            assert_eq!(
+                MatchSettings::decode(&settings.encode()),
+                Some(settings.clone())
+            );
+            settings.cycle_round_time(true);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_characters() {
+        let mut bytes = MatchSettings::default().encode();
+        bytes[3] = Character::ALL.len() as u8;
+        assert_eq!(MatchSettings::decode(&bytes), None);
+    }
+
+    #[test]
+    fn round_time_needs_both_bytes() {
+        let bytes = MatchSettings::default().encode();
+        assert_eq!(MatchSettings::decode(&bytes[..1]), None);
+    }
+}
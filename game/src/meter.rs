@@ -0,0 +1,184 @@
+//! Super Meter
+
+use crate::{common::*, GameState, StateReader, StateWriter};
+use bevy::{prelude::*, render::view::RenderLayers};
+
+/// Meter needed for a super attack.
+pub(crate) const MAX_METER: u8 = 100;
+
+/// Meter spent on an enhanced attack.
+pub(crate) const ENHANCED_COST: u8 = 50;
+
+/// Number of ticks the fight freezes for when a super attack starts.
+pub(crate) const SUPER_FREEZE_TICKS: u8 = 45;
+
+/// Color of the flash shown when a super attack starts.
+const SUPER_FLASH_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+
+/// Handles the super meter.
+pub(crate) struct MeterPlugin;
+
+impl Plugin for MeterPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SuperMeters>()
+            .add_event::<SuperEvent>()
+            .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(setup))
+            .add_system_set(SystemSet::on_update(GameState::InGame).with_system(flash_system))
+            .add_system_set(SystemSet::on_exit(GameState::InGame).with_system(cleanup));
+    }
+}
+
+/// Strength of an attack.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum AttackPower {
+    #[default]
+    Normal,
+    Enhanced,
+    Super,
+}
+
+impl AttackPower {
+    /// All attack powers in the order used for encoding.
+    const ALL: [AttackPower; 3] = [Self::Normal, Self::Enhanced, Self::Super];
+
+    fn to_u8(self) -> u8 {
+        Self::ALL
+            .iter()
+            .position(|p| *p == self)
+            .unwrap_or_default() as u8
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+
+    /// Damage multiplier.
+    pub(crate) fn damage_multiplier(&self) -> u8 {
+        match self {
+            Self::Normal => 1,
+            Self::Enhanced => 2,
+            Self::Super => 3,
+        }
+    }
+}
+
+/// Super meter of each player and the strength of the attacks they are making. The meter is kept
+/// between rounds until returning to the main menu.
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct SuperMeters {
+    pub(crate) meters: [u8; 2],
+    pub(crate) attacks: [AttackPower; 2],
+}
+
+impl SuperMeters {
+    /// Fill the meters when an attack connects. Dealing damage fills the meter faster than
+    /// receiving it.
+    pub(crate) fn gain(&mut self, attacker: usize, defender: usize, damage: u8) {
+        self.meters[attacker] = self.meters[attacker].saturating_add(damage).min(MAX_METER);
+        self.meters[defender] = self.meters[defender]
+            .saturating_add(damage / 2)
+            .min(MAX_METER);
+    }
+
+    /// Spend meter on the strongest attack it's enough for. Returns `None` if there isn't enough.
+    pub(crate) fn spend(&mut self, player: usize) -> Option<AttackPower> {
+        let meter = &mut self.meters[player];
+        if *meter >= MAX_METER {
+            *meter = 0;
+            Some(AttackPower::Super)
+        } else if *meter >= ENHANCED_COST {
+            *meter -= ENHANCED_COST;
+            Some(AttackPower::Enhanced)
+        } else {
+            None
+        }
+    }
+
+    /// Encode the meters.
+    pub(crate) fn encode(&self, writer: &mut StateWriter) {
+        for (meter, attack) in self.meters.iter().zip(self.attacks.iter()) {
+            writer.u8(*meter);
+            writer.u8(attack.to_u8());
+        }
+    }
+
+    /// Decode meters.
+    pub(crate) fn decode(reader: &mut StateReader) -> Option<Self> {
+        let mut meters = Self::default();
+        for i in 0..2 {
+            meters.meters[i] = reader.u8()?.min(MAX_METER);
+            meters.attacks[i] = AttackPower::from_u8(reader.u8()?)?;
+        }
+        Some(meters)
+    }
+}
+
+/// Sent when a super attack starts. Not sent while resimulating ticks.
+pub(crate) struct SuperEvent;
+
+/// Super meter entities.
+#[derive(Resource)]
+struct EntityData {
+    entities: Vec<Entity>,
+}
+
+/// Flash shown while the fight is frozen for a super attack.
+#[derive(Component)]
+struct SuperFlash(Timer);
+
+/// Start a round with the meter left over from the last one.
+fn setup(mut commands: Commands, mut super_meters: ResMut<SuperMeters>) {
+    super_meters.attacks = [AttackPower::Normal; 2];
+
+    let mut entities: Vec<Entity> = Vec::new();
+
+    let mut timer = Timer::from_seconds(
+        SUPER_FREEZE_TICKS as f32 * TICK_DURATION.as_secs_f32(),
+        TimerMode::Once,
+    );
+    timer.tick(timer.duration());
+
+    entities.push(
+        commands
+            .spawn(SpriteBundle {
+                sprite: Sprite {
+                    color: Color::NONE,
+                    custom_size: Some(Vec2::new(VIEW_WIDTH, VIEW_HEIGHT)),
+                    ..default()
+                },
+                transform: Transform::from_xyz(0.0, 0.0, FLASH_Z),
+                ..default()
+            })
+            .insert(SuperFlash(timer))
+            .insert(RenderLayers::layer(HUD_LAYER))
+            .id(),
+    );
+
+    commands.insert_resource(EntityData { entities });
+}
+
+/// Darken the screen while the fight is frozen for a super attack.
+fn flash_system(
+    time: Res<Time>,
+    mut super_events: EventReader<SuperEvent>,
+    mut flash_query: Query<(&mut SuperFlash, &mut Sprite)>,
+) {
+    let started = super_events.iter().count() > 0;
+
+    for (mut flash, mut sprite) in &mut flash_query {
+        if started {
+            flash.0.reset();
+        }
+        flash.0.tick(time.delta());
+        let remaining = 1.0 - flash.0.percent();
+        sprite.color = SUPER_FLASH_COLOR;
+        sprite.color.set_a(SUPER_FLASH_COLOR.a() * remaining);
+    }
+}
+
+/// Cleanup resources.
+fn cleanup(mut commands: Commands, entity_data: Res<EntityData>) {
+    for entity in entity_data.entities.iter() {
+        commands.entity(*entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,807 @@
+//! Netplay
+
+use crate::{
+    Character, LinkConditioner, LinkConditions, Player, PlayerInput, RelayTransport, Transport,
+    UdpTransport,
+};
+use bevy::{prelude::*, utils::Instant};
+use std::{io, net::SocketAddr, time::Duration};
+
+/// Identifies packets sent by this game.
+const PACKET_MAGIC: u8 = 0xF1;
+
+/// Number of ticks of input history kept. Must be larger than the input delay plus the
+/// maximum prediction.
+const INPUT_HISTORY: usize = 128;
+
+/// Maximum number of inputs sent in a single packet.
+pub(crate) const MAX_INPUTS_PER_PACKET: u32 = 64;
+
+/// Maximum number of ticks we simulate ahead of the last input received from the peer.
+pub(crate) const MAX_PREDICTION: u32 = 8;
+
+/// Default number of ticks local input is delayed by to hide some of the latency.
+const DEFAULT_INPUT_DELAY: u32 = 2;
+
+/// How often hello packets are sent while waiting for the peer.
+const HELLO_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the round trip time to the peer is measured.
+const PING_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Peer is considered disconnected if nothing is received for this long.
+pub(crate) const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Give up on a match if the peer doesn't show up within this time.
+pub(crate) const SYNCHRONIZE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Relay server used by the browser unless another one is given.
+const DEFAULT_WEB_RELAY: &str = "ws://localhost:7100";
+
+/// Netplay options given on the command line.
+#[derive(Resource, Clone, Debug)]
+pub struct NetplayOptions {
+    /// Play directly against a peer without going through the lobby.
+    pub(crate) direct: Option<DirectPeer>,
+
+    /// Relay server to connect through instead of connecting to the peer. This is the UDP
+    /// address of the relay on desktop and its WebSocket URL in the browser.
+    pub(crate) relay: Option<String>,
+
+    /// UDP port spectators can connect to while a match is played.
+    pub(crate) spectate_port: Option<u16>,
+
+    /// Address of a match to watch as a spectator.
+    pub(crate) watch: Option<SocketAddr>,
+
+    /// Number of ticks local input is delayed by.
+    pub(crate) input_delay: u32,
+
+    /// Artificial network conditions for testing.
+    pub(crate) link_conditions: LinkConditions,
+}
+
+/// Peer to play against when skipping the lobby.
+#[derive(Clone, Debug)]
+pub(crate) struct DirectPeer {
+    /// Player controlled by this peer.
+    pub(crate) local_player: Player,
+
+    /// UDP port to listen on.
+    pub(crate) local_port: u16,
+
+    /// Address of the other peer.
+    pub(crate) remote_addr: SocketAddr,
+}
+
+impl Default for NetplayOptions {
+    fn default() -> Self {
+        Self {
+            direct: None,
+            // Browsers can't use UDP so they always go through a relay.
+            relay: if cfg!(target_arch = "wasm32") {
+                Some(DEFAULT_WEB_RELAY.to_string())
+            } else {
+                None
+            },
+            spectate_port: None,
+            watch: None,
+            input_delay: DEFAULT_INPUT_DELAY,
+            link_conditions: LinkConditions::default(),
+        }
+    }
+}
+
+impl NetplayOptions {
+    /// Options for playing online through the given relay server.
+    pub fn with_relay(relay: String) -> Self {
+        Self {
+            relay: Some(relay),
+            ..default()
+        }
+    }
+
+    /// Parse netplay options from command line arguments.
+    ///
+    /// `[--player <1|2> --local-port <port> --remote <addr:port>] [--relay <addr:port>]
+    /// [--spectate-port <port>] [--watch <addr:port>] [--input-delay <ticks>] [--latency <ms>]
+    /// [--jitter <ms>] [--packet-loss <percent>]`
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut local_player = None;
+        let mut local_port = None;
+        let mut remote_addr = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+
+            match arg.as_str() {
+                "--player" => {
+                    local_player = match value()?.as_str() {
+                        "1" => Some(Player::One),
+                        "2" => Some(Player::Two),
+                        v => return Err(format!("Invalid player {}, expected 1 or 2", v)),
+                    }
+                }
+                "--local-port" => local_port = Some(parse(&arg, &value()?)?),
+                "--remote" => remote_addr = Some(parse(&arg, &value()?)?),
+                "--relay" => options.relay = Some(value()?),
+                "--spectate-port" => options.spectate_port = Some(parse(&arg, &value()?)?),
+                "--watch" => options.watch = Some(parse(&arg, &value()?)?),
+                "--input-delay" => options.input_delay = parse(&arg, &value()?)?,
+                "--latency" => {
+                    options.link_conditions.latency = Duration::from_millis(parse(&arg, &value()?)?)
+                }
+                "--jitter" => {
+                    options.link_conditions.jitter = Duration::from_millis(parse(&arg, &value()?)?)
+                }
+                "--packet-loss" => {
+                    let percent: f32 = parse(&arg, &value()?)?;
+                    options.link_conditions.packet_loss = (percent / 100.0).clamp(0.0, 1.0);
+                }
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+
+        options.direct = match (local_player, local_port, remote_addr) {
+            (None, None, None) => None,
+            (Some(local_player), Some(local_port), Some(remote_addr)) => Some(DirectPeer {
+                local_player,
+                local_port,
+                remote_addr,
+            }),
+            _ => return Err("Direct play needs --player, --local-port and --remote".to_string()),
+        };
+
+        // Inputs for the delay are kept with the ones waiting on the peer.
+        if options.input_delay > MAX_PREDICTION {
+            return Err(format!(
+                "Input delay can't be more than {} ticks",
+                MAX_PREDICTION
+            ));
+        }
+
+        if options.direct.is_some() && options.watch.is_some() {
+            return Err("Can't watch a match while playing directly".to_string());
+        }
+
+        Ok(options)
+    }
+
+    /// Apply the artificial network conditions (if any) to a transport.
+    pub(crate) fn condition<T: Transport + 'static>(
+        &self,
+        transport: T,
+        seed: u32,
+    ) -> Box<dyn Transport> {
+        if self.link_conditions.is_ideal() {
+            Box::new(transport)
+        } else {
+            Box::new(LinkConditioner::new(transport, self.link_conditions, seed))
+        }
+    }
+
+    /// Connect to the relay server and join a room.
+    pub(crate) fn connect_relay(&self, room: &str) -> Result<Box<dyn Transport>, String> {
+        let relay = self.relay.as_deref().ok_or("No relay server")?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use std::net::ToSocketAddrs;
+
+            let relay_addr = relay
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .ok_or_else(|| format!("Invalid relay address {}", relay))?;
+            let udp = UdpTransport::bind(0, relay_addr).map_err(|e| e.to_string())?;
+            Ok(self.condition(RelayTransport::new(udp, room), relay_addr.port() as u32))
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let websocket = crate::WebSocketTransport::connect(relay)?;
+            Ok(self.condition(RelayTransport::new(websocket, room), room.len() as u32))
+        }
+    }
+
+    /// Open the socket used to talk to the direct peer.
+    pub(crate) fn bind_direct(&self) -> Option<io::Result<Box<dyn Transport>>> {
+        self.direct.as_ref().map(|direct| {
+            UdpTransport::bind(direct.local_port, direct.remote_addr)
+                .map(|udp| self.condition(udp, direct.local_port as u32))
+        })
+    }
+}
+
+/// Parse a command line option value.
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {} for {}", value, arg))
+}
+
+/// Settings for the online match about to be played. Only present while playing online.
+#[derive(Resource, Clone, Debug)]
+pub(crate) struct NetplayConfig {
+    /// Player controlled by this peer.
+    pub(crate) local_player: Player,
+
+    /// Number of ticks local input is delayed by.
+    pub(crate) input_delay: u32,
+
+    /// Identifies the match so packets from a previous match are ignored.
+    pub(crate) match_id: u8,
+
+    /// True if the match was set up in the lobby and should return there afterwards.
+    pub(crate) from_lobby: bool,
+}
+
+/// Connection to the peer, kept between matches.
+#[derive(Resource)]
+pub(crate) struct NetTransport(pub(crate) Box<dyn Transport>);
+
+/// Ring buffer of inputs indexed by tick.
+struct InputHistory {
+    inputs: Vec<Option<(u32, PlayerInput)>>,
+}
+
+impl InputHistory {
+    fn new() -> Self {
+        Self {
+            inputs: vec![None; INPUT_HISTORY],
+        }
+    }
+
+    fn get(&self, tick: u32) -> Option<PlayerInput> {
+        match self.inputs[tick as usize % INPUT_HISTORY] {
+            Some((t, input)) if t == tick => Some(input),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, tick: u32, input: PlayerInput) {
+        self.inputs[tick as usize % INPUT_HISTORY] = Some((tick, input));
+    }
+}
+
+/// Messages exchanged between peers.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Message {
+    /// Sent until both peers are ready to start the match.
+    Hello { match_id: u8, synchronized: bool },
+
+    /// Inputs for consecutive ticks starting at `start_tick`. `ack` is the next tick the sender
+    /// expects from the receiver.
+    Input {
+        match_id: u8,
+        ack: u32,
+        start_tick: u32,
+        inputs: Vec<PlayerInput>,
+    },
+
+    /// Round trip time measurement. The receiver sends back the time in a `Pong`.
+    Ping { time: u32 },
+
+    /// Answer to a `Ping`.
+    Pong { time: u32 },
+
+    /// Sent by a guest to join a hosted match. `stages` is the checksum of the guest's stages.
+    Join { stages: u32 },
+
+    /// Sent by a host to a guest whose stages don't match its own.
+    StageMismatch,
+
+    /// Match settings chosen by the host.
+    Settings { settings: Vec<u8> },
+
+    /// Host starts a match.
+    Start { match_id: u8, settings: Vec<u8> },
+
+    /// Peer is leaving.
+    Leave,
+
+    /// Sent by a spectator to watch a match. `match_id` is `None` until the spectator has
+    /// received a snapshot of the match. `next_tick` acknowledges inputs received so far.
+    Watch {
+        match_id: Option<u8>,
+        next_tick: u32,
+    },
+
+    /// Encoded fight state for a spectator joining a match.
+    Snapshot { match_id: u8, state: Vec<u8> },
+
+    /// Confirmed inputs of both players for consecutive ticks starting at `start_tick`, sent to
+    /// spectators.
+    Spectate {
+        match_id: u8,
+        start_tick: u32,
+        inputs: Vec<[PlayerInput; 2]>,
+    },
+
+    /// Character and palette picked by the guest in the lobby.
+    Pick { character: Character, palette: u8 },
+}
+
+impl Message {
+    const HELLO: u8 = 0;
+    const INPUT: u8 = 1;
+    const PING: u8 = 2;
+    const PONG: u8 = 3;
+    const JOIN: u8 = 4;
+    const SETTINGS: u8 = 5;
+    const START: u8 = 6;
+    const LEAVE: u8 = 7;
+    const WATCH: u8 = 8;
+    const SNAPSHOT: u8 = 9;
+    const SPECTATE: u8 = 10;
+    const PICK: u8 = 11;
+    const STAGE_MISMATCH: u8 = 12;
+
+    /// Encode the message.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![PACKET_MAGIC];
+        match self {
+            Self::Hello {
+                match_id,
+                synchronized,
+            } => {
+                buf.extend([Self::HELLO, *match_id, *synchronized as u8]);
+            }
+            Self::Input {
+                match_id,
+                ack,
+                start_tick,
+                inputs,
+            } => {
+                buf.extend([Self::INPUT, *match_id]);
+                buf.extend_from_slice(&ack.to_le_bytes());
+                buf.extend_from_slice(&start_tick.to_le_bytes());
+                buf.push(inputs.len() as u8);
+                buf.extend(inputs.iter().map(|input| input.0));
+            }
+            Self::Ping { time } => {
+                buf.push(Self::PING);
+                buf.extend_from_slice(&time.to_le_bytes());
+            }
+            Self::Pong { time } => {
+                buf.push(Self::PONG);
+                buf.extend_from_slice(&time.to_le_bytes());
+            }
+            Self::Join { stages } => {
+                buf.push(Self::JOIN);
+                buf.extend_from_slice(&stages.to_le_bytes());
+            }
+            Self::StageMismatch => buf.push(Self::STAGE_MISMATCH),
+            Self::Settings { settings } => {
+                buf.push(Self::SETTINGS);
+                buf.extend_from_slice(settings);
+            }
+            Self::Start { match_id, settings } => {
+                buf.extend([Self::START, *match_id]);
+                buf.extend_from_slice(settings);
+            }
+            Self::Leave => buf.push(Self::LEAVE),
+            Self::Watch {
+                match_id,
+                next_tick,
+            } => {
+                buf.extend([
+                    Self::WATCH,
+                    match_id.is_some() as u8,
+                    match_id.unwrap_or_default(),
+                ]);
+                buf.extend_from_slice(&next_tick.to_le_bytes());
+            }
+            Self::Snapshot { match_id, state } => {
+                buf.extend([Self::SNAPSHOT, *match_id]);
+                buf.extend_from_slice(state);
+            }
+            Self::Spectate {
+                match_id,
+                start_tick,
+                inputs,
+            } => {
+                buf.extend([Self::SPECTATE, *match_id]);
+                buf.extend_from_slice(&start_tick.to_le_bytes());
+                buf.push(inputs.len() as u8);
+                buf.extend(inputs.iter().flat_map(|[p1, p2]| [p1.0, p2.0]));
+            }
+            Self::Pick { character, palette } => {
+                buf.extend([Self::PICK, character.index() as u8, *palette]);
+            }
+        }
+        buf
+    }
+
+    /// Decode a message. Returns `None` for malformed packets.
+    pub(crate) fn decode(packet: &[u8]) -> Option<Self> {
+        let u32_at = |bytes: &[u8], i: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(bytes.get(i..i + 4)?.try_into().ok()?))
+        };
+
+        match packet {
+            [PACKET_MAGIC, Self::HELLO, match_id, synchronized] => Some(Self::Hello {
+                match_id: *match_id,
+                synchronized: *synchronized != 0,
+            }),
+            [PACKET_MAGIC, Self::INPUT, match_id, rest @ ..] => {
+                let ack = u32_at(rest, 0)?;
+                let start_tick = u32_at(rest, 4)?;
+                let count = *rest.get(8)? as usize;
+                let inputs = rest.get(9..9 + count)?;
+                Some(Self::Input {
+                    match_id: *match_id,
+                    ack,
+                    start_tick,
+                    inputs: inputs.iter().map(|i| PlayerInput(*i)).collect(),
+                })
+            }
+            [PACKET_MAGIC, Self::PING, rest @ ..] => Some(Self::Ping {
+                time: u32_at(rest, 0)?,
+            }),
+            [PACKET_MAGIC, Self::PONG, rest @ ..] => Some(Self::Pong {
+                time: u32_at(rest, 0)?,
+            }),
+            [PACKET_MAGIC, Self::JOIN, rest @ ..] => Some(Self::Join {
+                stages: u32_at(rest, 0)?,
+            }),
+            [PACKET_MAGIC, Self::STAGE_MISMATCH] => Some(Self::StageMismatch),
+            [PACKET_MAGIC, Self::SETTINGS, settings @ ..] => Some(Self::Settings {
+                settings: settings.to_vec(),
+            }),
+            [PACKET_MAGIC, Self::START, match_id, settings @ ..] => Some(Self::Start {
+                match_id: *match_id,
+                settings: settings.to_vec(),
+            }),
+            [PACKET_MAGIC, Self::LEAVE] => Some(Self::Leave),
+            [PACKET_MAGIC, Self::WATCH, has_match_id, match_id, rest @ ..] => Some(Self::Watch {
+                match_id: (*has_match_id != 0).then_some(*match_id),
+                next_tick: u32_at(rest, 0)?,
+            }),
+            [PACKET_MAGIC, Self::SNAPSHOT, match_id, state @ ..] => Some(Self::Snapshot {
+                match_id: *match_id,
+                state: state.to_vec(),
+            }),
+            [PACKET_MAGIC, Self::SPECTATE, match_id, rest @ ..] => {
+                let start_tick = u32_at(rest, 0)?;
+                let count = *rest.get(4)? as usize;
+                let inputs = rest.get(5..5 + count * 2)?;
+                Some(Self::Spectate {
+                    match_id: *match_id,
+                    start_tick,
+                    inputs: inputs
+                        .chunks(2)
+                        .map(|i| [PlayerInput(i[0]), PlayerInput(i[1])])
+                        .collect(),
+                })
+            }
+            [PACKET_MAGIC, Self::PICK, character, palette] => Some(Self::Pick {
+                character: Character::from_index(*character as usize)?,
+                palette: *palette,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Measures round trip time to the peer.
+pub(crate) struct PingTracker {
+    epoch: Instant,
+    last_ping: Option<Instant>,
+    last_received: Instant,
+    rtt: Option<Duration>,
+}
+
+impl PingTracker {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            epoch: now,
+            last_ping: None,
+            last_received: now,
+            rtt: None,
+        }
+    }
+
+    /// Returns a ping message if it is time to send one.
+    pub(crate) fn ping(&mut self) -> Option<Message> {
+        let now = Instant::now();
+        if self
+            .last_ping
+            .is_none_or(|t| now.duration_since(t) >= PING_INTERVAL)
+        {
+            self.last_ping = Some(now);
+            Some(Message::Ping {
+                time: now.duration_since(self.epoch).as_millis() as u32,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Record that a packet was received from the peer.
+    pub(crate) fn received(&mut self) {
+        self.last_received = Instant::now();
+    }
+
+    /// Record the answer to one of our pings.
+    pub(crate) fn pong(&mut self, time: u32) {
+        let sent = Duration::from_millis(time as u64);
+        self.rtt = Instant::now().duration_since(self.epoch).checked_sub(sent);
+    }
+
+    /// Last measured round trip time.
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Time since anything was received from the peer.
+    pub(crate) fn silence(&self) -> Duration {
+        Instant::now().duration_since(self.last_received)
+    }
+}
+
+/// Network statistics for display.
+#[derive(Resource, Default, Debug)]
+pub(crate) struct NetStats {
+    /// True once both peers have started the match.
+    pub(crate) synchronized: bool,
+
+    /// Last measured round trip time to the peer.
+    pub(crate) ping: Option<Duration>,
+
+    /// Number of ticks simulated ahead of the peer's confirmed input.
+    pub(crate) prediction: u32,
+
+    /// Number of rollbacks performed this match.
+    pub(crate) rollbacks: u32,
+
+    /// True if the peer left or stopped responding.
+    pub(crate) disconnected: bool,
+}
+
+/// State of an online match. Exchanges inputs with the peer and predicts the peer's input for
+/// ticks that haven't been received yet.
+pub(crate) struct NetSession {
+    transport: Box<dyn Transport>,
+    local_player: Player,
+    input_delay: u32,
+    match_id: u8,
+    synchronized: bool,
+    started_at: Instant,
+    last_hello: Option<Instant>,
+    ping: PingTracker,
+    left: bool,
+    local_inputs: InputHistory,
+    remote_inputs: InputHistory,
+    predicted_inputs: InputHistory,
+    /// Next tick of local input to be added.
+    next_local_tick: u32,
+    /// Next tick of remote input expected. All earlier ticks have been received.
+    next_remote_tick: u32,
+    /// Next tick of local input the peer expects.
+    remote_ack: u32,
+    /// Earliest tick that was simulated with a wrong prediction.
+    rollback_tick: Option<u32>,
+}
+
+impl NetSession {
+    /// Start a new match over the given transport.
+    pub(crate) fn new(transport: Box<dyn Transport>, config: &NetplayConfig) -> Self {
+        let input_delay = config.input_delay;
+        let mut local_inputs = InputHistory::new();
+        // Nothing is pressed during the input delay at the start of the match.
+        for tick in 0..input_delay {
+            local_inputs.set(tick, PlayerInput::default());
+        }
+
+        Self {
+            transport,
+            local_player: config.local_player,
+            input_delay,
+            match_id: config.match_id,
+            synchronized: false,
+            started_at: Instant::now(),
+            last_hello: None,
+            ping: PingTracker::new(),
+            left: false,
+            local_inputs,
+            remote_inputs: InputHistory::new(),
+            predicted_inputs: InputHistory::new(),
+            next_local_tick: input_delay,
+            next_remote_tick: 0,
+            remote_ack: 0,
+            rollback_tick: None,
+        }
+    }
+
+    /// Ends the match and hands back the transport for the next one.
+    pub(crate) fn into_transport(self) -> Box<dyn Transport> {
+        self.transport
+    }
+
+    /// Player controlled by this peer.
+    pub(crate) fn local_player(&self) -> Player {
+        self.local_player
+    }
+
+    /// Returns true once both peers are ready to start the match.
+    pub(crate) fn is_synchronized(&self) -> bool {
+        self.synchronized
+    }
+
+    /// Returns true if the peer left or stopped responding.
+    pub(crate) fn is_disconnected(&self) -> bool {
+        if self.left {
+            true
+        } else if self.synchronized {
+            self.ping.silence() > DISCONNECT_TIMEOUT
+        } else {
+            Instant::now().duration_since(self.started_at) > SYNCHRONIZE_TIMEOUT
+        }
+    }
+
+    /// Last measured round trip time to the peer.
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        self.ping.rtt()
+    }
+
+    /// All ticks before this one have been simulated with confirmed inputs from both peers
+    /// (once any pending rollback is done).
+    pub(crate) fn confirmed_tick(&self) -> u32 {
+        self.next_remote_tick
+    }
+
+    /// Process packets received from the peer.
+    pub(crate) fn poll(&mut self) {
+        while let Some(packet) = self.transport.receive() {
+            let message = match Message::decode(&packet) {
+                Some(message) => message,
+                None => continue,
+            };
+            self.ping.received();
+
+            match message {
+                Message::Hello {
+                    match_id,
+                    synchronized,
+                } if match_id == self.match_id => {
+                    if !self.synchronized {
+                        info!("Connected to peer");
+                    }
+                    self.synchronized = true;
+                    if !synchronized {
+                        self.send(Message::Hello {
+                            match_id,
+                            synchronized: true,
+                        });
+                    }
+                }
+                Message::Input {
+                    match_id,
+                    ack,
+                    start_tick,
+                    inputs,
+                } if match_id == self.match_id => {
+                    self.synchronized = true;
+                    self.remote_ack = self.remote_ack.max(ack);
+                    self.add_remote_inputs(start_tick, &inputs);
+                }
+                Message::Ping { time } => self.send(Message::Pong { time }),
+                Message::Pong { time } => self.ping.pong(time),
+                Message::Leave => self.left = true,
+                _ => (),
+            }
+        }
+
+        if self.synchronized {
+            if let Some(ping) = self.ping.ping() {
+                self.send(ping);
+            }
+        } else {
+            // Keep saying hello until the peer answers.
+            let now = Instant::now();
+            if self
+                .last_hello
+                .is_none_or(|t| now.duration_since(t) >= HELLO_INTERVAL)
+            {
+                self.last_hello = Some(now);
+                self.send(Message::Hello {
+                    match_id: self.match_id,
+                    synchronized: false,
+                });
+            }
+        }
+    }
+
+    /// Store inputs received from the peer and check them against our predictions.
+    fn add_remote_inputs(&mut self, start_tick: u32, inputs: &[PlayerInput]) {
+        for (i, input) in inputs.iter().enumerate() {
+            let tick = start_tick + i as u32;
+            if tick < self.next_remote_tick {
+                // Already have it (packets carry every input the peer hasn't seen acked).
+                continue;
+            } else if tick > self.next_remote_tick {
+                // Gap. Wait for a packet that fills it.
+                break;
+            }
+
+            self.remote_inputs.set(tick, *input);
+            self.next_remote_tick += 1;
+
+            if let Some(predicted) = self.predicted_inputs.get(tick) {
+                if predicted != *input {
+                    self.rollback_tick = Some(self.rollback_tick.map_or(tick, |t| t.min(tick)));
+                }
+            }
+        }
+    }
+
+    /// Add local input for the next tick and send all unacknowledged inputs to the peer.
+    /// `current_tick` is the tick about to be simulated.
+    pub(crate) fn add_local_input(&mut self, current_tick: u32, input: PlayerInput) {
+        while self.next_local_tick <= current_tick + self.input_delay {
+            self.local_inputs.set(self.next_local_tick, input);
+            self.next_local_tick += 1;
+        }
+        self.send_inputs();
+    }
+
+    /// Send all local inputs the peer hasn't acknowledged.
+    pub(crate) fn send_inputs(&mut self) {
+        let start_tick = self
+            .remote_ack
+            .max(self.next_local_tick.saturating_sub(MAX_INPUTS_PER_PACKET));
+        let inputs = (start_tick..self.next_local_tick)
+            .filter_map(|tick| self.local_inputs.get(tick))
+            .collect();
+        self.send(Message::Input {
+            match_id: self.match_id,
+            ack: self.next_remote_tick,
+            start_tick,
+            inputs,
+        });
+    }
+
+    /// Returns true if we are too far ahead of the peer and must wait for its inputs.
+    pub(crate) fn should_stall(&self, current_tick: u32) -> bool {
+        current_tick >= self.next_remote_tick + MAX_PREDICTION
+    }
+
+    /// Takes the earliest tick that needs to be resimulated.
+    pub(crate) fn take_rollback_tick(&mut self) -> Option<u32> {
+        self.rollback_tick.take()
+    }
+
+    /// Inputs of both players for a tick. Remote inputs that haven't been received are
+    /// predicted by repeating the last received input.
+    pub(crate) fn inputs(&mut self, tick: u32) -> [PlayerInput; 2] {
+        let local = self.local_inputs.get(tick).unwrap_or_default();
+        let remote = match self.remote_inputs.get(tick) {
+            Some(input) => input,
+            None => {
+                let predicted = self
+                    .next_remote_tick
+                    .checked_sub(1)
+                    .and_then(|t| self.remote_inputs.get(t))
+                    .unwrap_or_default();
+                self.predicted_inputs.set(tick, predicted);
+                predicted
+            }
+        };
+
+        let mut inputs = [PlayerInput::default(); 2];
+        inputs[self.local_player.index()] = local;
+        inputs[self.local_player.opponent().index()] = remote;
+        inputs
+    }
+
+    /// Send a message to the peer.
+    fn send(&mut self, message: Message) {
+        self.transport.send(&message.encode());
+    }
+}
@@ -0,0 +1,314 @@
+//! Network
+
+use bevy::{prelude::*, utils::Instant};
+use std::{
+    collections::VecDeque,
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+/// Largest datagram we expect to receive.
+pub(crate) const MAX_PACKET_SIZE: usize = 1024;
+
+/// First byte of the packet that joins a room on the relay server. Must match the relay.
+const RELAY_JOIN: u8 = 0xF2;
+
+/// How often the room is joined again. This also keeps UDP mappings through NATs alive.
+const RELAY_JOIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sends and receives unreliable, unordered packets to and from a single peer.
+pub(crate) trait Transport: Send + Sync {
+    /// Send a packet to the peer.
+    fn send(&mut self, packet: &[u8]);
+
+    /// Receive the next pending packet from the peer if there is one.
+    fn receive(&mut self) -> Option<Vec<u8>>;
+
+    /// Forget the current peer so a new one can connect. Only meaningful for hosts.
+    fn disconnect(&mut self) {}
+}
+
+/// Transport over a non-blocking UDP socket.
+pub(crate) struct UdpTransport {
+    socket: UdpSocket,
+    remote_addr: Option<SocketAddr>,
+    listening: bool,
+}
+
+impl UdpTransport {
+    /// Bind to the local port and exchange packets with the remote address.
+    pub(crate) fn bind(local_port: u16, remote_addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self {
+            socket: Self::open(local_port)?,
+            remote_addr: Some(remote_addr),
+            listening: false,
+        })
+    }
+
+    /// Bind to the local port and exchange packets with the first peer that sends one.
+    pub(crate) fn listen(local_port: u16) -> io::Result<Self> {
+        Ok(Self {
+            socket: Self::open(local_port)?,
+            remote_addr: None,
+            listening: true,
+        })
+    }
+
+    fn open(local_port: u16) -> io::Result<UdpSocket> {
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], local_port)))?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) {
+        let remote_addr = match self.remote_addr {
+            Some(remote_addr) => remote_addr,
+            None => return,
+        };
+
+        // Packets are unreliable anyway so a failed send is the same as a lost packet.
+        if let Err(err) = self.socket.send_to(packet, remote_addr) {
+            debug!("Couldn't send packet to {}: {}", remote_addr, err);
+        }
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let mut buf = [0_u8; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                // Hosts accept the first peer that talks to them.
+                Ok((len, addr)) if self.remote_addr.is_none() => {
+                    info!("Peer {} connected", addr);
+                    self.remote_addr = Some(addr);
+                    return Some(buf[..len].to_vec());
+                }
+                // Ignore anything that isn't from our peer.
+                Ok((len, addr)) if Some(addr) == self.remote_addr => {
+                    return Some(buf[..len].to_vec())
+                }
+                Ok(_) => continue,
+                // On some platforms ICMP port unreachable errors show up here while the peer
+                // hasn't bound its socket yet. Treat them like an empty socket.
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        if self.listening {
+            self.remote_addr = None;
+        }
+    }
+}
+
+/// Artificial network conditions used for testing online play.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LinkConditions {
+    /// One way delay added to every outgoing packet.
+    pub latency: Duration,
+
+    /// Maximum random delay added on top of the latency.
+    pub jitter: Duration,
+
+    /// Probability (0.0 to 1.0) of an outgoing packet being dropped.
+    pub packet_loss: f32,
+}
+
+impl LinkConditions {
+    /// Returns true if no conditions are applied.
+    pub(crate) fn is_ideal(&self) -> bool {
+        self.latency.is_zero() && self.jitter.is_zero() && self.packet_loss <= 0.0
+    }
+}
+
+/// Wraps a transport to delay and drop outgoing packets.
+pub(crate) struct LinkConditioner<T: Transport> {
+    transport: T,
+    conditions: LinkConditions,
+    queue: VecDeque<(Instant, Vec<u8>)>,
+    rng: u32,
+}
+
+impl<T: Transport> LinkConditioner<T> {
+    /// Create a link conditioner. Different seeds give different packet loss/jitter patterns.
+    pub(crate) fn new(transport: T, conditions: LinkConditions, seed: u32) -> Self {
+        Self {
+            transport,
+            conditions,
+            queue: VecDeque::new(),
+            rng: seed | 1, // xorshift gets stuck on zero.
+        }
+    }
+
+    /// Send queued packets whose delay has elapsed.
+    fn flush(&mut self) {
+        let now = Instant::now();
+        while let Some((release_at, _)) = self.queue.front() {
+            if *release_at > now {
+                break;
+            }
+            let (_, packet) = self.queue.pop_front().unwrap();
+            self.transport.send(&packet);
+        }
+    }
+
+    /// Returns a random number in [0, 1) using xorshift.
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+impl<T: Transport> Transport for LinkConditioner<T> {
+    fn send(&mut self, packet: &[u8]) {
+        if self.random() < self.conditions.packet_loss {
+            self.flush();
+            return;
+        }
+
+        let jitter = self.conditions.jitter.mul_f32(self.random());
+        let release_at = Instant::now() + self.conditions.latency + jitter;
+
+        // Keep the queue sorted by release time. Jitter can reorder packets, just like a real
+        // network would.
+        let index = self
+            .queue
+            .iter()
+            .position(|(t, _)| *t > release_at)
+            .unwrap_or(self.queue.len());
+        self.queue.insert(index, (release_at, packet.to_vec()));
+
+        self.flush();
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.flush();
+        self.transport.receive()
+    }
+
+    fn disconnect(&mut self) {
+        self.queue.clear();
+        self.transport.disconnect();
+    }
+}
+
+/// Wraps a transport connected to a relay server. The relay pairs up the two peers that join the
+/// same room and forwards packets between them.
+pub(crate) struct RelayTransport<T: Transport> {
+    transport: T,
+    join: Vec<u8>,
+    last_join: Option<Instant>,
+}
+
+impl<T: Transport> RelayTransport<T> {
+    pub(crate) fn new(transport: T, room: &str) -> Self {
+        let mut join = vec![RELAY_JOIN];
+        join.extend_from_slice(room.as_bytes());
+        Self {
+            transport,
+            join,
+            last_join: None,
+        }
+    }
+
+    /// (Re)join the room if it's time to.
+    fn join(&mut self) {
+        let now = Instant::now();
+        if self
+            .last_join
+            .is_none_or(|t| now.duration_since(t) >= RELAY_JOIN_INTERVAL)
+        {
+            self.last_join = Some(now);
+            self.transport.send(&self.join);
+        }
+    }
+}
+
+impl<T: Transport> Transport for RelayTransport<T> {
+    fn send(&mut self, packet: &[u8]) {
+        self.join();
+        self.transport.send(packet);
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.join();
+        self.transport.receive()
+    }
+
+    fn disconnect(&mut self) {
+        self.transport.disconnect();
+    }
+}
+
+/// Transport over a WebSocket for the browser, which can't use UDP. Only works through a relay.
+#[cfg(target_arch = "wasm32")]
+pub(crate) struct WebSocketTransport {
+    socket: web_sys::WebSocket,
+    received: std::rc::Rc<std::cell::RefCell<VecDeque<Vec<u8>>>>,
+    _on_message: wasm_bindgen::closure::Closure<dyn FnMut(web_sys::MessageEvent)>,
+}
+
+// Bevy runs on a single thread in the browser so the socket is never shared between threads.
+#[cfg(target_arch = "wasm32")]
+unsafe impl Send for WebSocketTransport {}
+#[cfg(target_arch = "wasm32")]
+unsafe impl Sync for WebSocketTransport {}
+
+#[cfg(target_arch = "wasm32")]
+impl WebSocketTransport {
+    /// Open a WebSocket to the given URL. The connection completes in the background and
+    /// packets sent before then are dropped.
+    pub(crate) fn connect(url: &str) -> Result<Self, String> {
+        use wasm_bindgen::{closure::Closure, JsCast};
+
+        let socket = web_sys::WebSocket::new(url).map_err(|e| format!("{:?}", e))?;
+        socket.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+        let received = std::rc::Rc::new(std::cell::RefCell::new(VecDeque::new()));
+        let queue = received.clone();
+        let on_message = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(
+            move |event: web_sys::MessageEvent| {
+                if let Ok(buf) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                    queue
+                        .borrow_mut()
+                        .push_back(js_sys::Uint8Array::new(&buf).to_vec());
+                }
+            },
+        );
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        Ok(Self {
+            socket,
+            received,
+            _on_message: on_message,
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Transport for WebSocketTransport {
+    fn send(&mut self, packet: &[u8]) {
+        if self.socket.ready_state() == web_sys::WebSocket::OPEN {
+            if let Err(err) = self.socket.send_with_u8_array(packet) {
+                debug!("Couldn't send packet: {:?}", err);
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.received.borrow_mut().pop_front()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for WebSocketTransport {
+    fn drop(&mut self) {
+        self.socket.set_onmessage(None);
+        let _ = self.socket.close();
+    }
+}
@@ -1,16 +1,28 @@
 //! Player
 
-use crate::{common::*, CountdownCompleteEvent, GameAssets, GameState, GROUND_Y};
+use crate::{
+    common::*, countdown_system, max_player_distance, projectile_system, spawn_projectile, Arcade,
+    AttackPower, Callout, CalloutEvent, Character, CountdownCompleteEvent, DamageDealt, Effect,
+    EffectEvent, FightClock, FightInputs, FightStage, Freeze, GameAssets, GameState, Keys,
+    KnockOut, MatchSettings, NetplayConfig, PlayerInput, Projectile, Resimulating, RollbackStage,
+    Stage, Stages, StateReader, StateWriter, SuperEvent, SuperMeters, SUPER_FREEZE_TICKS,
+};
 use bevy::{app::Plugin, prelude::*, sprite::collide_aabb::collide};
 use bevy_kira_audio::prelude::*;
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 /// Scaling factor for player sprite.
 const PLAYER_SCALE: f32 = 2.75;
 
 /// Starting frame for idle animation.
-const IDLE_FRAME_START: usize = 32;
+pub(crate) const IDLE_FRAME_START: usize = 32;
+
+/// Starting distance of each player from the center of the scene.
+const START_X: f32 = 300.0;
 
 /// Gravity strength.
 pub(crate) const GRAVITY: f32 = -9.8 * 250.0;
@@ -18,27 +30,133 @@ pub(crate) const GRAVITY: f32 = -9.8 * 250.0;
 /// Initial velocity for player jumps.
 const JUMP_VELOCITY: f32 = 12.0 * 100.0;
 
-/// Velocity for horizontal player movement.
-const HORIZ_VELOCITY: f32 = 5.0 * 100.0;
+/// Walking speed of each character.
+const WALK_SPEEDS: [f32; 2] = [4.5 * 100.0, 5.5 * 100.0];
+
+/// Dash speed of each character. Dashes cover a set distance of speed over their ticks.
+const DASH_SPEEDS: [f32; 2] = [11.0 * 100.0, 13.0 * 100.0];
+
+/// Backdash speed of each character.
+const BACKDASH_SPEEDS: [f32; 2] = [9.0 * 100.0, 10.0 * 100.0];
+
+/// Number of ticks a dash lasts.
+const DASH_TICKS: u8 = 12;
+
+/// Number of ticks a backdash lasts.
+const BACKDASH_TICKS: u8 = 14;
+
+/// Number of ticks at the start of a backdash during which the player can't be hit or thrown.
+const BACKDASH_INVINCIBLE_TICKS: u8 = 8;
+
+/// Number of ticks a character can't act for after a dash.
+const DASH_RECOVERY_TICKS: u8 = 6;
+
+/// Number of ticks a character can't act for after a backdash.
+const BACKDASH_RECOVERY_TICKS: u8 = 10;
+
+/// Number of ticks within which a direction has to be tapped twice to dash.
+const DOUBLE_TAP_TICKS: u8 = 15;
+
+/// Number of ticks between puffs of dust while running.
+const RUNNING_DUST_TICKS: u32 = 12;
 
 /// Collider alpha (used for displaying collider for debugging).
 const COLLIDER_ALPHA: f32 = 0.0;
 
-/// Starting health stat.
-const MAX_HEALTH: u8 = 100;
+/// Starting health of each character.
+const MAX_HEALTHS: [u8; 2] = [100, 100];
 
-/// Animation frame used to determine collisions a player's attack.
+/// Animation frame used to determine collisions a character's attack.
 const ATTACK_FRAMES: [usize; 2] = [4, 2];
 
 /// Animation frame used to determine audio for attack.
 const ATTACK_AUDIO_FRAMES: [usize; 2] = [3, 3];
 
-/// Attack damage of player. Player one has slow powerful attack while player two has quick weaker
-/// attack (based on number of frames of animation).
+/// Attack damage of character. Mack has slow powerful attack while Kenji has quick weaker attack
+/// (based on number of frames of animation).
 const ATTACK_DAMAGES: [u8; 2] = [10_u8, 8_u8];
 
+/// Animation frame used to determine collisions for a character's air attack.
+const AIR_ATTACK_FRAMES: [usize; 2] = [3, 2];
+
+/// Air attack damage of each character.
+const AIR_ATTACK_DAMAGES: [u8; 2] = [8, 6];
+
+/// Number of ticks a character can't act for after landing during an air attack.
+const LANDING_RECOVERY_TICKS: [u8; 2] = [12, 8];
+
+/// Number of extra jumps each character can make in the air.
+const AIR_JUMPS: [u8; 2] = [0, 1];
+
+/// Air dash speed of each character. Characters without one can't air dash.
+const AIR_DASH_SPEEDS: [Option<f32>; 2] = [Some(9.0 * 100.0), None];
+
+/// Number of ticks an air dash lasts.
+const AIR_DASH_TICKS: u8 = 12;
+
+/// Animation frame on which a character's special move launches its projectile.
+const SPECIAL_FRAMES: [usize; 2] = [3, 2];
+
+/// Animation frame on which a character's throw grabs.
+const THROW_FRAMES: [usize; 2] = [1, 1];
+
+/// Throw damage of each character.
+const THROW_DAMAGES: [u8; 2] = [12, 12];
+
+/// Number of ticks a grabbed player has to escape the throw.
+const THROW_TECH_TICKS: u8 = 12;
+
+/// How far in front of (or behind) the thrower the thrown player lands.
+const THROW_DISTANCE: f32 = 150.0;
+
+/// Upward velocity of a thrown player.
+const THROW_VELOCITY: f32 = 6.0 * 100.0;
+
+/// How far apart players are pushed when a throw is escaped.
+const THROW_TECH_PUSH: f32 = 60.0;
+
+/// Number of ticks a knocked down player stays on the ground before getting up.
+const KNOCKDOWN_TICKS: u8 = 45;
+
+/// Speed of a tech roll when getting up quickly.
+const TECH_ROLL_SPEED: f32 = 6.0 * 100.0;
+
+/// Number of ticks a player is stuck for after taking a counter hit, on top of the hit animation.
+const COUNTER_HITSTUN_TICKS: u8 = 12;
+
+/// Number of ticks a player is stuck for after being punished, on top of the hit animation.
+const PUNISH_HITSTUN_TICKS: u8 = 6;
+
+/// Distance from the center of a character's sprite to its feet (Height=200 => y-center=100).
+const FEET_OFFSETS: [f32; 2] = [22.0, 28.0];
+
+/// Collider box position and size of each character.
+const COLLIDER_BOXES: [(Vec2, Vec2); 2] = [
+    (Vec2::new(0.0, 15.0), Vec2::new(30.0, 55.0)),
+    (Vec2::new(0.0, 0.0), Vec2::new(25.0, 58.0)),
+];
+
+/// Attack box position (facing right) and size of each character.
+const ATTACK_BOXES: [(Vec2, Vec2); 2] = [
+    (Vec2::new(145.0, 56.0), Vec2::new(75.0, 25.0)),
+    (Vec2::new(130.0, 32.0), Vec2::new(70.0, 35.0)),
+];
+
+/// Air attack box position (facing right) and size of each character. Air attacks reach down and
+/// forward.
+const AIR_ATTACK_BOXES: [(Vec2, Vec2); 2] = [
+    (Vec2::new(110.0, -10.0), Vec2::new(60.0, 35.0)),
+    (Vec2::new(100.0, -20.0), Vec2::new(55.0, 40.0)),
+];
+
+/// Throw box position (facing right) and size of each character. Much shorter than attacks.
+const THROW_BOXES: [(Vec2, Vec2); 2] = [
+    (Vec2::new(60.0, 15.0), Vec2::new(35.0, 40.0)),
+    (Vec2::new(60.0, 0.0), Vec2::new(35.0, 40.0)),
+];
+
 lazy_static! {
-    /// Frame ranges for player states (min, max).
+    /// Frame ranges for character states (min, max).
     static ref FRAMES: [HashMap<State, (usize, usize)>; 2] = {
         let mut p1 = HashMap::new();
         p1.insert(State::Attacking, (0, 5));
@@ -48,6 +166,16 @@ lazy_static! {
         p1.insert(State::Jumping, (40, 41));
         p1.insert(State::Running, (48, 55));
         p1.insert(State::TakingHit, (64, 67));
+        p1.insert(State::Victory, (8, 13));
+        p1.insert(State::Throwing, (0, 5));
+        p1.insert(State::Thrown, (64, 67));
+        p1.insert(State::Special, (0, 5));
+        p1.insert(State::AirAttacking, (0, 5));
+        p1.insert(State::Recovering, (32, 32));
+        p1.insert(State::Dashing, (48, 55));
+        p1.insert(State::Backdashing, (40, 41));
+        p1.insert(State::KnockedDown, (16, 21));
+        p1.insert(State::Rising, (16, 21));
 
         let mut p2 = HashMap::new();
         p2.insert(State::Attacking, (0, 3));
@@ -57,6 +185,41 @@ lazy_static! {
         p2.insert(State::Jumping, (40, 41));
         p2.insert(State::Running, (48, 55));
         p2.insert(State::TakingHit, (56, 58));
+        p2.insert(State::Victory, (8, 11));
+        p2.insert(State::Throwing, (0, 3));
+        p2.insert(State::Thrown, (56, 58));
+        p2.insert(State::Special, (0, 3));
+        p2.insert(State::AirAttacking, (0, 3));
+        p2.insert(State::Recovering, (32, 32));
+        p2.insert(State::Dashing, (48, 55));
+        p2.insert(State::Backdashing, (40, 41));
+        p2.insert(State::KnockedDown, (16, 22));
+        p2.insert(State::Rising, (16, 22));
+
+        [p1, p2]
+    };
+
+    /// Frame ranges for character states (min, max) during which the character can't be hit or
+    /// thrown. Covers the startup of reversals.
+    static ref INVINCIBLE_FRAMES: [HashMap<State, (usize, usize)>; 2] = {
+        let mut p1 = HashMap::new();
+        p1.insert(State::Special, (0, 2));
+
+        let mut p2 = HashMap::new();
+        p2.insert(State::Special, (0, 1));
+
+        [p1, p2]
+    };
+
+    /// Heavy moves that knock the opponent down when they hit. Enhanced and super attacks as
+    /// well as throws always knock down.
+    static ref KNOCKDOWN_STATES: [HashSet<State>; 2] = {
+        let mut p1 = HashSet::new();
+        p1.insert(State::Attacking);
+
+        // Kenji's air attack dives down onto the opponent.
+        let mut p2 = HashSet::new();
+        p2.insert(State::AirAttacking);
 
         [p1, p2]
     };
@@ -70,23 +233,34 @@ impl Plugin for PlayerPlugin {
         app.add_event::<HealthUpdateEvent>()
             // Setup the players when we enter game play.
             .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(setup))
-            // Enable all systems for game play updates.
-            .add_system_set(
-                SystemSet::on_update(GameState::InGame)
-                    .with_system(collision_system)
-                    .with_system(game_play_input_system.before(collision_system))
-                    .with_system(movement_system.before(collision_system))
-                    .with_system(animation_system)
-                    .with_system(game_over_system),
-            )
-            // Enabling animation and movement system will ensure movement/animations can
-            // complete on Game Over. Since input system is not enabled it will not allow
-            // game play anymore.
-            .add_system_set(
-                SystemSet::on_update(GameState::GameOver)
-                    .with_system(animation_system)
-                    .with_system(movement_system),
-            )
+            // Game play systems run once per simulation tick so they can be rolled back.
+            .stage(FightStage, |stage: &mut RollbackStage| {
+                // Enable all systems for game play updates. Every system is ordered so ticks are
+                // simulated the same way on both peers and when resimulating.
+                stage.add_system_set(
+                    SystemSet::on_update(GameState::InGame)
+                        // Sudden death changes health so it's settled before anyone is hit.
+                        .with_system(game_play_input_system.after(countdown_system))
+                        .with_system(movement_system.after(game_play_input_system))
+                        .with_system(facing_system.after(movement_system))
+                        .with_system(special_system.after(facing_system))
+                        .with_system(projectile_system.after(special_system))
+                        .with_system(collision_system.after(projectile_system))
+                        .with_system(throw_system.after(collision_system))
+                        .with_system(animation_system.after(throw_system))
+                        .with_system(game_over_system.after(animation_system)),
+                );
+                // Enabling animation and movement system will ensure movement/animations can
+                // complete on Game Over. Since input system is not enabled it will not allow
+                // game play anymore.
+                stage.add_system_set(
+                    SystemSet::on_update(GameState::GameOver)
+                        .with_system(movement_system)
+                        .with_system(projectile_system.after(movement_system))
+                        .with_system(victory_system.after(projectile_system))
+                        .with_system(animation_system.after(victory_system)),
+                )
+            })
             // Cleanup resources on leaving game over state.
             .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(cleanup));
     }
@@ -118,11 +292,19 @@ impl Player {
             Self::Two => Self::One,
         }
     }
+
+    /// Player one starts on the left facing right and player two on the right facing left.
+    pub(crate) fn facing(&self) -> f32 {
+        match self {
+            Self::One => 1.0,
+            Self::Two => -1.0,
+        }
+    }
 }
 
 /// Represents player states.
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
-enum State {
+pub(crate) enum State {
     Attacking,
     Dying,
     Falling,
@@ -130,6 +312,16 @@ enum State {
     Jumping,
     Running,
     TakingHit,
+    Victory,
+    Throwing,
+    Thrown,
+    Special,
+    AirAttacking,
+    Recovering,
+    Dashing,
+    Backdashing,
+    KnockedDown,
+    Rising,
 }
 
 impl Default for State {
@@ -138,6 +330,40 @@ impl Default for State {
     }
 }
 
+impl State {
+    /// All states in the order used for encoding.
+    const ALL: [State; 17] = [
+        Self::Attacking,
+        Self::Dying,
+        Self::Falling,
+        Self::Idling,
+        Self::Jumping,
+        Self::Running,
+        Self::TakingHit,
+        Self::Victory,
+        Self::Throwing,
+        Self::Thrown,
+        Self::Special,
+        Self::AirAttacking,
+        Self::Recovering,
+        Self::Dashing,
+        Self::Backdashing,
+        Self::KnockedDown,
+        Self::Rising,
+    ];
+
+    fn to_u8(self) -> u8 {
+        Self::ALL
+            .iter()
+            .position(|s| *s == self)
+            .unwrap_or_default() as u8
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+}
+
 /// Represents player's current state.
 #[derive(Component, Default, Deref, DerefMut)]
 struct CurrentState(State);
@@ -168,18 +394,13 @@ impl PreviousState {
 #[derive(Component, Deref, DerefMut)]
 struct Velocity(Vec3);
 
-/// Used to adjust sprite's y location based on vertical padding in sprite sheet.
+/// Palette the character is drawn with.
 #[derive(Component)]
-struct GroundY(f32);
+struct Palette(u8);
 
-/// Represents player action keys.
+/// Used to adjust sprite's y location based on vertical padding in sprite sheet.
 #[derive(Component)]
-struct Keys {
-    left: KeyCode,
-    right: KeyCode,
-    jump: KeyCode,
-    attack: KeyCode,
-}
+struct GroundY(f32);
 
 /// Represents the bounding box for testing attack collisions.
 #[derive(Component)]
@@ -189,6 +410,63 @@ struct ColliderBox;
 #[derive(Component)]
 struct AttackBox;
 
+/// Represents the air attack box for testing with collider_boxes.
+#[derive(Component)]
+struct AirAttackBox;
+
+/// Represents what a player has done since leaving the ground.
+#[derive(Component, Default)]
+struct AirActions {
+    /// Extra jumps made.
+    jumps: u8,
+
+    /// True once the player has air dashed.
+    dashed: bool,
+
+    /// Ticks left in the current air dash.
+    dash_ticks: u8,
+}
+
+/// Represents the number of ticks left before a player can act after landing, dashing or being
+/// knocked down.
+#[derive(Component, Default, Deref, DerefMut)]
+struct Recovery(u8);
+
+/// Represents the number of ticks left in a player's dash or backdash.
+#[derive(Component, Default, Deref, DerefMut)]
+struct Dash(u8);
+
+impl Dash {
+    /// Returns true if the player can't be hit or thrown.
+    fn invincible(&self, state: State) -> bool {
+        state == State::Backdashing && self.0 > BACKDASH_TICKS - BACKDASH_INVINCIBLE_TICKS
+    }
+}
+
+/// Represents the last direction tapped by a player, used to detect double taps.
+#[derive(Component, Default)]
+struct Tap {
+    /// -1 for left, 1 for right.
+    direction: i8,
+
+    /// Ticks left to tap the same direction again.
+    ticks: u8,
+}
+
+/// Represents the throw box for testing with collider_boxes.
+#[derive(Component)]
+struct ThrowBox;
+
+/// Represents a player holding their opponent in a throw.
+#[derive(Component, Default)]
+struct Grab {
+    /// Ticks left for the opponent to escape. Zero when not holding anyone.
+    ticks: u8,
+
+    /// Throw ends with the players on swapped sides.
+    switch_sides: bool,
+}
+
 /// Represents the current sprite index (used for determining collision).
 #[derive(Component, Deref, DerefMut)]
 struct CurrentFrame(usize);
@@ -201,103 +479,495 @@ pub(crate) struct Health(pub(crate) u8);
 pub(crate) struct HealthUpdateEvent {
     pub(crate) player: Player,
     pub(crate) health: u8,
+    pub(crate) max_health: u8,
 }
 
 impl HealthUpdateEvent {
-    pub(crate) fn new(player: Player, health: u8) -> Self {
-        HealthUpdateEvent { player, health }
+    pub(crate) fn new(player: Player, health: u8, character: Character) -> Self {
+        HealthUpdateEvent {
+            player,
+            health,
+            max_health: MAX_HEALTHS[character.index()],
+        }
+    }
+
+    /// Health left as a fraction of the character's maximum health.
+    pub(crate) fn percent(&self) -> f32 {
+        self.health as f32 / self.max_health as f32
+    }
+}
+
+/// Starting health of a character.
+pub(crate) fn max_health(character: Character) -> u8 {
+    MAX_HEALTHS[character.index()]
+}
+
+/// Snapshot of the components that make up a player's fight state.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct PlayerSnapshot {
+    pub(crate) translation: Vec3,
+    pub(crate) facing: f32,
+    pub(crate) velocity: Vec3,
+    pub(crate) current_state: State,
+    pub(crate) previous_state: State,
+    pub(crate) current_frame: usize,
+    pub(crate) health: u8,
+    pub(crate) animation_elapsed: Duration,
+    pub(crate) character: Character,
+    pub(crate) palette: u8,
+    pub(crate) grab_ticks: u8,
+    pub(crate) grab_switch_sides: bool,
+    pub(crate) air_jumps: u8,
+    pub(crate) air_dashed: bool,
+    pub(crate) air_dash_ticks: u8,
+    pub(crate) recovery: u8,
+    pub(crate) dash_ticks: u8,
+    pub(crate) tap_direction: i8,
+    pub(crate) tap_ticks: u8,
+}
+
+impl PlayerSnapshot {
+    /// Encode the snapshot.
+    pub(crate) fn encode(&self, writer: &mut StateWriter) {
+        writer.vec3(self.translation);
+        writer.f32(self.facing);
+        writer.vec3(self.velocity);
+        writer.u8(self.current_state.to_u8());
+        writer.u8(self.previous_state.to_u8());
+        writer.u16(self.current_frame as u16);
+        writer.u8(self.health);
+        writer.duration(self.animation_elapsed);
+        writer.u8(self.character.index() as u8);
+        writer.u8(self.palette);
+        writer.u8(self.grab_ticks);
+        writer.bool(self.grab_switch_sides);
+        writer.u8(self.air_jumps);
+        writer.bool(self.air_dashed);
+        writer.u8(self.air_dash_ticks);
+        writer.u8(self.recovery);
+        writer.u8(self.dash_ticks);
+        writer.u8(self.tap_direction as u8);
+        writer.u8(self.tap_ticks);
+    }
+
+    /// Decode a snapshot.
+    pub(crate) fn decode(reader: &mut StateReader) -> Option<Self> {
+        Some(Self {
+            translation: reader.vec3()?,
+            facing: reader.f32()?,
+            velocity: reader.vec3()?,
+            current_state: State::from_u8(reader.u8()?)?,
+            previous_state: State::from_u8(reader.u8()?)?,
+            current_frame: reader.u16()? as usize,
+            health: reader.u8()?,
+            animation_elapsed: reader.duration()?,
+            character: Character::from_index(reader.u8()? as usize)?,
+            palette: reader.u8()?,
+            grab_ticks: reader.u8()?,
+            grab_switch_sides: reader.bool()?,
+            air_jumps: reader.u8()?,
+            air_dashed: reader.bool()?,
+            air_dash_ticks: reader.u8()?,
+            recovery: reader.u8()?,
+            dash_ticks: reader.u8()?,
+            tap_direction: reader.u8()? as i8,
+            tap_ticks: reader.u8()?,
+        })
+    }
+}
+
+/// Save the fight state of both players.
+pub(crate) fn save_players(world: &mut World) -> [PlayerSnapshot; 2] {
+    let mut snapshots = [PlayerSnapshot::default(); 2];
+
+    let mut query = world.query::<(
+        &Player,
+        &Transform,
+        &Velocity,
+        &CurrentState,
+        &PreviousState,
+        &CurrentFrame,
+        &Health,
+        &AnimationTimer,
+        &Character,
+        &Palette,
+        &Grab,
+        &AirActions,
+        &Recovery,
+        &Dash,
+        &Tap,
+    )>();
+    for (
+        player,
+        transform,
+        velocity,
+        current_state,
+        previous_state,
+        current_frame,
+        health,
+        animation_timer,
+        character,
+        palette,
+        grab,
+        air_actions,
+        recovery,
+        dash,
+        tap,
+    ) in query.iter(world)
+    {
+        snapshots[player.index()] = PlayerSnapshot {
+            translation: transform.translation,
+            facing: transform.scale.x,
+            velocity: velocity.0,
+            current_state: current_state.0,
+            previous_state: previous_state.0,
+            current_frame: current_frame.0,
+            health: health.0,
+            animation_elapsed: animation_timer.elapsed(),
+            character: *character,
+            palette: palette.0,
+            grab_ticks: grab.ticks,
+            grab_switch_sides: grab.switch_sides,
+            air_jumps: air_actions.jumps,
+            air_dashed: air_actions.dashed,
+            air_dash_ticks: air_actions.dash_ticks,
+            recovery: recovery.0,
+            dash_ticks: dash.0,
+            tap_direction: tap.direction,
+            tap_ticks: tap.ticks,
+        };
+    }
+
+    snapshots
+}
+
+/// Restore the fight state of both players.
+pub(crate) fn restore_players(world: &mut World, snapshots: &[PlayerSnapshot; 2]) {
+    let stage_ground_y = world
+        .resource::<Stages>()
+        .get(world.resource::<MatchSettings>().stage)
+        .ground_y;
+    let mut players: Vec<(Entity, Player)> = Vec::new();
+    let mut changed_characters: Vec<(Entity, Player, Character)> = Vec::new();
+
+    let mut query = world.query::<(
+        Entity,
+        &Player,
+        &mut Transform,
+        &mut Velocity,
+        &mut CurrentState,
+        &mut PreviousState,
+        &mut CurrentFrame,
+        &mut Health,
+        &mut AnimationTimer,
+        &mut Character,
+        &mut Palette,
+        &mut GroundY,
+        // Queries take at most 16 components so the move state is grouped.
+        (
+            &mut Grab,
+            &mut AirActions,
+            &mut Recovery,
+            &mut Dash,
+            &mut Tap,
+        ),
+    )>();
+    for (
+        entity,
+        player,
+        mut transform,
+        mut velocity,
+        mut current_state,
+        mut previous_state,
+        mut current_frame,
+        mut health,
+        mut animation_timer,
+        mut character,
+        mut palette,
+        mut ground_y,
+        (mut grab, mut air_actions, mut recovery, mut dash, mut tap),
+    ) in query.iter_mut(world)
+    {
+        let snapshot = &snapshots[player.index()];
+        transform.translation = snapshot.translation;
+        transform.scale.x = snapshot.facing;
+        velocity.0 = snapshot.velocity;
+        current_state.0 = snapshot.current_state;
+        previous_state.0 = snapshot.previous_state;
+        current_frame.0 = snapshot.current_frame;
+        health.0 = snapshot.health;
+        animation_timer.set_elapsed(snapshot.animation_elapsed);
+        palette.0 = snapshot.palette;
+        grab.ticks = snapshot.grab_ticks;
+        grab.switch_sides = snapshot.grab_switch_sides;
+        air_actions.jumps = snapshot.air_jumps;
+        air_actions.dashed = snapshot.air_dashed;
+        air_actions.dash_ticks = snapshot.air_dash_ticks;
+        recovery.0 = snapshot.recovery;
+        dash.0 = snapshot.dash_ticks;
+        tap.direction = snapshot.tap_direction;
+        tap.ticks = snapshot.tap_ticks;
+        ground_y.0 = character_ground_y(snapshot.character, stage_ground_y);
+        if *character != snapshot.character {
+            // Only happens when a spectator joins a match played with other characters.
+            *character = snapshot.character;
+            changed_characters.push((entity, *player, snapshot.character));
+        }
+        players.push((entity, *player));
+    }
+
+    if !changed_characters.is_empty() {
+        apply_characters(world, &changed_characters);
+    }
+
+    // The sprite index drives the next animation frame so it has to match the current frame.
+    let mut sprite_query = world.query::<(&Parent, &mut TextureAtlasSprite)>();
+    for (parent, mut sprite) in sprite_query.iter_mut(world) {
+        if let Some((_, player)) = players.iter().find(|(e, _)| *e == parent.get()) {
+            let snapshot = &snapshots[player.index()];
+            sprite.index = snapshot.current_frame;
+            sprite.color = snapshot.character.palette(snapshot.palette);
+        }
+    }
+
+    // Health may have gone up or down so let the health bars catch up.
+    let mut health_update_events = world.resource_mut::<Events<HealthUpdateEvent>>();
+    for (_, player) in players.iter() {
+        let snapshot = &snapshots[player.index()];
+        health_update_events.send(HealthUpdateEvent::new(
+            *player,
+            snapshot.health,
+            snapshot.character,
+        ));
+    }
+}
+
+/// Switch the sprite sheet and boxes of players to their new characters.
+fn apply_characters(world: &mut World, changed: &[(Entity, Player, Character)]) {
+    let assets = world.resource::<GameAssets>();
+    let atlases: Vec<Handle<TextureAtlas>> = changed
+        .iter()
+        .map(|(_, _, character)| character.texture_atlas(assets))
+        .collect();
+
+    let mut sprite_query = world.query::<(&Parent, &mut Handle<TextureAtlas>)>();
+    for (parent, mut atlas) in sprite_query.iter_mut(world) {
+        if let Some(i) = changed.iter().position(|(e, _, _)| *e == parent.get()) {
+            *atlas = atlases[i].clone();
+        }
+    }
+
+    let mut box_query = world.query::<(
+        &Parent,
+        &mut Transform,
+        &mut GroundY,
+        Option<&ColliderBox>,
+        Option<&AttackBox>,
+        Option<&AirAttackBox>,
+        Option<&ThrowBox>,
+    )>();
+    for (
+        parent,
+        mut transform,
+        mut ground_y,
+        collider_box,
+        attack_box,
+        air_attack_box,
+        throw_box,
+    ) in box_query.iter_mut(world)
+    {
+        if let Some((_, _, character)) = changed.iter().find(|(e, _, _)| *e == parent.get()) {
+            if collider_box.is_some() {
+                *transform = collider_box_transform(*character);
+            } else if attack_box.is_some() {
+                *transform = attack_box_transform(*character);
+            } else if air_attack_box.is_some() {
+                *transform = air_attack_box_transform(*character);
+            } else if throw_box.is_some() {
+                *transform = throw_box_transform(*character);
+            } else {
+                continue;
+            }
+            ground_y.0 = transform.translation.y;
+        }
     }
 }
 
 /// Setup the players.
-fn setup(mut commands: Commands, assets: Res<GameAssets>, audio: Res<Audio>) {
-    audio.play(assets.in_game_audio.clone()).looped();
-
-    let mut entities: Vec<Entity> = Vec::new();
-
-    // Player 1: Adjust player feet (Height=200, y-feet=122 => y-center=100 => y-offset=22).
-    let mut pos = Vec3::new(-300.0, GROUND_Y, PLAYER_Z);
-    pos += Vec3::new(0.0, 22.0 * PLAYER_SCALE, 0.01);
-    entities.push(spawn_player(
-        &mut commands,
-        &assets,
-        Player::One,
-        pos,
-        Keys {
-            left: KeyCode::A,
-            right: KeyCode::D,
-            jump: KeyCode::W,
-            attack: KeyCode::S,
-        },
-        Vec3::new(0.0, 15.0, PLAYER_Z + 0.02),
-        Vec3::new(30.0, 55.0, 1.0) * PLAYER_SCALE,
-        Color::rgba(1.0, 0.0, 0.0, COLLIDER_ALPHA),
-        Vec3::new(145.0, 56.0, PLAYER_Z + 0.03),
-        Vec3::new(75.0, 25.0, 1.0) * PLAYER_SCALE,
-        Color::rgba(1.0, 1.0, 0.0, COLLIDER_ALPHA),
-    ));
-
-    // Player 2: Adjust player feet. (Height=200, y-feet=128 => y-center=100 => y-offset=28).
-    pos = Vec3::new(300.0, GROUND_Y, PLAYER_Z);
-    pos += Vec3::new(0.0, 28.0 * PLAYER_SCALE, 0.01);
-    entities.push(spawn_player(
-        &mut commands,
-        &assets,
-        Player::Two,
-        pos,
-        Keys {
-            left: KeyCode::Left,
-            right: KeyCode::Right,
-            jump: KeyCode::Up,
-            attack: KeyCode::Down,
-        },
-        Vec3::new(0.0, 0.0, PLAYER_Z + 0.02),
-        Vec3::new(25.0, 58.0, 1.0) * PLAYER_SCALE,
-        Color::rgba(0.0, 1.0, 0.0, COLLIDER_ALPHA),
-        Vec3::new(-130.0, 32.0, PLAYER_Z + 0.03),
-        Vec3::new(70.0, 35.0, 1.0) * PLAYER_SCALE,
-        Color::rgba(1.0, 0.0, 1.0, COLLIDER_ALPHA),
-    ));
+fn setup(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    audio: Res<Audio>,
+    settings: Res<MatchSettings>,
+    stages: Res<Stages>,
+    arcade: Option<Res<Arcade>>,
+    mut health_update_events: EventWriter<HealthUpdateEvent>,
+) {
+    let stage = stages.get(settings.stage);
+    audio.play(stage.music.clone()).looped();
+
+    let palettes = settings.match_palettes();
+
+    // Survival carries player one's health over between fights.
+    let character = settings.characters[Player::One.index()];
+    let max = max_health(character);
+    let health = arcade.map_or(max, |arcade| arcade.starting_health(max));
+    if health < max {
+        health_update_events.send(HealthUpdateEvent::new(Player::One, health, character));
+    }
+
+    let mut entities = vec![
+        spawn_player(
+            &mut commands,
+            &assets,
+            stage,
+            Player::One,
+            character,
+            palettes[Player::One.index()],
+            health,
+            Keys {
+                left: KeyCode::A,
+                right: KeyCode::D,
+                jump: KeyCode::W,
+                attack: KeyCode::S,
+                super_attack: KeyCode::E,
+                throw: KeyCode::Q,
+                special: KeyCode::F,
+            },
+            Color::rgba(1.0, 0.0, 0.0, COLLIDER_ALPHA),
+            Color::rgba(1.0, 1.0, 0.0, COLLIDER_ALPHA),
+            Color::rgba(1.0, 0.5, 0.0, COLLIDER_ALPHA),
+        ),
+        spawn_player(
+            &mut commands,
+            &assets,
+            stage,
+            Player::Two,
+            settings.characters[Player::Two.index()],
+            palettes[Player::Two.index()],
+            max_health(settings.characters[Player::Two.index()]),
+            Keys {
+                left: KeyCode::Left,
+                right: KeyCode::Right,
+                jump: KeyCode::Up,
+                attack: KeyCode::Down,
+                super_attack: KeyCode::RShift,
+                throw: KeyCode::RControl,
+                special: KeyCode::Slash,
+            },
+            Color::rgba(0.0, 1.0, 0.0, COLLIDER_ALPHA),
+            Color::rgba(1.0, 0.0, 1.0, COLLIDER_ALPHA),
+            Color::rgba(0.0, 0.5, 1.0, COLLIDER_ALPHA),
+        ),
+    ];
+
+    // Projectiles are launched by special moves.
+    for player in [Player::One, Player::Two] {
+        entities.push(spawn_projectile(&mut commands, player));
+    }
 
     commands.insert_resource(EntityData { entities });
 }
 
+/// Ground position of a character. Adjusts for where the character's feet are in the sprite.
+fn character_ground_y(character: Character, stage_ground_y: f32) -> f32 {
+    stage_ground_y + FEET_OFFSETS[character.index()] * PLAYER_SCALE
+}
+
+/// Transform of a player's sprite. Sprites face right. The player is flipped as a whole to face
+/// left.
+fn sprite_transform() -> Transform {
+    Transform {
+        scale: Vec3::new(PLAYER_SCALE, PLAYER_SCALE, 1.0),
+        ..default()
+    }
+}
+
+/// Transform of a character's collider box.
+fn collider_box_transform(character: Character) -> Transform {
+    let (pos, size) = COLLIDER_BOXES[character.index()];
+    Transform {
+        translation: pos.extend(PLAYER_Z + 0.02),
+        scale: (size * PLAYER_SCALE).extend(1.0),
+        ..default()
+    }
+}
+
+/// Transform of a character's attack box.
+fn attack_box_transform(character: Character) -> Transform {
+    let (pos, size) = ATTACK_BOXES[character.index()];
+    Transform {
+        translation: pos.extend(PLAYER_Z + 0.03),
+        scale: (size * PLAYER_SCALE).extend(1.0),
+        ..default()
+    }
+}
+
+/// Transform of a character's air attack box.
+fn air_attack_box_transform(character: Character) -> Transform {
+    let (pos, size) = AIR_ATTACK_BOXES[character.index()];
+    Transform {
+        translation: pos.extend(PLAYER_Z + 0.03),
+        scale: (size * PLAYER_SCALE).extend(1.0),
+        ..default()
+    }
+}
+
+/// Transform of a character's throw box.
+fn throw_box_transform(character: Character) -> Transform {
+    let (pos, size) = THROW_BOXES[character.index()];
+    Transform {
+        translation: pos.extend(PLAYER_Z + 0.04),
+        scale: (size * PLAYER_SCALE).extend(1.0),
+        ..default()
+    }
+}
+
 /// Spawn players.
 fn spawn_player(
     commands: &mut Commands,
     assets: &Res<GameAssets>,
+    stage: &Stage,
     player: Player,
-    player_pos: Vec3,
+    character: Character,
+    palette: u8,
+    health: u8,
     keys: Keys,
-    collider_box_pos: Vec3,
-    collider_box_size: Vec3,
     collider_box_color: Color,
-    attack_box_pos: Vec3,
-    attack_box_size: Vec3,
     attack_box_color: Color,
+    throw_box_color: Color,
 ) -> Entity {
-    let player_atlas_handle = match player {
-        Player::One => assets.player_one_texture_atlas.clone(),
-        Player::Two => assets.player_two_texture_atlas.clone(),
-    };
-
-    let player_tex_scale = match player {
-        Player::One => Vec3::new(PLAYER_SCALE, PLAYER_SCALE, 1.0), // Right facing
-        Player::Two => Vec3::new(-PLAYER_SCALE, PLAYER_SCALE, 1.0), // Flip X-axis for left facing
-    };
+    let player_pos = Vec3::new(
+        -START_X * player.facing(),
+        character_ground_y(character, stage.ground_y),
+        PLAYER_Z + 0.01,
+    );
+    let collider_box_transform = collider_box_transform(character);
+    let attack_box_transform = attack_box_transform(character);
+    let air_attack_box_transform = air_attack_box_transform(character);
+    let throw_box_transform = throw_box_transform(character);
 
     commands
         .spawn(player)
-        .insert(Health(MAX_HEALTH))
+        .insert(character)
+        .insert(Palette(palette))
+        .insert(Health(health))
         .insert(CurrentState::default())
         .insert(PreviousState::default())
         .insert(Velocity(Vec3::new(0.0, 0.0, 0.0)))
         .insert(GroundY(player_pos.y))
         .insert(CurrentFrame(IDLE_FRAME_START))
+        .insert(Grab::default())
+        .insert(AirActions::default())
+        .insert(Recovery::default())
+        .insert(Dash::default())
+        .insert(Tap::default())
         .insert(SpatialBundle {
             visibility: Visibility { is_visible: true },
+            // Flipping the player on x turns them around along with their boxes.
             transform: Transform {
                 translation: player_pos,
+                scale: Vec3::new(player.facing(), 1.0, 1.0),
                 ..default()
             },
             ..default()
@@ -307,49 +977,63 @@ fn spawn_player(
             0.1,
             TimerMode::Repeating,
         )))
-        .with_children(|player| {
-            player.spawn(SpriteSheetBundle {
-                texture_atlas: player_atlas_handle,
+        .with_children(|parent| {
+            parent.spawn(SpriteSheetBundle {
+                texture_atlas: character.texture_atlas(assets),
                 sprite: TextureAtlasSprite {
                     index: IDLE_FRAME_START, // Idling frame start. Avoids starting at Attacking frame.
+                    color: character.palette(palette),
                     ..default()
                 },
-                transform: Transform {
-                    scale: player_tex_scale,
-                    ..default()
-                },
+                transform: sprite_transform(),
                 ..default()
             });
 
-            player
+            parent
                 .spawn(ColliderBox)
-                .insert(GroundY(collider_box_pos.y))
+                .insert(GroundY(collider_box_transform.translation.y))
                 .insert(SpriteBundle {
                     sprite: Sprite {
                         color: collider_box_color,
                         ..default()
                     },
-                    transform: Transform {
-                        translation: collider_box_pos,
-                        scale: collider_box_size,
+                    transform: collider_box_transform,
+                    ..default()
+                });
+
+            parent
+                .spawn(AttackBox)
+                .insert(GroundY(attack_box_transform.translation.y))
+                .insert(SpriteBundle {
+                    sprite: Sprite {
+                        color: attack_box_color,
                         ..default()
                     },
+                    transform: attack_box_transform,
                     ..default()
                 });
 
-            player
-                .spawn(AttackBox)
-                .insert(GroundY(attack_box_pos.y))
+            parent
+                .spawn(AirAttackBox)
+                .insert(GroundY(air_attack_box_transform.translation.y))
                 .insert(SpriteBundle {
                     sprite: Sprite {
                         color: attack_box_color,
                         ..default()
                     },
-                    transform: Transform {
-                        translation: attack_box_pos,
-                        scale: attack_box_size,
+                    transform: air_attack_box_transform,
+                    ..default()
+                });
+
+            parent
+                .spawn(ThrowBox)
+                .insert(GroundY(throw_box_transform.translation.y))
+                .insert(SpriteBundle {
+                    sprite: Sprite {
+                        color: throw_box_color,
                         ..default()
                     },
+                    transform: throw_box_transform,
                     ..default()
                 });
         })
@@ -358,57 +1042,215 @@ fn spawn_player(
 
 /// Handle play input.
 fn game_play_input_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut player_query: Query<
-        (
-            &mut CurrentState,
-            &mut PreviousState,
-            &Keys,
-            &Transform,
-            &GroundY,
-            &mut Velocity,
-        ),
-        With<Player>,
-    >,
+    inputs: Res<FightInputs>,
+    mut super_meters: ResMut<SuperMeters>,
+    mut freeze: ResMut<Freeze>,
+    mut super_events: EventWriter<SuperEvent>,
+    resimulating: Res<Resimulating>,
+    mut player_query: Query<(
+        &Player,
+        &mut CurrentState,
+        &mut PreviousState,
+        &Transform,
+        &GroundY,
+        &mut Velocity,
+        &Character,
+        &mut AirActions,
+        &mut Dash,
+        &mut Tap,
+    )>,
+    projectile_query: Query<&Projectile>,
 ) {
-    for (mut current_state, mut previous_state, keys, transform, ground_y, mut velocity) in
-        player_query.iter_mut()
+    // Players can only have one projectile out at a time.
+    let mut projectiles_active = [false; 2];
+    for projectile in &projectile_query {
+        projectiles_active[projectile.owner().index()] = projectile.active();
+    }
+
+    for (
+        player,
+        mut current_state,
+        mut previous_state,
+        transform,
+        ground_y,
+        mut velocity,
+        character,
+        mut air_actions,
+        mut dash,
+        mut tap,
+    ) in player_query.iter_mut()
     {
+        let player = *player;
+
+        // Work out if a direction was tapped twice.
+        tap.ticks = tap.ticks.saturating_sub(1);
+        let tapped = if inputs.just_pressed(player, PlayerInput::LEFT) {
+            -1
+        } else if inputs.just_pressed(player, PlayerInput::RIGHT) {
+            1
+        } else {
+            0
+        };
+        let double_tapped = tapped != 0 && tapped == tap.direction && tap.ticks > 0;
+        if tapped != 0 {
+            tap.direction = tapped;
+            tap.ticks = if double_tapped { 0 } else { DOUBLE_TAP_TICKS };
+        }
+
         // Don't do anything if player is dead.
         match current_state.0 {
             State::Dying => continue,
+            // Pressing jump once on the ground gets up right away, rolling in the direction held.
+            State::KnockedDown => {
+                velocity.x = 0.0;
+                if transform.translation.y <= ground_y.0
+                    && inputs.just_pressed(player, PlayerInput::JUMP)
+                {
+                    if inputs.pressed(player, PlayerInput::LEFT) {
+                        velocity.x = -TECH_ROLL_SPEED;
+                    } else if inputs.pressed(player, PlayerInput::RIGHT) {
+                        velocity.x = TECH_ROLL_SPEED;
+                    }
+                    current_state.set_state(State::Rising);
+                }
+                continue;
+            }
+            // Getting up carries on until it's over.
+            State::Rising => continue,
+            // Players stand still during a throw or while recovering. Throw escapes are handled
+            // with the throw.
+            State::Throwing | State::Thrown | State::Recovering => {
+                velocity.x = 0.0;
+                continue;
+            }
+            // Dashes carry on until they are over.
+            State::Dashing | State::Backdashing => continue,
             _ => (),
         }
 
+        // Air dashes carry on until they are over.
+        if air_actions.dash_ticks > 0 {
+            continue;
+        }
+
+        // Double tapping towards the opponent dashes and away from them backdashes.
+        if double_tapped
+            && transform.translation.y == ground_y.0
+            && matches!(current_state.0, State::Idling | State::Running)
+        {
+            let direction = tapped as f32;
+            previous_state.set_from_current(&current_state);
+            if direction == transform.scale.x {
+                current_state.set_state(State::Dashing);
+                velocity.x = DASH_SPEEDS[character.index()] * direction;
+                dash.0 = DASH_TICKS;
+            } else {
+                current_state.set_state(State::Backdashing);
+                velocity.x = BACKDASH_SPEEDS[character.index()] * direction;
+                dash.0 = BACKDASH_TICKS;
+            }
+            continue;
+        }
+
+        let walk_speed = WALK_SPEEDS[character.index()];
+
         // Move left as long as left key is pressed.
-        if keyboard_input.pressed(keys.left) {
-            velocity.x = -HORIZ_VELOCITY;
-        } else if keyboard_input.just_released(keys.left) {
+        if inputs.pressed(player, PlayerInput::LEFT) {
+            velocity.x = -walk_speed;
+        } else if inputs.just_released(player, PlayerInput::LEFT) {
             velocity.x = 0.0;
         }
 
         // Move right as long as right key is pressed.
-        if keyboard_input.pressed(keys.right) {
-            velocity.x = HORIZ_VELOCITY;
-        } else if keyboard_input.just_released(keys.right) {
+        if inputs.pressed(player, PlayerInput::RIGHT) {
+            velocity.x = walk_speed;
+        } else if inputs.just_released(player, PlayerInput::RIGHT) {
             velocity.x = 0.0;
         }
 
         // Jump gives an initial upward velocity which will be adjusted based on GRAVITY.
-        if keyboard_input.pressed(keys.jump) {
-            if transform.translation.y == ground_y.0 {
+        if transform.translation.y == ground_y.0 {
+            if inputs.pressed(player, PlayerInput::JUMP) {
                 velocity.y = JUMP_VELOCITY;
             }
+        } else if inputs.just_pressed(player, PlayerInput::JUMP)
+            && !matches!(current_state.0, State::AirAttacking | State::TakingHit)
+        {
+            // Pressing jump again in the air dashes in the direction held or jumps again.
+            let direction = if inputs.pressed(player, PlayerInput::LEFT) {
+                -1.0
+            } else if inputs.pressed(player, PlayerInput::RIGHT) {
+                1.0
+            } else {
+                0.0
+            };
+            match AIR_DASH_SPEEDS[character.index()] {
+                Some(speed) if !air_actions.dashed && direction != 0.0 => {
+                    air_actions.dashed = true;
+                    air_actions.dash_ticks = AIR_DASH_TICKS;
+                    velocity.x = speed * direction;
+                    velocity.y = 0.0;
+                    continue;
+                }
+                _ if air_actions.jumps < AIR_JUMPS[character.index()] => {
+                    air_actions.jumps += 1;
+                    velocity.y = JUMP_VELOCITY;
+                }
+                _ => (),
+            }
         }
 
-        if keyboard_input.pressed(keys.attack) {
-            // If player is either attacking already or taking a hit don't allow an attack.
-            match current_state.0 {
-                State::Attacking | State::TakingHit => (),
-                _ => {
-                    previous_state.set_from_current(&current_state);
-                    current_state.set_state(State::Attacking);
+        // If player is either attacking already or taking a hit don't allow an attack.
+        match current_state.0 {
+            State::Attacking | State::AirAttacking | State::Special | State::TakingHit => continue,
+            _ => (),
+        }
+
+        // Meter is spent on an enhanced attack, or a super attack once full. Like special moves
+        // these are ground moves so they can't be started in the air.
+        let grounded = transform.translation.y <= ground_y.0;
+        if inputs.just_pressed(player, PlayerInput::SUPER) && grounded {
+            if let Some(power) = super_meters.spend(player.index()) {
+                super_meters.attacks[player.index()] = power;
+                previous_state.set_from_current(&current_state);
+                current_state.set_state(State::Attacking);
+
+                // Freeze the fight so the super attack stands out.
+                if power == AttackPower::Super {
+                    freeze.0 = SUPER_FREEZE_TICKS;
+                    if !resimulating.0 {
+                        super_events.send(SuperEvent);
+                    }
                 }
+                continue;
+            }
+        }
+
+        if inputs.just_pressed(player, PlayerInput::SPECIAL)
+            && grounded
+            && !projectiles_active[player.index()]
+        {
+            previous_state.set_from_current(&current_state);
+            current_state.set_state(State::Special);
+            continue;
+        }
+
+        // Throws can only be started on the ground.
+        if inputs.just_pressed(player, PlayerInput::THROW) && grounded {
+            velocity.x = 0.0;
+            previous_state.set_from_current(&current_state);
+            current_state.set_state(State::Throwing);
+            continue;
+        }
+
+        // Attacks in the air use the character's air attack.
+        if inputs.pressed(player, PlayerInput::ATTACK) {
+            super_meters.attacks[player.index()] = AttackPower::Normal;
+            previous_state.set_from_current(&current_state);
+            if !grounded {
+                current_state.set_state(State::AirAttacking);
+            } else {
+                current_state.set_state(State::Attacking);
             }
         }
     }
@@ -416,7 +1258,6 @@ fn game_play_input_system(
 
 /// Handle player movement based on velocity.
 fn movement_system(
-    time: Res<Time>,
     mut player_query: Query<(
         &Player,
         &mut CurrentState,
@@ -426,12 +1267,30 @@ fn movement_system(
         &mut Velocity,
         &CurrentFrame,
         &Health,
+        &Character,
+        &Grab,
+        &mut AirActions,
+        &mut Recovery,
+        &mut Dash,
     )>,
     app_state: Res<bevy::prelude::State<GameState>>,
+    settings: Res<MatchSettings>,
+    stages: Res<Stages>,
+    clock: Res<FightClock>,
+    resimulating: Res<Resimulating>,
+    mut effect_events: EventWriter<EffectEvent>,
 ) {
+    let stage = stages.get(settings.stage);
+    let max_distance = max_player_distance(stage);
     let mut new_velocities = [Vec2::default(); 2];
     let mut move_x = [false; 2];
-    let delta_time = time.delta_seconds();
+    let delta_time = TICK_DURATION.as_secs_f32();
+
+    // Positions before moving so the players can't push each other out of view.
+    let mut positions = [0.0; 2];
+    for (player, _, _, transform, ..) in player_query.iter() {
+        positions[player.index()] = transform.translation.x;
+    }
 
     for (
         player,
@@ -442,26 +1301,58 @@ fn movement_system(
         mut velocity,
         current_frame,
         health,
+        character,
+        grab,
+        mut air_actions,
+        mut recovery,
+        mut dash,
     ) in &mut player_query
     {
         // Handle horizontal movement.
         let new_x = transform.translation.x + velocity.x * delta_time;
-        if new_x > crate::scene::SCENE_MIN_X && new_x < crate::scene::SCENE_MAX_X {
+        let opponent_x = positions[player.opponent().index()];
+        let in_view = (new_x - opponent_x).abs() <= max_distance
+            || (new_x - opponent_x).abs() < (transform.translation.x - opponent_x).abs();
+        if new_x > stage.min_x && new_x < stage.max_x && in_view {
             transform.translation.x = new_x;
             move_x[player.index()] = true;
         } else {
             move_x[player.index()] = false;
         }
 
+        // Air dashes hold the player at the same height.
+        if air_actions.dash_ticks > 0 {
+            air_actions.dash_ticks -= 1;
+            velocity.y = 0.0;
+            if air_actions.dash_ticks == 0 {
+                velocity.x = 0.0;
+            }
+        }
+
         // Handle vertical movement.
         transform.translation.y += velocity.y * delta_time;
         if transform.translation.y > ground_y.0 {
             // Player is in the air keep decreasing velocity.
-            velocity.y += GRAVITY * delta_time;
+            if air_actions.dash_ticks == 0 {
+                velocity.y += GRAVITY * delta_time;
+            }
         } else if transform.translation.y <= ground_y.0 {
             // Player has hit the ground. Reset velocity and position.
+            if velocity.y < 0.0 {
+                if !resimulating.0 {
+                    let position = Vec2::new(transform.translation.x, stage.ground_y);
+                    effect_events.send(EffectEvent::new(Effect::LandingDust, position));
+                }
+
+                // Air attacks end on landing and leave the player open for a moment.
+                if current_state.0 == State::AirAttacking {
+                    current_state.set_state(State::Recovering);
+                    recovery.0 = LANDING_RECOVERY_TICKS[character.index()];
+                }
+            }
             transform.translation.y = ground_y.0;
             velocity.y = 0.0;
+            *air_actions = AirActions::default();
         }
 
         // Check if player is dying.
@@ -475,7 +1366,7 @@ fn movement_system(
                 // Once player is on ground and not dead, move to idle state so player doesn't
                 // continue running or jumping.
                 if transform.translation.y <= ground_y.0 {
-                    if !matches!(current_state.0, State::Dying) {
+                    if !matches!(current_state.0, State::Dying | State::Victory) {
                         current_state.0 = State::Idling;
                     }
                     velocity.x = 0.0;
@@ -492,18 +1383,98 @@ fn movement_system(
             }
             State::Attacking => {
                 // Let player finish attacking.
-                let max_frame = FRAMES[player.index()].get(&State::Attacking).unwrap().1;
+                let max_frame = FRAMES[character.index()].get(&State::Attacking).unwrap().1;
                 if current_frame.0 == max_frame {
                     current_state.set_from_previous(previous_state);
                 }
             }
-            State::TakingHit => {
-                // Let player finish taking hit.
-                let max_frame = FRAMES[player.index()].get(&State::TakingHit).unwrap().1;
+            State::AirAttacking => {
+                // Let player finish attacking. Landing ends the attack early.
+                let max_frame = FRAMES[character.index()]
+                    .get(&State::AirAttacking)
+                    .unwrap()
+                    .1;
                 if current_frame.0 == max_frame {
+                    current_state.set_from_previous(previous_state);
+                }
+            }
+            State::Dashing | State::Backdashing => {
+                // Dashes cover a set distance and leave the player open for a moment.
+                dash.0 = dash.0.saturating_sub(1);
+                if dash.0 == 0 {
+                    velocity.x = 0.0;
+                    recovery.0 = if current_state.0 == State::Dashing {
+                        DASH_RECOVERY_TICKS
+                    } else {
+                        BACKDASH_RECOVERY_TICKS
+                    };
+                    current_state.set_state(State::Recovering);
+                }
+            }
+            State::Recovering => {
+                // Wait for player to recover.
+                recovery.0 = recovery.0.saturating_sub(1);
+                if recovery.0 == 0 {
+                    current_state.0 = State::Idling;
+                }
+            }
+            State::Special => {
+                // Let player finish their special move.
+                let max_frame = FRAMES[character.index()].get(&State::Special).unwrap().1;
+                if current_frame.0 == max_frame {
+                    current_state.set_from_previous(previous_state);
+                }
+            }
+            State::Throwing => {
+                // Let player finish throwing unless they are still holding their opponent.
+                let max_frame = FRAMES[character.index()].get(&State::Throwing).unwrap().1;
+                if current_frame.0 == max_frame && grab.ticks == 0 {
+                    current_state.set_from_previous(previous_state);
+                }
+            }
+            State::Thrown => {
+                // Held until the throw lands or is escaped.
+            }
+            State::KnockedDown => {
+                // Stay down for a while once on the ground.
+                if transform.translation.y <= ground_y.0 {
+                    velocity.x = 0.0;
+                    recovery.0 = recovery.0.saturating_sub(1);
+                    if recovery.0 == 0 {
+                        current_state.set_state(State::Rising);
+                    }
+                }
+            }
+            State::Rising => {
+                // Getting up plays the fall backwards so it's over on the first frame.
+                let min_frame = FRAMES[character.index()].get(&State::Rising).unwrap().0;
+                if current_frame.0 == min_frame {
+                    velocity.x = 0.0;
+                    recovery.0 = 0;
+                    current_state.set_state(State::Idling);
+                }
+            }
+            State::TakingHit => {
+                // Getting hit stops a dash.
+                if matches!(previous_state.0, State::Dashing | State::Backdashing) {
+                    velocity.x = 0.0;
+                    dash.0 = 0;
+                }
+
+                // Let player finish taking hit. Counter hits and punishes leave the player stuck
+                // for a while longer.
+                let max_frame = FRAMES[character.index()].get(&State::TakingHit).unwrap().1;
+                if current_frame.0 == max_frame && recovery.0 > 0 {
+                    current_state.set_state(State::Recovering);
+                } else if current_frame.0 == max_frame {
                     match previous_state.0 {
-                        State::Attacking => {
-                            // Don't resume attacking state after taking a hit.
+                        State::Attacking
+                        | State::AirAttacking
+                        | State::Special
+                        | State::Throwing
+                        | State::Dashing
+                        | State::Backdashing => {
+                            // Don't resume attacking or throwing state after taking a hit.
                             // Determine state based on position/velocity.
                             if transform.translation.y > ground_y.0 {
                                 if velocity.y > 0.0 {
@@ -540,96 +1511,668 @@ fn movement_system(
             }
         }
 
+        // Kick up dust every few steps.
+        if current_state.0 == State::Running
+            && move_x[player.index()]
+            && clock.tick.is_multiple_of(RUNNING_DUST_TICKS)
+            && !resimulating.0
+        {
+            let position = Vec2::new(transform.translation.x, stage.ground_y);
+            let effect = Effect::RunningDust {
+                direction: velocity.x.signum(),
+            };
+            effect_events.send(EffectEvent::new(effect, position));
+        }
+
         // Store positions for collider_boxes.
         new_velocities[player.index()] = Vec2::new(velocity.x, velocity.y);
     }
 }
 
-/// Handle collision detection.
+/// A hit landed on a player on this tick.
+#[derive(Copy, Clone)]
+struct Hit {
+    damage: u8,
+    callout: Option<Callout>,
+
+    /// True if the hit knocks the player down.
+    knockdown: bool,
+
+    /// Where the hit spark is shown.
+    position: Vec2,
+
+    /// True if the hit was made by a projectile.
+    projectile: bool,
+}
+
+/// Handle collision detection. Hits on both players are worked out before any are applied so
+/// players that hit each other on the same tick trade hits, whatever order they are processed in.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn collision_system(
     mut player_query: Query<(
         &Player,
         &mut CurrentState,
         &mut PreviousState,
-        &CurrentFrame,
+        &mut CurrentFrame,
+        &mut AnimationTimer,
         &mut Health,
+        &Character,
+        &Dash,
+        &mut Recovery,
     )>,
     collider_box_query: Query<(&Parent, &GlobalTransform, &Transform), With<ColliderBox>>,
     attack_box_query: Query<(&Parent, &GlobalTransform, &Transform), With<AttackBox>>,
+    air_attack_box_query: Query<(&Parent, &GlobalTransform, &Transform), With<AirAttackBox>>,
+    mut projectile_query: Query<(&mut Projectile, &Transform)>,
     mut health_update_events: EventWriter<HealthUpdateEvent>,
+    mut effect_events: EventWriter<EffectEvent>,
+    mut callout_events: EventWriter<CalloutEvent>,
+    mut super_meters: ResMut<SuperMeters>,
+    mut damage_dealt: ResMut<DamageDealt>,
+    resimulating: Res<Resimulating>,
 ) {
     // Since we need to check one player's collider with the opponent's attack_box we need to
     // load this information before running the collision detection.
-    let mut players = [(State::default(), State::default(), 0_usize); 2];
-    for (player, current_state, previous_state, current_frame, _health) in &player_query {
-        players[player.index()] = (current_state.0, previous_state.0, current_frame.0);
+    let mut players = [(State::default(), 0_usize, Character::default(), false); 2];
+    for (player, current_state, _, current_frame, _, _, character, dash, _) in &player_query {
+        let invincible = dash.invincible(current_state.0)
+            || is_invincible(character, current_state.0, current_frame.0);
+        players[player.index()] = (current_state.0, current_frame.0, *character, invincible);
     }
 
     let mut collider_boxes = [(Vec3::default(), Vec2::default()); 2];
     for (parent, gt, t) in &collider_box_query {
-        let (player, _, _, _, _) = player_query.get(parent.get()).unwrap();
+        let (player, ..) = player_query.get(parent.get()).unwrap();
         collider_boxes[player.index()] = (gt.translation(), t.scale.truncate());
     }
 
     let mut attack_boxes = [(Vec3::default(), Vec2::default()); 2];
     for (parent, gt, t) in &attack_box_query {
-        let (player, _, _, _, _) = player_query.get(parent.get()).unwrap();
+        let (player, ..) = player_query.get(parent.get()).unwrap();
         attack_boxes[player.index()] = (gt.translation(), t.scale.truncate());
     }
 
-    // Check collision detection.
-    for (player, mut current_state, mut previous_state, _current_frame, mut health) in
-        &mut player_query
-    {
-        match current_state.0 {
-            State::TakingHit | State::Dying => continue,
-            _ => (),
+    let mut air_attack_boxes = [(Vec3::default(), Vec2::default()); 2];
+    for (parent, gt, t) in &air_attack_box_query {
+        let (player, ..) = player_query.get(parent.get()).unwrap();
+        air_attack_boxes[player.index()] = (gt.translation(), t.scale.truncate());
+    }
+
+    let mut projectiles: [Option<(Vec3, Vec2, u8)>; 2] = [None; 2];
+    for (projectile, transform) in &projectile_query {
+        if projectile.active() {
+            projectiles[projectile.owner().index()] = Some((
+                transform.translation,
+                projectile.size(),
+                projectile.damage(),
+            ));
+        }
+    }
+
+    // Work out the hit on each player.
+    let mut hits: [Option<Hit>; 2] = [None; 2];
+    for player in [Player::One, Player::Two] {
+        let (current_state, current_frame, character, invincible) = players[player.index()];
+        if invincible
+            || matches!(
+                current_state,
+                State::TakingHit | State::Dying | State::Thrown
+            )
+        {
+            continue;
         }
 
         let opponent = player.opponent().index();
         let (collider_box_pos, collider_box_size) = collider_boxes[player.index()];
-        let opponent_attack_frame = ATTACK_FRAMES[opponent];
-        let (opponent_current_state, _opponent_previous_state, opponent_current_frame) =
+        let (opponent_current_state, opponent_current_frame, opponent_character, _) =
             players[opponent];
-        let (opponent_attack_box_pos, opponent_attack_box_size) = attack_boxes[opponent];
-        let opponent_attack_damage = ATTACK_DAMAGES[opponent];
-
-        match opponent_current_state {
-            State::Attacking => {
-                if opponent_current_frame == opponent_attack_frame {
-                    if collide(
-                        opponent_attack_box_pos,
+        let multiplier = super_meters.attacks[opponent].damage_multiplier();
+        let knockdown = super_meters.attacks[opponent] != AttackPower::Normal
+            || KNOCKDOWN_STATES[opponent_character.index()].contains(&opponent_current_state);
+        let callout = hit_callout(&character, current_state, current_frame);
+
+        // Attacks only hit on their attack frame. Air attacks have their own box.
+        let opponent_attack = match opponent_current_state {
+            State::Attacking
+                if opponent_current_frame == ATTACK_FRAMES[opponent_character.index()] =>
+            {
+                Some((
+                    attack_boxes[opponent],
+                    ATTACK_DAMAGES[opponent_character.index()] * multiplier,
+                ))
+            }
+            State::AirAttacking
+                if opponent_current_frame == AIR_ATTACK_FRAMES[opponent_character.index()] =>
+            {
+                Some((
+                    air_attack_boxes[opponent],
+                    AIR_ATTACK_DAMAGES[opponent_character.index()] * multiplier,
+                ))
+            }
+            _ => None,
+        };
+
+        if let Some(((opponent_attack_box_pos, opponent_attack_box_size), opponent_attack_damage)) =
+            opponent_attack
+        {
+            if collide(
+                opponent_attack_box_pos,
+                opponent_attack_box_size,
+                collider_box_pos,
+                collider_box_size,
+            )
+            .is_some()
+            {
+                // Spark where the boxes overlap.
+                hits[player.index()] = Some(Hit {
+                    damage: hit_damage(callout, opponent_attack_damage),
+                    callout,
+                    knockdown,
+                    position: overlap_center(
+                        opponent_attack_box_pos.truncate(),
                         opponent_attack_box_size,
-                        collider_box_pos,
+                        collider_box_pos.truncate(),
                         collider_box_size,
-                    )
-                    .is_some()
-                    {
-                        // Switch state to TakingHit.
-                        previous_state.set_state(current_state.0);
-                        current_state.set_state(State::TakingHit);
-
-                        // Just in case damage is not a nice divisior of MAX_HEALTH.
-                        if let Some(h) = health.0.checked_sub(opponent_attack_damage) {
-                            health.0 = h;
-                        } else {
-                            health.0 = 0;
-                        }
-                        health_update_events.send(HealthUpdateEvent::new(*player, health.0));
-                    }
+                    ),
+                    projectile: false,
+                });
+                continue;
+            }
+        }
+
+        // Check the opponent's projectile if the attack didn't hit.
+        if let Some((projectile_pos, projectile_size, projectile_damage)) = projectiles[opponent] {
+            if collide(
+                projectile_pos,
+                projectile_size,
+                collider_box_pos,
+                collider_box_size,
+            )
+            .is_some()
+            {
+                hits[player.index()] = Some(Hit {
+                    damage: hit_damage(callout, projectile_damage),
+                    callout,
+                    knockdown: false,
+                    position: overlap_center(
+                        projectile_pos.truncate(),
+                        projectile_size,
+                        collider_box_pos.truncate(),
+                        collider_box_size,
+                    ),
+                    projectile: true,
+                });
+            }
+        }
+    }
+
+    // Apply the hits.
+    let mut projectile_hits = [false; 2];
+    for (
+        player,
+        mut current_state,
+        mut previous_state,
+        mut current_frame,
+        mut animation_timer,
+        mut health,
+        character,
+        _dash,
+        mut recovery,
+    ) in &mut player_query
+    {
+        let hit = match hits[player.index()] {
+            Some(hit) => hit,
+            None => continue,
+        };
+        let opponent = player.opponent().index();
+
+        take_hit(
+            character,
+            &mut current_state,
+            &mut previous_state,
+            &mut current_frame,
+            &mut animation_timer,
+            &mut recovery,
+            hit.knockdown,
+            hit.callout,
+        );
+
+        // Just in case damage is not a nice divisior of max health.
+        let damage = hit.damage.min(health.0);
+        health.0 -= damage;
+        super_meters.gain(opponent, player.index(), damage);
+        damage_dealt.deal(opponent, damage);
+        health_update_events.send(HealthUpdateEvent::new(*player, health.0, *character));
+        projectile_hits[opponent] |= hit.projectile;
+
+        if !resimulating.0 {
+            effect_events.send(EffectEvent::new(Effect::HitSpark, hit.position));
+            if let Some(callout) = hit.callout {
+                let event = CalloutEvent::new(player.opponent(), callout, hit.position);
+                callout_events.send(event);
+            }
+        }
+    }
+
+    // Projectiles are used up when they hit.
+    for (mut projectile, _) in &mut projectile_query {
+        if projectile_hits[projectile.owner().index()] {
+            projectile.deactivate();
+        }
+    }
+}
+
+/// Switch a player to taking a hit. Heavy hits knock the player down while counter hits and
+/// punishes leave the player stuck for longer.
+#[allow(clippy::too_many_arguments)]
+fn take_hit(
+    character: &Character,
+    current_state: &mut CurrentState,
+    previous_state: &mut PreviousState,
+    current_frame: &mut CurrentFrame,
+    animation_timer: &mut AnimationTimer,
+    recovery: &mut Recovery,
+    knockdown: bool,
+    callout: Option<Callout>,
+) {
+    previous_state.set_state(current_state.0);
+    if knockdown {
+        knock_down(
+            character,
+            current_state,
+            current_frame,
+            animation_timer,
+            recovery,
+        );
+    } else {
+        current_state.set_state(State::TakingHit);
+        recovery.0 = match callout {
+            Some(Callout::Counter) => COUNTER_HITSTUN_TICKS,
+            Some(Callout::Punish) => PUNISH_HITSTUN_TICKS,
+            None => 0,
+        };
+    }
+}
+
+/// Works out if a hit is a counter hit or a punish based on what the player being hit was doing.
+/// Hits before or on a move's active frame are counter hits and hits after it are punishes.
+fn hit_callout(character: &Character, state: State, frame: usize) -> Option<Callout> {
+    let active_frame = match state {
+        State::Attacking => ATTACK_FRAMES[character.index()],
+        State::AirAttacking => AIR_ATTACK_FRAMES[character.index()],
+        State::Special => SPECIAL_FRAMES[character.index()],
+        State::Throwing => THROW_FRAMES[character.index()],
+        State::Recovering => return Some(Callout::Punish),
+        _ => return None,
+    };
+
+    // Until the move's animation starts the player is still in startup.
+    let (start, end) = *FRAMES[character.index()].get(&state).unwrap();
+    if frame < start || frame > end || frame <= active_frame {
+        Some(Callout::Counter)
+    } else {
+        Some(Callout::Punish)
+    }
+}
+
+/// Damage of a hit. Counter hits do half as much again and punishes a quarter.
+fn hit_damage(callout: Option<Callout>, damage: u8) -> u8 {
+    match callout {
+        Some(Callout::Counter) => damage.saturating_add(damage / 2),
+        Some(Callout::Punish) => damage.saturating_add(damage / 4),
+        None => damage,
+    }
+}
+
+/// Knock a player down. The fall is started from its first frame instead of waiting for the
+/// animation timer to catch up with the new state.
+fn knock_down(
+    character: &Character,
+    current_state: &mut CurrentState,
+    current_frame: &mut CurrentFrame,
+    animation_timer: &mut AnimationTimer,
+    recovery: &mut Recovery,
+) {
+    current_state.set_state(State::KnockedDown);
+    current_frame.0 = FRAMES[character.index()]
+        .get(&State::KnockedDown)
+        .unwrap()
+        .0;
+    animation_timer.reset();
+    recovery.0 = KNOCKDOWN_TICKS;
+}
+
+/// Returns true if the character can't be hit or thrown on the given frame of a state. Knocked
+/// down players can't be touched until they are back on their feet.
+fn is_invincible(character: &Character, state: State, frame: usize) -> bool {
+    matches!(state, State::KnockedDown | State::Rising)
+        || matches!(
+            INVINCIBLE_FRAMES[character.index()].get(&state),
+            Some((start, end)) if (*start..=*end).contains(&frame)
+        )
+}
+
+/// Launch projectiles from special moves.
+fn special_system(
+    player_query: Query<(
+        &Player,
+        &CurrentState,
+        &CurrentFrame,
+        &Transform,
+        &Character,
+    )>,
+    mut projectile_query: Query<(&mut Projectile, &mut Transform), Without<Player>>,
+) {
+    for (player, current_state, current_frame, transform, character) in &player_query {
+        for (mut projectile, mut projectile_transform) in &mut projectile_query {
+            if projectile.owner() != *player {
+                continue;
+            }
+
+            // The launch frame lasts a few ticks so only launch once per special move.
+            if current_state.0 != State::Special {
+                projectile.reload();
+            } else if current_frame.0 == SPECIAL_FRAMES[character.index()] && projectile.ready() {
+                projectile.launch(
+                    &mut projectile_transform,
+                    transform.translation,
+                    transform.scale.x,
+                    *character,
+                );
+            }
+        }
+    }
+}
+
+/// Turn players to face each other. Players only turn around on the ground when free to act.
+fn facing_system(mut player_query: Query<(&Player, &CurrentState, &mut Transform, &GroundY)>) {
+    let mut positions = [0.0; 2];
+    for (player, _, transform, _) in player_query.iter() {
+        positions[player.index()] = transform.translation.x;
+    }
+
+    for (player, current_state, mut transform, ground_y) in &mut player_query {
+        let offset = positions[player.opponent().index()] - transform.translation.x;
+        if matches!(current_state.0, State::Idling | State::Running)
+            && transform.translation.y <= ground_y.0
+            && offset != 0.0
+            && transform.scale.x != offset.signum()
+        {
+            transform.scale.x = offset.signum();
+        }
+    }
+}
+
+/// What happens with a player's throw on this tick.
+#[derive(Copy, Clone, PartialEq)]
+enum ThrowOutcome {
+    /// Opponent was grabbed.
+    Grab { switch_sides: bool },
+
+    /// Still holding the opponent.
+    Hold,
+
+    /// Opponent escaped the throw. Also happens when both players grab each other.
+    Tech,
+
+    /// Opponent was thrown.
+    Land,
+}
+
+/// Handle throws. A throw that connects grabs the opponent, who can escape by pressing throw
+/// before the throw lands. Throws can't be blocked. Holding back when grabbing throws the
+/// opponent behind the thrower so the players switch sides.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn throw_system(
+    inputs: Res<FightInputs>,
+    mut player_query: Query<(
+        &Player,
+        &mut CurrentState,
+        &mut PreviousState,
+        &mut CurrentFrame,
+        &mut AnimationTimer,
+        &mut Transform,
+        &GroundY,
+        &mut Velocity,
+        &mut Grab,
+        &mut Health,
+        &Character,
+        &Dash,
+        &mut Recovery,
+    )>,
+    collider_box_query: Query<
+        (&Parent, &GlobalTransform, &Transform),
+        (With<ColliderBox>, Without<Player>),
+    >,
+    throw_box_query: Query<
+        (&Parent, &GlobalTransform, &Transform),
+        (With<ThrowBox>, Without<Player>),
+    >,
+    settings: Res<MatchSettings>,
+    stages: Res<Stages>,
+    mut health_update_events: EventWriter<HealthUpdateEvent>,
+    mut effect_events: EventWriter<EffectEvent>,
+    mut super_meters: ResMut<SuperMeters>,
+    mut damage_dealt: ResMut<DamageDealt>,
+    resimulating: Res<Resimulating>,
+) {
+    let stage = stages.get(settings.stage);
+
+    // Since throws involve both players we need to load their information first.
+    let mut players = [(
+        State::default(),
+        0_usize,
+        Vec3::default(),
+        1.0_f32,
+        false,
+        Character::default(),
+    ); 2];
+    let mut grabs = [(0_u8, false); 2];
+    let mut invincible = [false; 2];
+    for (
+        player,
+        current_state,
+        _,
+        current_frame,
+        _,
+        transform,
+        ground_y,
+        _,
+        grab,
+        _,
+        character,
+        dash,
+        _,
+    ) in &player_query
+    {
+        players[player.index()] = (
+            current_state.0,
+            current_frame.0,
+            transform.translation,
+            transform.scale.x,
+            transform.translation.y <= ground_y.0,
+            *character,
+        );
+        grabs[player.index()] = (grab.ticks, grab.switch_sides);
+        invincible[player.index()] = dash.invincible(current_state.0)
+            || is_invincible(character, current_state.0, current_frame.0);
+    }
+
+    let mut collider_boxes = [(Vec3::default(), Vec2::default()); 2];
+    for (parent, gt, t) in &collider_box_query {
+        let (player, ..) = player_query.get(parent.get()).unwrap();
+        collider_boxes[player.index()] = (gt.translation(), t.scale.truncate());
+    }
+
+    let mut throw_boxes = [(Vec3::default(), Vec2::default()); 2];
+    for (parent, gt, t) in &throw_box_query {
+        let (player, ..) = player_query.get(parent.get()).unwrap();
+        throw_boxes[player.index()] = (gt.translation(), t.scale.truncate());
+    }
+
+    // Work out what happens with each player's throw.
+    let mut outcomes: [Option<ThrowOutcome>; 2] = [None; 2];
+    for player in [Player::One, Player::Two] {
+        let opponent = player.opponent();
+        let (state, frame, _, facing, grounded, character) = players[player.index()];
+        let (opponent_state, _, _, _, opponent_grounded, _) = players[opponent.index()];
+        let (grab_ticks, _) = grabs[player.index()];
+
+        if grab_ticks > 0 {
+            outcomes[player.index()] = Some(if inputs.just_pressed(opponent, PlayerInput::THROW) {
+                ThrowOutcome::Tech
+            } else if grab_ticks > 1 {
+                ThrowOutcome::Hold
+            } else {
+                ThrowOutcome::Land
+            });
+        } else if state == State::Throwing
+            && frame == THROW_FRAMES[character.index()]
+            && grounded
+            && opponent_grounded
+            && !invincible[opponent.index()]
+            && !matches!(
+                opponent_state,
+                State::TakingHit | State::Dying | State::Thrown
+            )
+        {
+            let (throw_box_pos, throw_box_size) = throw_boxes[player.index()];
+            let (collider_box_pos, collider_box_size) = collider_boxes[opponent.index()];
+            if collide(
+                throw_box_pos,
+                throw_box_size,
+                collider_box_pos,
+                collider_box_size,
+            )
+            .is_some()
+            {
+                let back = if facing > 0.0 {
+                    PlayerInput::LEFT
+                } else {
+                    PlayerInput::RIGHT
+                };
+                outcomes[player.index()] = Some(ThrowOutcome::Grab {
+                    switch_sides: inputs.pressed(player, back),
+                });
+            }
+        }
+    }
+
+    // Players grabbing each other on the same tick break each other's throws.
+    if let [Some(ThrowOutcome::Grab { .. }), Some(ThrowOutcome::Grab { .. })] = outcomes {
+        outcomes = [Some(ThrowOutcome::Tech), None];
+    }
+
+    for (
+        player,
+        mut current_state,
+        mut previous_state,
+        mut current_frame,
+        mut animation_timer,
+        mut transform,
+        _ground_y,
+        mut velocity,
+        mut grab,
+        mut health,
+        character,
+        _dash,
+        mut recovery,
+    ) in &mut player_query
+    {
+        let opponent = player.opponent().index();
+
+        // Player's own throw.
+        let (_, _, _, facing, _, _) = players[player.index()];
+        match outcomes[player.index()] {
+            Some(ThrowOutcome::Grab { switch_sides }) => {
+                grab.ticks = THROW_TECH_TICKS;
+                grab.switch_sides = switch_sides;
+            }
+            Some(ThrowOutcome::Hold) => {
+                grab.ticks -= 1;
+            }
+            Some(ThrowOutcome::Tech) => {
+                *grab = Grab::default();
+                current_state.set_state(State::Idling);
+                transform.translation.x = (transform.translation.x - facing * THROW_TECH_PUSH)
+                    .clamp(stage.min_x, stage.max_x);
+
+                if !resimulating.0 {
+                    let (_, _, opponent_pos, _, _, _) = players[opponent];
+                    let position =
+                        (transform.translation.truncate() + opponent_pos.truncate()) / 2.0;
+                    effect_events.send(EffectEvent::new(Effect::HitSpark, position));
                 }
             }
-            _ => (),
+            Some(ThrowOutcome::Land) => {
+                *grab = Grab::default();
+                current_state.set_state(State::Idling);
+            }
+            None => (),
+        }
+
+        // Opponent's throw.
+        let (_, _, thrower_pos, thrower_facing, _, thrower_character) = players[opponent];
+        let (_, switch_sides) = grabs[opponent];
+        match outcomes[opponent] {
+            Some(ThrowOutcome::Grab { .. }) => {
+                velocity.x = 0.0;
+                previous_state.set_from_current(&current_state);
+                current_state.set_state(State::Thrown);
+            }
+            Some(ThrowOutcome::Tech) => {
+                current_state.set_state(State::Idling);
+                transform.translation.x = (transform.translation.x
+                    + thrower_facing * THROW_TECH_PUSH)
+                    .clamp(stage.min_x, stage.max_x);
+            }
+            Some(ThrowOutcome::Land) => {
+                // Land in front of the thrower or behind them when switching sides.
+                let side = if switch_sides {
+                    -thrower_facing
+                } else {
+                    thrower_facing
+                };
+                transform.translation.x =
+                    (thrower_pos.x + side * THROW_DISTANCE).clamp(stage.min_x, stage.max_x);
+                velocity.y = THROW_VELOCITY;
+                previous_state.set_state(State::Idling);
+                knock_down(
+                    character,
+                    &mut current_state,
+                    &mut current_frame,
+                    &mut animation_timer,
+                    &mut recovery,
+                );
+
+                let damage = THROW_DAMAGES[thrower_character.index()].min(health.0);
+                health.0 -= damage;
+                super_meters.gain(opponent, player.index(), damage);
+                damage_dealt.deal(opponent, damage);
+                health_update_events.send(HealthUpdateEvent::new(*player, health.0, *character));
+
+                if !resimulating.0 {
+                    let position = transform.translation.truncate();
+                    effect_events.send(EffectEvent::new(Effect::HitSpark, position));
+                }
+            }
+            Some(ThrowOutcome::Hold) | None => (),
         }
     }
 }
 
+/// Center of the area where two boxes overlap.
+fn overlap_center(a_pos: Vec2, a_size: Vec2, b_pos: Vec2, b_size: Vec2) -> Vec2 {
+    let min = (a_pos - a_size / 2.0).max(b_pos - b_size / 2.0);
+    let max = (a_pos + a_size / 2.0).min(b_pos + b_size / 2.0);
+    (min + max) / 2.0
+}
+
 /// Animate the player sprite.
 fn animation_system(
-    time: Res<Time>,
     mut player_query: Query<
         (
-            &Player,
+            &Character,
             &CurrentState,
             &mut AnimationTimer,
             &mut CurrentFrame,
@@ -639,45 +2182,60 @@ fn animation_system(
     mut sprite_query: Query<(&Parent, &mut TextureAtlasSprite)>,
     assets: Res<GameAssets>,
     audio: Res<Audio>,
+    resimulating: Res<Resimulating>,
 ) {
     for (parent, mut sprite) in &mut sprite_query {
-        let (player, current_state, mut animation_timer, mut current_frame) =
+        let (character, current_state, mut animation_timer, mut current_frame) =
             player_query.get_mut(parent.get()).unwrap();
 
-        animation_timer.tick(time.delta());
+        animation_timer.tick(TICK_DURATION);
         if animation_timer.just_finished() {
-            let (frame, _looped) = next_frame(player, current_state.0, sprite.index);
+            let (frame, _looped) = next_frame(character, current_state.0, sprite.index);
             sprite.index = frame;
             current_frame.0 = frame;
 
-            if sprite.index == ATTACK_AUDIO_FRAMES[player.index()] {
-                match player {
-                    Player::One => {
-                        audio.play(assets.player_one_attack_audio.clone());
-                    }
-                    Player::Two => {
-                        audio.play(assets.player_two_attack_audio.clone());
-                    }
-                }
+            // Sounds were already played the first time these ticks were simulated.
+            if current_state.0 == State::Attacking
+                && sprite.index == ATTACK_AUDIO_FRAMES[character.index()]
+                && !resimulating.0
+            {
+                audio.play(character.attack_audio(&assets));
             }
         }
     }
 }
 
-/// Gets next animation frame for player.
-fn next_frame(player: &Player, state: State, current: usize) -> (usize, bool) {
-    let (start, end) = FRAMES[player.index()].get(&state).unwrap();
+/// Gets next frame of a character's idle animation. Used to animate portraits outside a fight.
+pub(crate) fn next_idle_frame(character: &Character, current: usize) -> usize {
+    next_frame(character, State::Idling, current).0
+}
+
+/// Gets next animation frame for character.
+fn next_frame(character: &Character, state: State, current: usize) -> (usize, bool) {
+    let (start, end) = FRAMES[character.index()].get(&state).unwrap();
     let (frame, looped) = next_player_sprite_frame(current, *start, *end);
 
     match state {
-        State::Dying => {
-            // Don't loop dying animation.
+        State::Dying | State::Victory | State::KnockedDown => {
+            // Don't loop dying animation, victory pose or falling down.
             if looped {
                 (*end, false)
             } else {
                 (frame, looped)
             }
         }
+        State::Thrown => {
+            // Hold the first frame of being hit while held.
+            (*start, false)
+        }
+        State::Rising => {
+            // Get up by playing the fall backwards.
+            if current < *start || current > *end {
+                (*end, false)
+            } else {
+                ((current - 1).max(*start), false)
+            }
+        }
         _ => (frame, looped),
     }
 }
@@ -702,7 +2260,14 @@ fn game_over_system(
     mut countdown_complete_events: EventReader<CountdownCompleteEvent>,
     mut health_update_events: EventReader<HealthUpdateEvent>,
     mut app_state: ResMut<bevy::prelude::State<GameState>>,
+    netplay_config: Option<Res<NetplayConfig>>,
 ) {
+    // Online matches can only end on ticks confirmed by both peers which is handled by the
+    // rollback stage.
+    if netplay_config.is_some() {
+        return;
+    }
+
     let mut game_over = false;
 
     // Check if countdown is complete.
@@ -713,15 +2278,11 @@ fn game_over_system(
         }
     }
 
-    if !game_over {
-        // Check if one player has 0 health.
-        if !health_update_events.is_empty() {
-            for event in health_update_events.iter() {
-                if event.health == 0 {
-                    game_over = true;
-                    break;
-                }
-            }
+    // Check if either player has 0 health. Every event is read so both players being knocked out
+    // on the same tick ends the round in a draw.
+    for event in health_update_events.iter() {
+        if event.health == 0 {
+            game_over = true;
         }
     }
 
@@ -731,9 +2292,135 @@ fn game_over_system(
     }
 }
 
+/// Have the winner of a knock out strike a victory pose once they're on the ground.
+fn victory_system(
+    knock_out: Option<Res<KnockOut>>,
+    mut player_query: Query<(&Player, &mut CurrentState, &Transform, &GroundY)>,
+) {
+    let winner = match knock_out.and_then(|knock_out| knock_out.posing()) {
+        Some(winner) => winner,
+        None => return,
+    };
+
+    for (player, mut current_state, transform, ground_y) in &mut player_query {
+        if *player == winner
+            && transform.translation.y <= ground_y.0
+            && current_state.0 != State::Victory
+        {
+            current_state.0 = State::Victory;
+        }
+    }
+}
+
 /// Cleanup resources.
 fn cleanup(mut commands: Commands, entity_data: Res<EntityData>) {
     for entity in entity_data.entities.iter() {
         commands.entity(*entity).despawn_recursive();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(snapshot: &PlayerSnapshot) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+        snapshot.encode(&mut writer);
+        writer.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<PlayerSnapshot> {
+        PlayerSnapshot::decode(&mut StateReader::new(bytes))
+    }
+
+    #[test]
+    fn every_state_survives_encoding() {
+        for (i, state) in State::ALL.iter().enumerate() {
+            let snapshot = PlayerSnapshot {
+                current_state: *state,
+                previous_state: State::ALL[(i + 1) % State::ALL.len()],
+                ..default()
+            };
+            assert_eq!(decode(&encode(&snapshot)), Some(snapshot));
+        }
+    }
+
+    #[test]
+    fn animation_is_restored_exactly() {
+        let snapshot = PlayerSnapshot {
+            translation: Vec3::new(-120.5, 33.25, 1.0),
+            velocity: Vec3::new(3.0, -9.5, 0.0),
+            current_frame: 300,
+            health: 55,
+            animation_elapsed: Duration::from_nanos(16_666_667),
+            ..default()
+        };
+        assert_eq!(decode(&encode(&snapshot)), Some(snapshot));
+    }
+
+    #[test]
+    fn rejects_unknown_states() {
+        let mut bytes = encode(&PlayerSnapshot::default());
+        let jumping = encode(&PlayerSnapshot {
+            current_state: State::Jumping,
+            ..default()
+        });
+
+        // The state is the only byte that changes.
+        let i = bytes
+            .iter()
+            .zip(&jumping)
+            .position(|(a, b)| a != b)
+            .unwrap();
+        bytes[i] = u8::MAX;
+        assert_eq!(decode(&bytes), None);
+    }
+
+    #[test]
+    fn every_character_has_a_knockdown_move() {
+        for character in Character::ALL {
+            assert!(!KNOCKDOWN_STATES[character.index()].is_empty());
+        }
+    }
+
+    #[test]
+    fn knocked_down_players_cant_be_hit() {
+        for character in Character::ALL.iter() {
+            for frame in 0..100 {
+                assert!(is_invincible(character, State::KnockedDown, frame));
+                assert!(is_invincible(character, State::Rising, frame));
+                assert!(!is_invincible(character, State::Idling, frame));
+            }
+        }
+    }
+
+    #[test]
+    fn knockdowns_restart_the_fall() {
+        let mut current_state = CurrentState(State::Attacking);
+        let mut previous_state = PreviousState(State::Idling);
+        let mut current_frame = CurrentFrame(3);
+        let mut animation_timer = AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating));
+        animation_timer.tick(Duration::from_millis(80));
+        let mut recovery = Recovery(0);
+
+        take_hit(
+            &Character::Mack,
+            &mut current_state,
+            &mut previous_state,
+            &mut current_frame,
+            &mut animation_timer,
+            &mut recovery,
+            true,
+            None,
+        );
+
+        assert_eq!(current_state.0, State::KnockedDown);
+        assert_eq!(previous_state.0, State::Attacking);
+        assert_eq!(
+            current_frame.0,
+            FRAMES[Character::Mack.index()][&State::KnockedDown].0
+        );
+        assert_eq!(animation_timer.elapsed(), Duration::ZERO);
+        assert_eq!(recovery.0, KNOCKDOWN_TICKS);
+    }
+}
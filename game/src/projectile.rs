@@ -0,0 +1,274 @@
+//! Projectile
+
+use crate::{
+    common::*, Character, Effect, EffectEvent, MatchSettings, Player, Resimulating, Stages,
+    StateReader, StateWriter,
+};
+use bevy::{prelude::*, sprite::collide_aabb::collide};
+
+/// Speed of each character's projectile.
+const PROJECTILE_SPEEDS: [f32; 2] = [5.0 * 100.0, 7.0 * 100.0];
+
+/// Damage of each character's projectile.
+const PROJECTILE_DAMAGES: [u8; 2] = [8, 6];
+
+/// Number of ticks a projectile lasts before fizzling out.
+const PROJECTILE_TICKS: u16 = 120;
+
+/// Size of a projectile's sprite and hitbox.
+const PROJECTILE_SIZE: Vec2 = Vec2::new(40.0, 28.0);
+
+/// Height a projectile is launched at above the player's position.
+const PROJECTILE_Y: f32 = 20.0;
+
+/// Distance in front of the player a projectile is launched at.
+const PROJECTILE_X: f32 = 90.0;
+
+/// Color of each character's projectile.
+const PROJECTILE_COLORS: [Color; 2] = [Color::rgb(1.0, 0.55, 0.2), Color::rgb(0.4, 0.8, 1.0)];
+
+/// Number of ticks each animation frame is shown for.
+const FRAME_TICKS: u16 = 4;
+
+/// Scale of the projectile's sprite on each animation frame.
+const FRAME_SCALES: [f32; 4] = [1.0, 1.15, 1.3, 1.15];
+
+/// Represents a player's projectile. Each player has one which is hidden until launched so a
+/// player can only have one projectile out at a time.
+#[derive(Component)]
+pub(crate) struct Projectile {
+    owner: Player,
+    active: bool,
+
+    /// Set while the special move that launched the projectile plays so it only launches once.
+    launched: bool,
+    velocity: f32,
+
+    /// Ticks left before fizzling out.
+    ticks: u16,
+    damage: u8,
+    character: Character,
+}
+
+impl Projectile {
+    /// Returns true if the projectile is out.
+    pub(crate) fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Returns true if the projectile can be launched by the current special move.
+    pub(crate) fn ready(&self) -> bool {
+        !self.active && !self.launched
+    }
+
+    /// Allow the projectile to be launched again once the special move that launched it is over.
+    pub(crate) fn reload(&mut self) {
+        self.launched = false;
+    }
+
+    /// Player the projectile belongs to.
+    pub(crate) fn owner(&self) -> Player {
+        self.owner
+    }
+
+    /// Damage dealt by the projectile.
+    pub(crate) fn damage(&self) -> u8 {
+        self.damage
+    }
+
+    /// Size of the projectile's hitbox.
+    pub(crate) fn size(&self) -> Vec2 {
+        PROJECTILE_SIZE
+    }
+
+    /// Launch the projectile in front of a player.
+    pub(crate) fn launch(
+        &mut self,
+        transform: &mut Transform,
+        position: Vec3,
+        facing: f32,
+        character: Character,
+    ) {
+        self.active = true;
+        self.launched = true;
+        self.velocity = PROJECTILE_SPEEDS[character.index()] * facing;
+        self.ticks = PROJECTILE_TICKS;
+        self.damage = PROJECTILE_DAMAGES[character.index()];
+        self.character = character;
+        transform.translation.x = position.x + PROJECTILE_X * facing;
+        transform.translation.y = position.y + PROJECTILE_Y;
+    }
+
+    /// Remove the projectile from the fight.
+    pub(crate) fn deactivate(&mut self) {
+        self.active = false;
+    }
+
+    /// Animation frame based on how long the projectile has been out.
+    fn frame(&self) -> usize {
+        ((PROJECTILE_TICKS - self.ticks) / FRAME_TICKS) as usize % FRAME_SCALES.len()
+    }
+}
+
+/// Snapshot of a projectile's fight state.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct ProjectileSnapshot {
+    pub(crate) active: bool,
+    pub(crate) launched: bool,
+    pub(crate) translation: Vec3,
+    pub(crate) velocity: f32,
+    pub(crate) ticks: u16,
+    pub(crate) damage: u8,
+    pub(crate) character: Character,
+}
+
+impl ProjectileSnapshot {
+    /// Encode the snapshot.
+    pub(crate) fn encode(&self, writer: &mut StateWriter) {
+        writer.bool(self.active);
+        writer.bool(self.launched);
+        writer.vec3(self.translation);
+        writer.f32(self.velocity);
+        writer.u16(self.ticks);
+        writer.u8(self.damage);
+        writer.u8(self.character.index() as u8);
+    }
+
+    /// Decode a snapshot.
+    pub(crate) fn decode(reader: &mut StateReader) -> Option<Self> {
+        Some(Self {
+            active: reader.bool()?,
+            launched: reader.bool()?,
+            translation: reader.vec3()?,
+            velocity: reader.f32()?,
+            ticks: reader.u16()?,
+            damage: reader.u8()?,
+            character: Character::from_index(reader.u8()? as usize)?,
+        })
+    }
+}
+
+/// Save the fight state of both players' projectiles.
+pub(crate) fn save_projectiles(world: &mut World) -> [ProjectileSnapshot; 2] {
+    let mut snapshots = [ProjectileSnapshot::default(); 2];
+
+    let mut query = world.query::<(&Projectile, &Transform)>();
+    for (projectile, transform) in query.iter(world) {
+        snapshots[projectile.owner.index()] = ProjectileSnapshot {
+            active: projectile.active,
+            launched: projectile.launched,
+            translation: transform.translation,
+            velocity: projectile.velocity,
+            ticks: projectile.ticks,
+            damage: projectile.damage,
+            character: projectile.character,
+        };
+    }
+
+    snapshots
+}
+
+/// Restore the fight state of both players' projectiles.
+pub(crate) fn restore_projectiles(world: &mut World, snapshots: &[ProjectileSnapshot; 2]) {
+    let mut query = world.query::<(
+        &mut Projectile,
+        &mut Transform,
+        &mut Sprite,
+        &mut Visibility,
+    )>();
+    for (mut projectile, mut transform, mut sprite, mut visibility) in query.iter_mut(world) {
+        let snapshot = &snapshots[projectile.owner.index()];
+        projectile.active = snapshot.active;
+        projectile.launched = snapshot.launched;
+        projectile.velocity = snapshot.velocity;
+        projectile.ticks = snapshot.ticks;
+        projectile.damage = snapshot.damage;
+        projectile.character = snapshot.character;
+        transform.translation = snapshot.translation;
+        show(&projectile, &mut sprite, &mut visibility);
+    }
+}
+
+/// Spawn a player's projectile. It's hidden until launched.
+pub(crate) fn spawn_projectile(commands: &mut Commands, player: Player) -> Entity {
+    commands
+        .spawn(Projectile {
+            owner: player,
+            active: false,
+            launched: false,
+            velocity: 0.0,
+            ticks: 0,
+            damage: 0,
+            character: Character::default(),
+        })
+        .insert(SpriteBundle {
+            visibility: Visibility { is_visible: false },
+            transform: Transform::from_xyz(0.0, 0.0, PLAYER_Z + 0.05),
+            ..default()
+        })
+        .id()
+}
+
+/// Show a projectile's current animation frame.
+fn show(projectile: &Projectile, sprite: &mut Sprite, visibility: &mut Visibility) {
+    visibility.is_visible = projectile.active;
+    sprite.color = PROJECTILE_COLORS[projectile.character.index()];
+    sprite.custom_size = Some(PROJECTILE_SIZE * FRAME_SCALES[projectile.frame()]);
+}
+
+/// Move projectiles until they fizzle out or leave the stage. Projectiles that run into each other
+/// cancel out.
+pub(crate) fn projectile_system(
+    mut projectile_query: Query<(
+        &mut Projectile,
+        &mut Transform,
+        &mut Sprite,
+        &mut Visibility,
+    )>,
+    settings: Res<MatchSettings>,
+    stages: Res<Stages>,
+    mut effect_events: EventWriter<EffectEvent>,
+    resimulating: Res<Resimulating>,
+) {
+    let stage = stages.get(settings.stage);
+    let delta_time = TICK_DURATION.as_secs_f32();
+
+    let mut positions: [Option<Vec3>; 2] = [None; 2];
+    for (mut projectile, mut transform, _, _) in &mut projectile_query {
+        if !projectile.active {
+            continue;
+        }
+
+        transform.translation.x += projectile.velocity * delta_time;
+        projectile.ticks = projectile.ticks.saturating_sub(1);
+
+        let half_width = PROJECTILE_SIZE.x / 2.0;
+        if projectile.ticks == 0
+            || transform.translation.x < stage.min_x - half_width
+            || transform.translation.x > stage.max_x + half_width
+        {
+            projectile.active = false;
+        } else {
+            positions[projectile.owner.index()] = Some(transform.translation);
+        }
+    }
+
+    // Opposing projectiles cancel out.
+    let cancelled = match positions {
+        [Some(a), Some(b)] => collide(a, PROJECTILE_SIZE, b, PROJECTILE_SIZE).is_some(),
+        _ => false,
+    };
+    if cancelled && !resimulating.0 {
+        if let [Some(a), Some(b)] = positions {
+            let position = (a.truncate() + b.truncate()) / 2.0;
+            effect_events.send(EffectEvent::new(Effect::HitSpark, position));
+        }
+    }
+
+    for (mut projectile, _, mut sprite, mut visibility) in &mut projectile_query {
+        if cancelled {
+            projectile.active = false;
+        }
+        show(&projectile, &mut sprite, &mut visibility);
+    }
+}
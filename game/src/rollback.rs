@@ -0,0 +1,488 @@
+//! Rollback
+
+use crate::{
+    common::*, local_input_system, DamageDealt, FightInputs, FightState, GameState, LocalInputs,
+    NetSession, NetStats, NetTransport, NetplayConfig, NetplayOptions, PlayerInput, RoundScore,
+    Spectating, SpectatorServer, SpectatorSession, UdpTransport, MAX_PREDICTION,
+};
+use bevy::prelude::*;
+use std::time::Duration;
+
+/// Maximum number of ticks simulated in a single frame so a slow frame doesn't snowball.
+const MAX_TICKS_PER_FRAME: u32 = 4;
+
+/// Number of fight states kept for rolling back. A rollback never goes further back than the
+/// maximum prediction.
+const SNAPSHOT_COUNT: usize = MAX_PREDICTION as usize + 2;
+
+/// Label for the stage running the fight simulation.
+#[derive(StageLabel)]
+pub(crate) struct FightStage;
+
+/// Runs the fight simulation at a fixed tick rate and, during online matches, rolls back and
+/// resimulates ticks that were simulated with a wrong prediction of the peer's input.
+pub(crate) struct RollbackPlugin;
+
+impl Plugin for RollbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LocalInputs>()
+            .init_resource::<FightInputs>()
+            .init_resource::<FightClock>()
+            .init_resource::<Resimulating>()
+            .init_resource::<TimeScale>()
+            .init_resource::<Freeze>()
+            .init_resource::<RoundScore>()
+            .init_resource::<DamageDealt>()
+            .add_stage_after(CoreStage::Update, FightStage, RollbackStage::new())
+            .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(setup))
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame).with_system(local_input_system),
+            );
+    }
+}
+
+/// Set to true while ticks are being resimulated after a rollback. Used to avoid repeating side
+/// effects like sounds.
+#[derive(Resource, Default)]
+pub(crate) struct Resimulating(pub(crate) bool);
+
+/// Speed the fight is played at. Slowed down to make moments like a knock out stand out.
+#[derive(Resource)]
+pub(crate) struct TimeScale(pub(crate) f32);
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// Number of ticks the fight stays frozen for. Frozen ticks still count but nothing moves.
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Freeze(pub(crate) u8);
+
+/// Keeps track of simulation ticks.
+#[derive(Resource, Default)]
+pub(crate) struct FightClock {
+    /// Next tick to be simulated.
+    pub(crate) tick: u32,
+
+    /// Frame time not yet consumed by a tick.
+    accumulator: Duration,
+
+    /// True once the stage has started the match.
+    started: bool,
+}
+
+/// Result of trying to advance the simulation by a tick.
+enum Advance {
+    /// Tick was simulated.
+    Ticked,
+
+    /// Waiting on the peer.
+    Stalled,
+
+    /// Fight is over.
+    Finished,
+}
+
+/// Stage that owns the fight simulation systems.
+pub(crate) struct RollbackStage {
+    schedule: SystemStage,
+    session: Option<NetSession>,
+    snapshots: Vec<Option<FightState>>,
+    spectator: Option<SpectatorSession>,
+    spectator_server: Option<SpectatorServer>,
+}
+
+impl RollbackStage {
+    pub(crate) fn new() -> Self {
+        Self {
+            // Ticks must play out the same way every time they are simulated so systems run one
+            // at a time in a fixed order.
+            schedule: SystemStage::single_threaded(),
+            session: None,
+            snapshots: vec![None; SNAPSHOT_COUNT],
+            spectator: None,
+            spectator_server: None,
+        }
+    }
+
+    /// Add systems that run once per simulation tick.
+    pub(crate) fn add_system_set(&mut self, system_set: SystemSet) -> &mut Self {
+        self.schedule.add_system_set(system_set);
+        self
+    }
+
+    /// Start a new match. Online matches take the transport for the duration of the match.
+    fn start_match(&mut self, world: &mut World) {
+        self.snapshots.iter_mut().for_each(|s| *s = None);
+
+        // Spectators play back the match they watch instead of playing one.
+        if let Some(spectating) = world.get_resource::<Spectating>().cloned() {
+            let options = world.resource::<NetplayOptions>();
+            match UdpTransport::bind(0, spectating.host) {
+                Ok(udp) => {
+                    let transport = options.condition(udp, spectating.host.port() as u32);
+                    self.spectator = Some(SpectatorSession::new(transport));
+                    world.insert_resource(NetStats::default());
+                }
+                Err(err) => {
+                    // Shown the same way as losing the connection to the match.
+                    error!("Couldn't open spectator socket: {}", err);
+                    world.insert_resource(NetStats {
+                        disconnected: true,
+                        ..default()
+                    });
+                    world
+                        .resource_mut::<State<GameState>>()
+                        .set(GameState::GameOver)
+                        .expect("Couldn't switch state to GameOver");
+                }
+            }
+            return;
+        }
+
+        // Let spectators watch the match if enabled.
+        if let Some(port) = world.resource::<NetplayOptions>().spectate_port {
+            if self.spectator_server.is_none() {
+                match SpectatorServer::bind(port) {
+                    Ok(server) => self.spectator_server = Some(server),
+                    Err(err) => error!("Couldn't open spectator port {}: {}", port, err),
+                }
+            }
+        }
+        if let Some(server) = self.spectator_server.as_mut() {
+            server.start_match();
+        }
+
+        let config = world.get_resource::<NetplayConfig>().cloned();
+        let transport = world.remove_resource::<NetTransport>();
+        match (config, transport) {
+            (Some(config), Some(NetTransport(transport))) => {
+                self.session = Some(NetSession::new(transport, &config));
+                world.insert_resource(NetStats::default());
+
+                // The lobby picks the next match id. Direct matches just count up.
+                if !config.from_lobby {
+                    world.resource_mut::<NetplayConfig>().match_id =
+                        config.match_id.wrapping_add(1);
+                }
+            }
+            (_, transport) => {
+                if let Some(transport) = transport {
+                    world.insert_resource(transport);
+                }
+                world.remove_resource::<NetStats>();
+            }
+        }
+    }
+
+    /// End the match and return the transport.
+    fn end_match(&mut self, world: &mut World) {
+        if let Some(session) = self.session.take() {
+            world.insert_resource(NetTransport(session.into_transport()));
+        }
+        self.spectator = None;
+    }
+
+    /// Saved state for the start of a tick.
+    fn snapshot(&self, tick: u32) -> Option<&FightState> {
+        self.snapshots[tick as usize % SNAPSHOT_COUNT]
+            .as_ref()
+            .filter(|state| state.tick == tick)
+    }
+
+    /// State for the start of the current tick or of one of the saved earlier ticks.
+    fn state_at(&self, world: &mut World, tick: u32) -> Option<FightState> {
+        if tick == world.resource::<FightClock>().tick {
+            Some(FightState::save(world, tick))
+        } else {
+            self.snapshot(tick).cloned()
+        }
+    }
+
+    /// Save the state for the start of a tick.
+    fn save_snapshot(&mut self, world: &mut World, tick: u32) {
+        self.snapshots[tick as usize % SNAPSHOT_COUNT] = Some(FightState::save(world, tick));
+    }
+
+    /// Simulate a single tick with the given inputs. The clock is set to the tick first so systems
+    /// see the same tick whether it's simulated for the first time or resimulated.
+    fn run_tick(&mut self, world: &mut World, tick: u32, inputs: [PlayerInput; 2]) {
+        world.resource_mut::<FightClock>().tick = tick;
+
+        // Inputs made while frozen are picked up once the fight resumes.
+        let mut freeze = world.resource_mut::<Freeze>();
+        if freeze.0 > 0 {
+            freeze.0 -= 1;
+            return;
+        }
+
+        let fight_inputs = world.resource::<FightInputs>().next(inputs);
+        world.insert_resource(fight_inputs);
+        self.schedule.run(world);
+    }
+
+    /// Advance a local match by a tick.
+    fn advance_local(&mut self, world: &mut World) -> Advance {
+        let tick = world.resource::<FightClock>().tick;
+        let inputs = world.resource::<LocalInputs>().0;
+        self.run_tick(world, tick, inputs);
+        world.resource_mut::<FightClock>().tick = tick + 1;
+        Advance::Ticked
+    }
+
+    /// Advance an online match by a tick.
+    fn advance_online(&mut self, world: &mut World, mut session: NetSession) -> NetSession {
+        session.poll();
+        if !session.is_synchronized() {
+            return session;
+        }
+        world.resource_mut::<NetStats>().synchronized = true;
+
+        let tick = world.resource::<FightClock>().tick;
+
+        // Resimulate from the earliest tick where the peer's input was mispredicted.
+        if let Some(rollback_tick) = session.take_rollback_tick() {
+            if let Some(state) = self.snapshot(rollback_tick).cloned() {
+                state.restore(world);
+                world.resource_mut::<Resimulating>().0 = true;
+                for t in rollback_tick..tick {
+                    if t > rollback_tick {
+                        self.save_snapshot(world, t);
+                    }
+                    let inputs = session.inputs(t);
+                    self.run_tick(world, t, inputs);
+                }
+                world.resource_mut::<Resimulating>().0 = false;
+                world.resource_mut::<NetStats>().rollbacks += 1;
+            }
+        }
+
+        if session.should_stall(tick) {
+            session.send_inputs();
+            return session;
+        }
+
+        let local_input = world.resource::<LocalInputs>().0[session.local_player().index()];
+        session.add_local_input(tick, local_input);
+
+        self.save_snapshot(world, tick);
+        let inputs = session.inputs(tick);
+        self.run_tick(world, tick, inputs);
+        world.resource_mut::<FightClock>().tick = tick + 1;
+
+        let confirmed_tick = session.confirmed_tick().min(tick + 1);
+        let mut stats = world.resource_mut::<NetStats>();
+        stats.prediction = tick + 1 - confirmed_tick;
+        stats.ping = session.rtt();
+
+        session
+    }
+
+    /// Ends the fight if the peer left or stopped responding.
+    fn check_disconnected(&mut self, world: &mut World) -> bool {
+        match &self.session {
+            Some(session) if session.is_disconnected() => {
+                warn!("Peer disconnected");
+                world.resource_mut::<NetStats>().disconnected = true;
+                world
+                    .resource_mut::<State<GameState>>()
+                    .set(GameState::GameOver)
+                    .expect("Couldn't switch state to GameOver");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks if the fight is over as of the last tick confirmed by both peers. Both peers will
+    /// see the same confirmed state so they end the fight on the same tick.
+    fn check_confirmed_game_over(&mut self, world: &mut World) -> bool {
+        let session = match &self.session {
+            Some(session) => session,
+            None => return false,
+        };
+
+        let tick = world.resource::<FightClock>().tick;
+        let confirmed_tick = session.confirmed_tick().min(tick);
+
+        match self.state_at(world, confirmed_tick) {
+            Some(state) if state.is_over() => {
+                // Drop any predicted ticks so both peers show exactly the same ending.
+                if confirmed_tick < tick {
+                    state.restore(world);
+                    world.resource_mut::<FightClock>().tick = confirmed_tick;
+                }
+                world
+                    .resource_mut::<State<GameState>>()
+                    .set(GameState::GameOver)
+                    .expect("Couldn't switch state to GameOver");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Advance a watched match by a tick.
+    fn advance_spectator(
+        &mut self,
+        world: &mut World,
+        spectator: &mut SpectatorSession,
+    ) -> Advance {
+        spectator.poll();
+        if spectator.is_disconnected() {
+            warn!("Lost connection to match");
+            world.resource_mut::<NetStats>().disconnected = true;
+            world
+                .resource_mut::<State<GameState>>()
+                .set(GameState::GameOver)
+                .expect("Couldn't switch state to GameOver");
+            return Advance::Finished;
+        }
+
+        // Joining mid-match starts from a snapshot.
+        if let Some(state) = spectator.take_state() {
+            state.restore(world);
+            world.resource_mut::<FightClock>().tick = state.tick;
+            world.resource_mut::<NetStats>().synchronized = true;
+        }
+
+        let tick = world.resource::<FightClock>().tick;
+        match spectator.inputs(tick) {
+            Some(inputs) => {
+                self.run_tick(world, tick, inputs);
+                world.resource_mut::<FightClock>().tick = tick + 1;
+                Advance::Ticked
+            }
+            None => Advance::Stalled,
+        }
+    }
+
+    /// Record confirmed inputs for spectators and send them what they are missing.
+    fn serve_spectators(&mut self, world: &mut World, game_state: &GameState) {
+        let mut server = match self.spectator_server.take() {
+            Some(server) => server,
+            None => return,
+        };
+
+        // Online inputs are recorded once confirmed. Local inputs are recorded as they are played.
+        if let (GameState::InGame, Some(session)) = (game_state, self.session.as_mut()) {
+            let confirmed_tick = session
+                .confirmed_tick()
+                .min(world.resource::<FightClock>().tick);
+            while server.recorded_ticks() < confirmed_tick {
+                server.record(session.inputs(server.recorded_ticks()));
+            }
+        }
+
+        // Snapshots are only available while the match is being recorded.
+        let needs_snapshot = server.poll();
+        let snapshot = match game_state {
+            GameState::InGame if needs_snapshot => self.state_at(world, server.recorded_ticks()),
+            _ => None,
+        };
+        server.send(snapshot.as_ref());
+
+        self.spectator_server = Some(server);
+    }
+}
+
+impl Stage for RollbackStage {
+    fn run(&mut self, world: &mut World) {
+        let game_state = world.resource::<State<GameState>>().current().clone();
+        match game_state {
+            GameState::InGame | GameState::GameOver => (),
+            _ => {
+                self.end_match(world);
+                return;
+            }
+        }
+
+        if !world.resource::<FightClock>().started {
+            self.start_match(world);
+            world.resource_mut::<FightClock>().started = true;
+        }
+
+        // Nothing to play back if the spectator couldn't connect.
+        if self.spectator.is_none() && world.contains_resource::<Spectating>() {
+            return;
+        }
+
+        // Work out how many ticks fit in the time since the last frame.
+        let delta = world
+            .resource::<Time>()
+            .delta()
+            .mul_f32(world.resource::<TimeScale>().0);
+        let mut ticks = 0;
+        {
+            let mut clock = world.resource_mut::<FightClock>();
+            clock.accumulator += delta;
+            while clock.accumulator >= TICK_DURATION && ticks < MAX_TICKS_PER_FRAME {
+                clock.accumulator -= TICK_DURATION;
+                ticks += 1;
+            }
+            if ticks == MAX_TICKS_PER_FRAME {
+                clock.accumulator = clock.accumulator.min(TICK_DURATION);
+            }
+        }
+
+        for _ in 0..ticks {
+            let advance = match (game_state.clone(), self.session.take()) {
+                (GameState::InGame, session) if self.spectator.is_some() => {
+                    self.session = session;
+                    let mut spectator = self.spectator.take().unwrap();
+                    let advance = self.advance_spectator(world, &mut spectator);
+                    self.spectator = Some(spectator);
+                    advance
+                }
+                (GameState::InGame, Some(session)) => {
+                    let tick = world.resource::<FightClock>().tick;
+                    self.session = Some(self.advance_online(world, session));
+                    if self.check_disconnected(world) || self.check_confirmed_game_over(world) {
+                        Advance::Finished
+                    } else if world.resource::<FightClock>().tick == tick {
+                        Advance::Stalled
+                    } else {
+                        Advance::Ticked
+                    }
+                }
+                (GameState::GameOver, Some(mut session)) => {
+                    // Keep resending our inputs in case the peer hasn't confirmed the end yet.
+                    session.poll();
+                    session.send_inputs();
+                    self.session = Some(session);
+                    self.advance_local(world)
+                }
+                (_, session) => {
+                    self.session = session;
+                    if let (GameState::InGame, Some(server)) =
+                        (&game_state, self.spectator_server.as_mut())
+                    {
+                        server.record(world.resource::<LocalInputs>().0);
+                    }
+                    self.advance_local(world)
+                }
+            };
+
+            match advance {
+                Advance::Ticked => (),
+                Advance::Stalled => {
+                    world.resource_mut::<FightClock>().accumulator = Duration::ZERO;
+                    break;
+                }
+                Advance::Finished => break,
+            }
+        }
+
+        self.serve_spectators(world, &game_state);
+    }
+}
+
+/// Reset the simulation for a new match.
+fn setup(mut commands: Commands) {
+    commands.insert_resource(FightClock::default());
+    commands.insert_resource(FightInputs::default());
+    commands.insert_resource(Freeze::default());
+    commands.insert_resource(DamageDealt::default());
+}
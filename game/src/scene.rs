@@ -1,22 +1,7 @@
 //! Scene
 
-use crate::{common::*, GameAssets, GameState};
-use bevy::{app::Plugin, prelude::*};
-
-/// Scaling factor for background sprite.
-const BG_SCALE: f32 = 3.2;
-
-/// Scaling factor for shop sprite.
-const SHOP_SCALE: f32 = 2.85;
-
-/// Ground location along y-axis.
-pub(crate) const GROUND_Y: f32 = -66.0 * BG_SCALE;
-
-/// Scene minimum x bounds
-pub(crate) const SCENE_MIN_X: f32 = -WINDOW_WIDTH as f32 / 2.0 + 30.0;
-
-/// Scene maximum x bounds
-pub(crate) const SCENE_MAX_X: f32 = WINDOW_WIDTH as f32 / 2.0 - 25.0;
+use crate::{common::*, GameState, MatchSettings, StageCamera, Stages};
+use bevy::{app::Plugin, ecs::system::CommandQueue, prelude::*};
 
 /// Handles the game scene assets.
 pub(crate) struct ScenePlugin;
@@ -24,14 +9,26 @@ pub(crate) struct ScenePlugin;
 impl Plugin for ScenePlugin {
     fn build(&self, app: &mut App) {
         app
-            // Setup the scene when entering main menu.
-            .add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(setup))
+            // Setup the scene once stages are loaded and rebuild it when another stage is picked.
+            .add_system(setup)
+            .add_system(parallax_system)
             // Run animation system in all game states.
             .add_system_set(SystemSet::on_update(GameState::MainMenu).with_system(animation_system))
+            .add_system_set(SystemSet::on_update(GameState::Lobby).with_system(animation_system))
+            .add_system_set(
+                SystemSet::on_update(GameState::CharacterSelect).with_system(animation_system),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::StageSelect).with_system(animation_system),
+            )
             .add_system_set(SystemSet::on_update(GameState::InGame).with_system(animation_system))
             .add_system_set(SystemSet::on_update(GameState::GameOver).with_system(animation_system))
-            // Cleanup resources on leaving game over state.
-            .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(cleanup));
+            .add_system_set(
+                SystemSet::on_update(GameState::ArcadeContinue).with_system(animation_system),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::ArcadeEnding).with_system(animation_system),
+            );
     }
 }
 
@@ -39,55 +36,148 @@ impl Plugin for ScenePlugin {
 #[derive(Resource)]
 struct EntityData {
     entities: Vec<Entity>,
+
+    /// Stage the scene was built for.
+    stage: u8,
 }
 
-/// Represents the shop sprite.
+/// Represents an animated stage prop.
 #[derive(Component)]
-struct Shop;
+struct Prop;
 
-/// Setup the scene.
-fn setup(mut commands: Commands, assets: Res<GameAssets>) {
-    let mut entities: Vec<Entity> = Vec::new();
+/// Background layer that scrolls slower than the camera.
+#[derive(Component)]
+struct Parallax {
+    position: Vec2,
+    factor: f32,
+}
 
-    // Setup camera.
-    entities.push(commands.spawn(Camera2dBundle::default()).id());
+/// Setup the scene.
+fn setup(
+    mut commands: Commands,
+    stages: Option<Res<Stages>>,
+    settings: Res<MatchSettings>,
+    entity_data: Option<Res<EntityData>>,
+) {
+    if let Some(stages) = stages {
+        build(&mut commands, &stages, &settings, entity_data.as_deref());
+    }
+}
 
-    // Background sprite.
-    entities.push(
-        commands
-            .spawn(SpriteBundle {
-                texture: assets.background_image.clone(),
-                transform: Transform::from_xyz(0.0, 0.0, 0.0)
-                    .with_scale(Vec3::new(BG_SCALE, BG_SCALE, BG_Z)),
-                ..default()
-            })
-            .id(),
+/// Rebuild the scene right away if the stage has changed. A restored fight state can change the
+/// stage in the middle of a frame and the players are placed on the new stage straight away.
+pub(crate) fn rebuild_scene(world: &mut World) {
+    let mut queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut queue, world);
+    build(
+        &mut commands,
+        world.resource::<Stages>(),
+        world.resource::<MatchSettings>(),
+        world.get_resource::<EntityData>(),
     );
+    queue.apply(world);
+}
 
-    // Animated shop sprite.
-    entities.push(
-        commands
-            .spawn(Shop)
-            .insert(SpriteSheetBundle {
-                texture_atlas: assets.shop_texture_atlas.clone(),
-                transform: Transform {
-                    translation: Vec3::new(280.0, -28.5, BG_Z + 0.01),
-                    scale: Vec3::new(SHOP_SCALE, SHOP_SCALE, 1.0),
+/// Build the scene for the current stage.
+fn build(
+    commands: &mut Commands,
+    stages: &Stages,
+    settings: &MatchSettings,
+    entity_data: Option<&EntityData>,
+) {
+    // Scene is kept as long as the stage doesn't change.
+    if let Some(entity_data) = entity_data {
+        if entity_data.stage == settings.stage {
+            return;
+        }
+        for entity in entity_data.entities.iter() {
+            commands.entity(*entity).despawn_recursive();
+        }
+    }
+
+    let stage = stages.get(settings.stage);
+    let mut entities: Vec<Entity> = Vec::new();
+
+    // Background layers.
+    for (i, layer) in stage.layers.iter().enumerate() {
+        entities.push(
+            commands
+                .spawn(SpriteBundle {
+                    sprite: Sprite {
+                        color: layer.color,
+                        custom_size: layer.size,
+                        ..default()
+                    },
+                    texture: layer.image.clone().unwrap_or_default(),
+                    transform: Transform {
+                        translation: layer.position.extend(BG_Z + 0.01 * i as f32),
+                        scale: Vec3::new(layer.scale, layer.scale, 1.0),
+                        ..default()
+                    },
                     ..default()
-                },
+                })
+                .insert(Parallax {
+                    position: layer.position,
+                    factor: layer.parallax,
+                })
+                .id(),
+        );
+    }
+
+    // Props drawn in front of the background layers.
+    let props_z = BG_Z + 0.01 * stage.layers.len() as f32;
+    for (i, prop) in stage.props.iter().enumerate() {
+        let mut entity = commands.spawn(SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                color: prop.color,
                 ..default()
-            })
-            .insert(AnimationTimer(Timer::from_seconds(
-                0.1,
-                TimerMode::Repeating,
-            )))
-            .id(),
-    );
+            },
+            texture_atlas: prop.texture_atlas.clone(),
+            transform: Transform {
+                translation: prop.position.extend(props_z + 0.01 * i as f32),
+                scale: Vec3::new(prop.scale, prop.scale, 1.0),
+                ..default()
+            },
+            ..default()
+        });
+
+        // Each prop animates at its own rate.
+        if prop.frames > 1 {
+            entity
+                .insert(Prop)
+                .insert(AnimationTimer(Timer::from_seconds(
+                    prop.frame_time,
+                    TimerMode::Repeating,
+                )));
+        }
 
-    commands.insert_resource(EntityData { entities });
+        entities.push(entity.id());
+    }
+
+    commands.insert_resource(EntityData {
+        entities,
+        stage: settings.stage,
+    });
 }
 
-/// Animate the shop sprite.
+/// Scroll background layers relative to the camera.
+fn parallax_system(
+    camera_query: Query<&Transform, With<StageCamera>>,
+    mut layer_query: Query<(&Parallax, &mut Transform), Without<StageCamera>>,
+) {
+    let camera = match camera_query.get_single() {
+        Ok(camera) => camera.translation.truncate(),
+        Err(_) => return,
+    };
+
+    for (parallax, mut transform) in &mut layer_query {
+        let position = parallax.position + camera * (1.0 - parallax.factor);
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
+/// Animate the stage props.
 fn animation_system(
     time: Res<Time>,
     texture_atlases: Res<Assets<TextureAtlas>>,
@@ -97,7 +187,7 @@ fn animation_system(
             &mut TextureAtlasSprite,
             &Handle<TextureAtlas>,
         ),
-        With<Shop>,
+        With<Prop>,
     >,
 ) {
     for (mut timer, mut sprite, texture_atlas_handle) in &mut query {
@@ -108,10 +198,3 @@ fn animation_system(
         }
     }
 }
-
-/// Cleanup resources.
-fn cleanup(mut commands: Commands, entity_data: Res<EntityData>) {
-    for entity in entity_data.entities.iter() {
-        commands.entity(*entity).despawn_recursive();
-    }
-}
@@ -0,0 +1,270 @@
+//! Spectator
+
+use crate::{
+    FightState, Message, PlayerInput, Transport, DISCONNECT_TIMEOUT, MAX_INPUTS_PER_PACKET,
+    MAX_PACKET_SIZE, SYNCHRONIZE_TIMEOUT,
+};
+use bevy::{prelude::*, utils::Instant};
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+};
+
+/// Number of ticks playback trails behind the newest input received so that short network
+/// hiccups don't stall it.
+const SPECTATOR_DELAY: u32 = 30;
+
+/// Present while watching a match as a spectator.
+#[derive(Resource, Clone, Debug)]
+pub(crate) struct Spectating {
+    /// Address of the player whose match is watched.
+    pub(crate) host: SocketAddr,
+}
+
+/// A spectator watching the match.
+struct Spectator {
+    addr: SocketAddr,
+
+    /// Next tick of inputs to send. `None` until the spectator has been sent a snapshot.
+    next_tick: Option<u32>,
+
+    last_seen: Instant,
+}
+
+/// Streams the confirmed inputs of a match to spectators. Spectators only ever receive so they
+/// can't affect the players.
+pub(crate) struct SpectatorServer {
+    socket: UdpSocket,
+    match_id: u8,
+    inputs: Vec<[PlayerInput; 2]>,
+    spectators: Vec<Spectator>,
+}
+
+impl SpectatorServer {
+    /// Listen for spectators on the given port.
+    pub(crate) fn bind(port: u16) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_nonblocking(true)?;
+        info!("Spectators can watch on port {}", port);
+        Ok(Self {
+            socket,
+            match_id: 0,
+            inputs: Vec::new(),
+            spectators: Vec::new(),
+        })
+    }
+
+    /// Start recording a new match. Spectators of the previous match have to join again.
+    pub(crate) fn start_match(&mut self) {
+        self.match_id = self.match_id.wrapping_add(1);
+        self.inputs.clear();
+        self.spectators.clear();
+    }
+
+    /// Number of ticks recorded so far.
+    pub(crate) fn recorded_ticks(&self) -> u32 {
+        self.inputs.len() as u32
+    }
+
+    /// Record the confirmed inputs of the next tick.
+    pub(crate) fn record(&mut self, inputs: [PlayerInput; 2]) {
+        self.inputs.push(inputs);
+    }
+
+    /// Process packets from spectators. Returns true if a spectator needs a snapshot.
+    pub(crate) fn poll(&mut self) -> bool {
+        let mut buf = [0_u8; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, addr)) => {
+                    if let Some(Message::Watch {
+                        match_id,
+                        next_tick,
+                    }) = Message::decode(&buf[..len])
+                    {
+                        self.watch(addr, match_id, next_tick);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    debug!("Couldn't receive packet: {}", err);
+                    break;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        self.spectators.retain(|spectator| {
+            let connected = now.duration_since(spectator.last_seen) <= DISCONNECT_TIMEOUT;
+            if !connected {
+                info!("Spectator {} left", spectator.addr);
+            }
+            connected
+        });
+
+        self.spectators.iter().any(|s| s.next_tick.is_none())
+    }
+
+    /// Handle a spectator asking to watch.
+    fn watch(&mut self, addr: SocketAddr, match_id: Option<u8>, next_tick: u32) {
+        let next_tick = match match_id {
+            Some(match_id) if match_id == self.match_id => {
+                Some(next_tick.min(self.recorded_ticks()))
+            }
+            // Still watching an earlier match.
+            Some(_) => return,
+            None => None,
+        };
+
+        let now = Instant::now();
+        match self.spectators.iter_mut().find(|s| s.addr == addr) {
+            Some(spectator) => {
+                spectator.next_tick = next_tick;
+                spectator.last_seen = now;
+            }
+            None => {
+                info!("Spectator {} joined", addr);
+                self.spectators.push(Spectator {
+                    addr,
+                    next_tick,
+                    last_seen: now,
+                });
+            }
+        }
+    }
+
+    /// Send a snapshot to spectators that need one and inputs they haven't received yet to the
+    /// rest. `snapshot` is the fight state at the start of the first tick not recorded yet.
+    pub(crate) fn send(&mut self, snapshot: Option<&FightState>) {
+        let snapshot = snapshot.map(|state| {
+            let message = Message::Snapshot {
+                match_id: self.match_id,
+                state: state.encode(),
+            };
+            (state.tick, message.encode())
+        });
+
+        let recorded_ticks = self.recorded_ticks();
+        for spectator in self.spectators.iter_mut() {
+            if let (None, Some((tick, packet))) = (spectator.next_tick, &snapshot) {
+                send_to(&self.socket, spectator.addr, packet);
+                spectator.next_tick = Some(*tick);
+            }
+
+            if let Some(start_tick) = spectator.next_tick {
+                // Sent even without new inputs so the spectator knows the match is still there.
+                let end_tick = recorded_ticks.min(start_tick + MAX_INPUTS_PER_PACKET);
+                let message = Message::Spectate {
+                    match_id: self.match_id,
+                    start_tick,
+                    inputs: self.inputs[start_tick as usize..end_tick as usize].to_vec(),
+                };
+                send_to(&self.socket, spectator.addr, &message.encode());
+            }
+        }
+    }
+}
+
+/// Send a packet to a spectator. Lost packets are sent again later.
+fn send_to(socket: &UdpSocket, addr: SocketAddr, packet: &[u8]) {
+    if let Err(err) = socket.send_to(packet, addr) {
+        debug!("Couldn't send packet to {}: {}", addr, err);
+    }
+}
+
+/// Receives a match from one of the players and plays it back a little behind.
+pub(crate) struct SpectatorSession {
+    transport: Box<dyn Transport>,
+    match_id: Option<u8>,
+    state: Option<FightState>,
+    /// Tick of the first input received.
+    start_tick: u32,
+    inputs: Vec<[PlayerInput; 2]>,
+    /// True until enough inputs have been received to start playing back.
+    buffering: bool,
+    started_at: Instant,
+    last_received: Option<Instant>,
+}
+
+impl SpectatorSession {
+    pub(crate) fn new(transport: Box<dyn Transport>) -> Self {
+        Self {
+            transport,
+            match_id: None,
+            state: None,
+            start_tick: 0,
+            inputs: Vec::new(),
+            buffering: true,
+            started_at: Instant::now(),
+            last_received: None,
+        }
+    }
+
+    /// Returns true if the match can't be reached or stopped sending.
+    pub(crate) fn is_disconnected(&self) -> bool {
+        match self.last_received {
+            Some(t) => Instant::now().duration_since(t) > DISCONNECT_TIMEOUT,
+            None => Instant::now().duration_since(self.started_at) > SYNCHRONIZE_TIMEOUT,
+        }
+    }
+
+    /// Next tick of inputs expected.
+    fn next_tick(&self) -> u32 {
+        self.start_tick + self.inputs.len() as u32
+    }
+
+    /// Process packets from the match and acknowledge what has been received.
+    pub(crate) fn poll(&mut self) {
+        while let Some(packet) = self.transport.receive() {
+            match Message::decode(&packet) {
+                Some(Message::Snapshot { match_id, state }) if self.match_id.is_none() => {
+                    if let Some(state) = FightState::decode(&state) {
+                        info!("Watching match from tick {}", state.tick);
+                        self.match_id = Some(match_id);
+                        self.start_tick = state.tick;
+                        self.state = Some(state);
+                        self.last_received = Some(Instant::now());
+                    }
+                }
+                Some(Message::Spectate {
+                    match_id,
+                    start_tick,
+                    inputs,
+                }) if Some(match_id) == self.match_id => {
+                    self.last_received = Some(Instant::now());
+                    let next_tick = self.next_tick();
+                    if start_tick <= next_tick {
+                        let skip = (next_tick - start_tick) as usize;
+                        self.inputs.extend(inputs.iter().skip(skip));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let message = Message::Watch {
+            match_id: self.match_id,
+            next_tick: self.next_tick(),
+        };
+        self.transport.send(&message.encode());
+    }
+
+    /// Takes the snapshot of the match to start watching from.
+    pub(crate) fn take_state(&mut self) -> Option<FightState> {
+        self.state.take()
+    }
+
+    /// Inputs to play back for a tick. Playback starts once inputs for `SPECTATOR_DELAY` ticks
+    /// have been received. Returns `None` while waiting for inputs.
+    pub(crate) fn inputs(&mut self, tick: u32) -> Option<[PlayerInput; 2]> {
+        let next_tick = self.next_tick();
+        if tick < self.start_tick || tick >= next_tick {
+            return None;
+        }
+        if self.buffering && next_tick < tick + SPECTATOR_DELAY {
+            return None;
+        }
+        self.buffering = false;
+        self.inputs.get((tick - self.start_tick) as usize).copied()
+    }
+}
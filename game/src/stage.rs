@@ -0,0 +1,454 @@
+//! Stage
+
+use crate::{common::*, GameAssets};
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use bevy_kira_audio::prelude::*;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// File listing the stages to load.
+const STAGE_LIST_PATH: &str = "stages/all.stages.ron";
+
+/// Handles loading stage definitions.
+pub(crate) struct StagePlugin;
+
+impl Plugin for StagePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Stage>()
+            .add_asset::<StageList>()
+            .init_asset_loader::<StageLoader>()
+            .init_asset_loader::<StageListLoader>()
+            .add_startup_system(load)
+            .add_system(setup);
+    }
+}
+
+/// A stage definition file.
+#[derive(Deserialize)]
+struct StageFile {
+    name: String,
+    music: String,
+    ground_y: f32,
+    min_x: f32,
+    max_x: f32,
+    #[serde(default = "default_width")]
+    width: f32,
+    layers: Vec<LayerFile>,
+    #[serde(default)]
+    props: Vec<PropFile>,
+}
+
+/// A background layer in a stage definition file. Layers without an image are drawn as a solid
+/// rectangle of the given size.
+#[derive(Deserialize)]
+struct LayerFile {
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    size: Option<(f32, f32)>,
+    #[serde(default)]
+    position: (f32, f32),
+    #[serde(default = "default_parallax")]
+    parallax: f32,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default = "default_color")]
+    color: (f32, f32, f32),
+}
+
+/// A prop in a stage definition file. Props with a single frame don't animate.
+#[derive(Deserialize)]
+struct PropFile {
+    image: String,
+    tile_size: (f32, f32),
+    #[serde(default = "default_frames")]
+    columns: usize,
+    #[serde(default = "default_frames")]
+    rows: usize,
+    #[serde(default = "default_frame_time")]
+    frame_time: f32,
+    #[serde(default)]
+    position: (f32, f32),
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default = "default_color")]
+    color: (f32, f32, f32),
+}
+
+/// A stage list file.
+#[derive(Deserialize)]
+struct StageListFile {
+    stages: Vec<String>,
+}
+
+fn default_width() -> f32 {
+    VIEW_WIDTH
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_parallax() -> f32 {
+    1.0
+}
+
+fn default_frames() -> usize {
+    1
+}
+
+fn default_frame_time() -> f32 {
+    0.1
+}
+
+fn default_color() -> (f32, f32, f32) {
+    (1.0, 1.0, 1.0)
+}
+
+/// A place to fight in.
+#[derive(TypeUuid, Clone, Debug)]
+#[uuid = "6f0b3e56-1c1e-4a53-9f0e-2d5c7d1f6a41"]
+pub(crate) struct Stage {
+    pub(crate) name: String,
+    pub(crate) music: Handle<AudioSource>,
+
+    /// Location of the ground along y-axis.
+    pub(crate) ground_y: f32,
+
+    /// Horizontal bounds players can move in.
+    pub(crate) min_x: f32,
+    pub(crate) max_x: f32,
+
+    /// Width of the stage centered on x = 0. The camera doesn't show anything past it.
+    pub(crate) width: f32,
+
+    /// Background layers drawn back to front.
+    pub(crate) layers: Vec<StageLayer>,
+
+    /// Sprites drawn in front of the background layers.
+    pub(crate) props: Vec<StageProp>,
+
+    /// Every image the stage uses. Stages with missing images aren't offered.
+    images: Vec<Handle<Image>>,
+}
+
+impl Stage {
+    /// Plain stage fought on when no stages could be loaded.
+    fn fallback(music: Handle<AudioSource>) -> Self {
+        let solid = |size: Vec2, position: Vec2, color: Color| StageLayer {
+            image: None,
+            size: Some(size),
+            position,
+            parallax: 1.0,
+            scale: 1.0,
+            color,
+        };
+
+        Self {
+            name: "TRAINING ROOM".to_string(),
+            music,
+            ground_y: -211.2,
+            min_x: -482.0,
+            max_x: 487.0,
+            width: VIEW_WIDTH,
+            layers: vec![
+                solid(
+                    Vec2::new(VIEW_WIDTH, VIEW_HEIGHT),
+                    Vec2::ZERO,
+                    Color::rgb(0.25, 0.3, 0.35),
+                ),
+                solid(
+                    Vec2::new(VIEW_WIDTH, 80.0),
+                    Vec2::new(0.0, -251.2),
+                    Color::rgb(0.15, 0.15, 0.15),
+                ),
+            ],
+            props: Vec::new(),
+            images: Vec::new(),
+        }
+    }
+}
+
+/// A background layer.
+#[derive(Clone, Debug)]
+pub(crate) struct StageLayer {
+    pub(crate) image: Option<Handle<Image>>,
+    pub(crate) size: Option<Vec2>,
+    pub(crate) position: Vec2,
+
+    /// How much the layer moves with the camera. Layers at 1.0 move with the fighters while
+    /// smaller values make layers look further away.
+    pub(crate) parallax: f32,
+
+    pub(crate) scale: f32,
+    pub(crate) color: Color,
+}
+
+/// A prop.
+#[derive(Clone, Debug)]
+pub(crate) struct StageProp {
+    pub(crate) texture_atlas: Handle<TextureAtlas>,
+    pub(crate) frames: usize,
+    pub(crate) frame_time: f32,
+    pub(crate) position: Vec2,
+    pub(crate) scale: f32,
+    pub(crate) color: Color,
+}
+
+/// Stages in the order shown on the stage select screen.
+#[derive(TypeUuid, Debug)]
+#[uuid = "0d7e2a7c-5b44-4c0e-8f3a-96e1b2d4c8f7"]
+pub(crate) struct StageList {
+    stages: Vec<Handle<Stage>>,
+}
+
+/// Loads `.stage.ron` files.
+#[derive(Default)]
+struct StageLoader;
+
+impl AssetLoader for StageLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let file: StageFile = ron::de::from_bytes(bytes)?;
+            let mut dependencies: Vec<AssetPath<'static>> = Vec::new();
+            let mut images: Vec<Handle<Image>> = Vec::new();
+
+            let music_path = asset_path(&file.music);
+            let music = load_context.get_handle(music_path.clone());
+            dependencies.push(music_path);
+
+            let layers = file
+                .layers
+                .iter()
+                .map(|layer| {
+                    let image = layer.image.as_ref().map(|image| {
+                        let image_path = asset_path(image);
+                        let image: Handle<Image> = load_context.get_handle(image_path.clone());
+                        dependencies.push(image_path);
+                        images.push(image.clone());
+                        image
+                    });
+                    StageLayer {
+                        image,
+                        size: layer.size.map(|(w, h)| Vec2::new(w, h)),
+                        position: Vec2::new(layer.position.0, layer.position.1),
+                        parallax: layer.parallax,
+                        scale: layer.scale,
+                        color: Color::rgb(layer.color.0, layer.color.1, layer.color.2),
+                    }
+                })
+                .collect();
+
+            let props = file
+                .props
+                .iter()
+                .enumerate()
+                .map(|(i, prop)| {
+                    let image_path = asset_path(&prop.image);
+                    let image: Handle<Image> = load_context.get_handle(image_path.clone());
+                    images.push(image.clone());
+                    let texture_atlas = TextureAtlas::from_grid(
+                        image,
+                        Vec2::new(prop.tile_size.0, prop.tile_size.1),
+                        prop.columns,
+                        prop.rows,
+                        None,
+                        None,
+                    );
+                    let texture_atlas = load_context.set_labeled_asset(
+                        &format!("prop{}", i),
+                        LoadedAsset::new(texture_atlas).with_dependency(image_path),
+                    );
+                    StageProp {
+                        texture_atlas,
+                        frames: prop.columns * prop.rows,
+                        frame_time: prop.frame_time,
+                        position: Vec2::new(prop.position.0, prop.position.1),
+                        scale: prop.scale,
+                        color: Color::rgb(prop.color.0, prop.color.1, prop.color.2),
+                    }
+                })
+                .collect();
+
+            let stage = Stage {
+                name: file.name,
+                music,
+                ground_y: file.ground_y,
+                min_x: file.min_x,
+                max_x: file.max_x,
+                width: file.width,
+                layers,
+                props,
+                images,
+            };
+            load_context.set_default_asset(LoadedAsset::new(stage).with_dependencies(dependencies));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["stage.ron"]
+    }
+}
+
+/// Loads `.stages.ron` files listing the stages to load.
+#[derive(Default)]
+struct StageListLoader;
+
+impl AssetLoader for StageListLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let file: StageListFile = ron::de::from_bytes(bytes)?;
+            let paths: Vec<AssetPath<'static>> =
+                file.stages.iter().map(|s| asset_path(s)).collect();
+            let stages = paths
+                .iter()
+                .map(|path| load_context.get_handle(path.clone()))
+                .collect();
+            load_context
+                .set_default_asset(LoadedAsset::new(StageList { stages }).with_dependencies(paths));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["stages.ron"]
+    }
+}
+
+/// Path of an asset relative to the assets folder.
+fn asset_path(path: &str) -> AssetPath<'static> {
+    AssetPath::new(PathBuf::from(path), None)
+}
+
+/// Stage list being loaded.
+#[derive(Resource)]
+struct StageListHandle(Handle<StageList>);
+
+/// Stages that finished loading.
+#[derive(Resource)]
+pub(crate) struct Stages(Vec<Stage>);
+
+impl Stages {
+    /// Identifies the stages on offer by their names. Peers pick stages by index so they need the
+    /// same stages to fight on the same one.
+    pub(crate) fn checksum(&self) -> u32 {
+        // FNV-1a over the names in order.
+        self.0
+            .iter()
+            .flat_map(|stage| stage.name.bytes().chain([0]))
+            .fold(0x811c_9dc5, |hash, b| {
+                (hash ^ b as u32).wrapping_mul(0x0100_0193)
+            })
+    }
+
+    /// Stage at an index. Out of range indices wrap around.
+    pub(crate) fn get(&self, index: u8) -> &Stage {
+        &self.0[index as usize % self.0.len()]
+    }
+
+    /// Next or previous stage index.
+    pub(crate) fn cycle(&self, index: u8, forward: bool) -> u8 {
+        let n = self.0.len();
+        let i = index as usize % n;
+        (if forward {
+            (i + 1) % n
+        } else {
+            (i + n - 1) % n
+        }) as u8
+    }
+}
+
+/// Start loading the stage list.
+fn load(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(StageListHandle(asset_server.load(STAGE_LIST_PATH)));
+}
+
+/// Collect the stages once they have finished loading. Stages that couldn't be loaded completely
+/// are left out. A plain stage is used if none could be loaded.
+fn setup(
+    mut commands: Commands,
+    stages: Option<Res<Stages>>,
+    assets: Option<Res<GameAssets>>,
+    stage_list: Res<StageListHandle>,
+    asset_server: Res<AssetServer>,
+    stage_lists: Res<Assets<StageList>>,
+    stage_assets: Res<Assets<Stage>>,
+) {
+    if stages.is_some() {
+        return;
+    }
+    let assets = match assets {
+        Some(assets) => assets,
+        None => return,
+    };
+
+    // Stages and their images load after the list so wait for all of them before reporting
+    // anything. This runs every frame until then.
+    let finished = |state: LoadState| matches!(state, LoadState::Loaded | LoadState::Failed);
+    let stage_list = match asset_server.get_load_state(&stage_list.0) {
+        LoadState::Failed => None,
+        _ => match stage_lists.get(&stage_list.0) {
+            Some(stage_list) => Some(stage_list),
+            None => return,
+        },
+    };
+    let handles = stage_list.map_or(&[][..], |stage_list| &stage_list.stages[..]);
+    for handle in handles.iter() {
+        match (
+            asset_server.get_load_state(handle),
+            stage_assets.get(handle),
+        ) {
+            (LoadState::Failed, _) => (),
+            (_, Some(stage)) => {
+                if !stage
+                    .images
+                    .iter()
+                    .all(|image| finished(asset_server.get_load_state(image)))
+                {
+                    return;
+                }
+            }
+            (_, None) => return,
+        }
+    }
+
+    if stage_list.is_none() {
+        error!("Couldn't load stage list {}", STAGE_LIST_PATH);
+    }
+
+    let mut stages: Vec<Stage> = Vec::new();
+    for handle in handles.iter() {
+        match stage_assets.get(handle) {
+            None => warn!("Couldn't load stage {:?}", handle),
+            Some(stage)
+                if stage
+                    .images
+                    .iter()
+                    .any(|image| asset_server.get_load_state(image) == LoadState::Failed) =>
+            {
+                warn!("Leaving out stage {} because of missing images", stage.name);
+            }
+            Some(stage) => stages.push(stage.clone()),
+        }
+    }
+
+    if stages.is_empty() {
+        error!("Couldn't load any stages, using a plain stage");
+        stages.push(Stage::fallback(assets.main_menu_audio.clone()));
+    }
+    commands.insert_resource(Stages(stages));
+}
@@ -0,0 +1,243 @@
+//! Stage Select
+
+use crate::{
+    menu_background, menu_border, menu_button, menu_button_text, menu_root, GameAssets, GameState,
+    MatchSettings, Stages,
+};
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+
+/// Handles picking the stage before a match.
+pub(crate) struct StageSelectPlugin;
+
+impl Plugin for StageSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<StageSelectCommand>()
+            .add_system_set(SystemSet::on_enter(GameState::StageSelect).with_system(setup))
+            .add_system_set(
+                SystemSet::on_update(GameState::StageSelect)
+                    .with_system(input_system.before(stage_select_system))
+                    .with_system(button_press_system.before(stage_select_system))
+                    .with_system(stage_select_system)
+                    .with_system(display_system.after(stage_select_system)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::StageSelect).with_system(cleanup));
+    }
+}
+
+/// Stage select entities.
+#[derive(Resource)]
+struct EntityData {
+    entities: Vec<Entity>,
+}
+
+/// Actions from keyboard and buttons.
+enum StageSelectCommand {
+    Fight,
+    Back,
+    Stage { forward: bool },
+    RoundTime { forward: bool },
+    TimeOver { forward: bool },
+}
+
+/// Represents stage select buttons.
+#[derive(Component)]
+enum StageSelectButton {
+    Fight,
+    Stage,
+    RoundTime,
+    TimeOver,
+    Back,
+}
+
+/// Text showing the stage name.
+#[derive(Component)]
+struct StageText;
+
+/// Text showing the round time.
+#[derive(Component)]
+struct RoundTimeText;
+
+/// Text showing how the winner is decided when time runs out.
+#[derive(Component)]
+struct TimeOverText;
+
+/// Setup the stage select screen. The scene behind it shows the highlighted stage.
+fn setup(mut commands: Commands, assets: Res<GameAssets>, audio: Res<Audio>) {
+    audio.play(assets.main_menu_audio.clone()).looped();
+
+    let mut entities: Vec<Entity> = Vec::new();
+
+    entities.push(
+        commands
+            .spawn(menu_root())
+            .with_children(|parent| {
+                parent.spawn(menu_border()).with_children(|parent| {
+                    parent.spawn(menu_background()).with_children(|parent| {
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent.spawn(menu_button_text(&assets, "BACK"));
+                                parent.spawn(ImageBundle {
+                                    image: UiImage(assets.escape_key_image.clone()),
+                                    transform: Transform::from_scale(Vec3::new(0.58, 0.58, 0.58)),
+                                    ..default()
+                                });
+                            })
+                            .insert(StageSelectButton::Back);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent.spawn(menu_button_text(&assets, "FIGHT"));
+                                parent.spawn(ImageBundle {
+                                    image: UiImage(assets.return_key_image.clone()),
+                                    transform: Transform::from_scale(Vec3::new(0.5, 0.5, 0.5)),
+                                    ..default()
+                                });
+                            })
+                            .insert(StageSelectButton::Fight);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent
+                                    .spawn(menu_button_text(&assets, ""))
+                                    .insert(StageText);
+                            })
+                            .insert(StageSelectButton::Stage);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent
+                                    .spawn(menu_button_text(&assets, ""))
+                                    .insert(RoundTimeText);
+                            })
+                            .insert(StageSelectButton::RoundTime);
+
+                        parent
+                            .spawn(menu_button())
+                            .with_children(|parent| {
+                                parent
+                                    .spawn(menu_button_text(&assets, ""))
+                                    .insert(TimeOverText);
+                            })
+                            .insert(StageSelectButton::TimeOver);
+
+                        parent.spawn(menu_button_text(&assets, "SELECT STAGE"));
+                    });
+                });
+            })
+            .id(),
+    );
+
+    commands.insert_resource(EntityData { entities });
+}
+
+/// Handle keyboard input.
+fn input_system(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut stage_select_commands: EventWriter<StageSelectCommand>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        stage_select_commands.send(StageSelectCommand::Fight);
+        keyboard_input.clear_just_pressed(KeyCode::Return);
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        stage_select_commands.send(StageSelectCommand::Back);
+        keyboard_input.clear_just_pressed(KeyCode::Escape);
+    } else if keyboard_input.any_just_pressed([KeyCode::Left, KeyCode::A]) {
+        stage_select_commands.send(StageSelectCommand::Stage { forward: false });
+    } else if keyboard_input.any_just_pressed([KeyCode::Right, KeyCode::D]) {
+        stage_select_commands.send(StageSelectCommand::Stage { forward: true });
+    } else if keyboard_input.any_just_pressed([KeyCode::Up, KeyCode::W]) {
+        stage_select_commands.send(StageSelectCommand::RoundTime { forward: true });
+    } else if keyboard_input.any_just_pressed([KeyCode::Down, KeyCode::S]) {
+        stage_select_commands.send(StageSelectCommand::TimeOver { forward: true });
+    }
+}
+
+/// Processes button press.
+#[allow(clippy::type_complexity)]
+fn button_press_system(
+    buttons: Query<(&Interaction, &StageSelectButton), (Changed<Interaction>, With<Button>)>,
+    mut stage_select_commands: EventWriter<StageSelectCommand>,
+) {
+    for (interaction, button) in buttons.iter() {
+        if *interaction == Interaction::Clicked {
+            stage_select_commands.send(match button {
+                StageSelectButton::Fight => StageSelectCommand::Fight,
+                StageSelectButton::Stage => StageSelectCommand::Stage { forward: true },
+                StageSelectButton::RoundTime => StageSelectCommand::RoundTime { forward: true },
+                StageSelectButton::TimeOver => StageSelectCommand::TimeOver { forward: true },
+                StageSelectButton::Back => StageSelectCommand::Back,
+            });
+        }
+    }
+}
+
+/// Handle stage select commands.
+fn stage_select_system(
+    mut stage_select_commands: EventReader<StageSelectCommand>,
+    mut settings: ResMut<MatchSettings>,
+    stages: Res<Stages>,
+    mut state: ResMut<State<GameState>>,
+) {
+    for command in stage_select_commands.iter() {
+        match command {
+            StageSelectCommand::Fight => {
+                state
+                    .set(GameState::InGame)
+                    .expect("Couldn't switch state to InGame");
+                return;
+            }
+            StageSelectCommand::Back => {
+                state
+                    .set(GameState::CharacterSelect)
+                    .expect("Couldn't switch state to CharacterSelect");
+                return;
+            }
+            StageSelectCommand::Stage { forward } => {
+                settings.stage = stages.cycle(settings.stage, *forward);
+            }
+            StageSelectCommand::RoundTime { forward } => settings.cycle_round_time(*forward),
+            StageSelectCommand::TimeOver { forward } => settings.cycle_time_over(*forward),
+        }
+    }
+}
+
+/// Update stage select text.
+#[allow(clippy::type_complexity)]
+fn display_system(
+    settings: Res<MatchSettings>,
+    stages: Res<Stages>,
+    mut text_query: Query<(
+        &mut Text,
+        Option<&StageText>,
+        Option<&RoundTimeText>,
+        Option<&TimeOverText>,
+    )>,
+) {
+    for (mut text, stage, round_time, time_over) in &mut text_query {
+        let value = if stage.is_some() {
+            format!("STAGE: {}", stages.get(settings.stage).name)
+        } else if round_time.is_some() {
+            format!("ROUND TIME: {}", settings.round_time_text())
+        } else if time_over.is_some() {
+            format!("TIME OVER: {}", settings.time_over.name())
+        } else {
+            continue;
+        };
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+/// Cleanup resources.
+fn cleanup(mut commands: Commands, entity_data: Res<EntityData>, audio: Res<Audio>) {
+    for entity in entity_data.entities.iter() {
+        commands.entity(*entity).despawn_recursive();
+    }
+    audio.stop();
+}
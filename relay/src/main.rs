@@ -0,0 +1,306 @@
+//! Relay
+//!
+//! Forwards packets between two players that can't reach each other directly, e.g. a browser
+//! (which can only use WebSockets) and a desktop (which uses UDP). Players join a room by name
+//! and everything else they send is forwarded to the other player in the same room.
+
+use log::{error, info, warn};
+use std::{
+    collections::HashMap,
+    io,
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tungstenite::{accept, Message};
+
+/// First byte of the packet that joins a room. Must match the game.
+const RELAY_JOIN: u8 = 0xF2;
+
+/// Default port for WebSocket clients.
+const DEFAULT_WS_PORT: u16 = 7100;
+
+/// Default port for UDP clients.
+const DEFAULT_UDP_PORT: u16 = 7101;
+
+/// Largest datagram we expect to receive.
+const MAX_PACKET_SIZE: usize = 1024;
+
+/// UDP clients that haven't sent anything for this long have left.
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the UDP loop waits for a packet before checking for clients that have left.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a WebSocket client waits for a message before forwarding queued packets.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Number of players in a room.
+const ROOM_SIZE: usize = 2;
+
+/// Identifies a connected client.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum PeerId {
+    Udp(SocketAddr),
+    WebSocket(u64),
+}
+
+/// A client that joined a room.
+struct Client {
+    room: String,
+    last_seen: Instant,
+}
+
+/// Rooms and the clients in them.
+#[derive(Default)]
+struct Relay {
+    clients: HashMap<PeerId, Client>,
+
+    /// Packets waiting to be sent to WebSocket clients.
+    websockets: HashMap<u64, mpsc::Sender<Vec<u8>>>,
+}
+
+impl Relay {
+    /// Handle a packet from a client.
+    fn receive(&mut self, socket: &UdpSocket, from: PeerId, packet: &[u8]) {
+        match packet.split_first() {
+            Some((&RELAY_JOIN, room)) => self.join(from, String::from_utf8_lossy(room).into()),
+            Some(_) => self.forward(socket, from, packet),
+            None => (),
+        }
+    }
+
+    /// Remove UDP clients that haven't sent anything for a while so their rooms can be joined
+    /// again. WebSocket clients leave when their connection closes.
+    fn expire(&mut self) {
+        let now = Instant::now();
+        self.clients.retain(|id, client| match id {
+            PeerId::Udp(_) if now.duration_since(client.last_seen) >= PEER_TIMEOUT => {
+                info!("{:?} timed out of room {}", id, client.room);
+                false
+            }
+            _ => true,
+        });
+    }
+
+    /// Add a client to a room unless the room is full.
+    fn join(&mut self, peer: PeerId, room: String) {
+        self.expire();
+        let now = Instant::now();
+
+        match self.clients.get_mut(&peer) {
+            Some(client) if client.room == room => {
+                client.last_seen = now;
+                return;
+            }
+            _ => (),
+        }
+
+        let members = self
+            .clients
+            .iter()
+            .filter(|(id, client)| **id != peer && client.room == room)
+            .count();
+        if members >= ROOM_SIZE {
+            warn!("{:?} can't join full room {}", peer, room);
+            return;
+        }
+
+        info!("{:?} joined room {}", peer, room);
+        self.clients.insert(
+            peer,
+            Client {
+                room,
+                last_seen: now,
+            },
+        );
+    }
+
+    /// Remove a client.
+    fn leave(&mut self, peer: PeerId) {
+        if let Some(client) = self.clients.remove(&peer) {
+            info!("{:?} left room {}", peer, client.room);
+        }
+        if let PeerId::WebSocket(id) = peer {
+            self.websockets.remove(&id);
+        }
+    }
+
+    /// Send a packet to the other client in the sender's room.
+    fn forward(&mut self, socket: &UdpSocket, from: PeerId, packet: &[u8]) {
+        let room = match self.clients.get_mut(&from) {
+            Some(client) => {
+                client.last_seen = Instant::now();
+                client.room.clone()
+            }
+            None => return,
+        };
+
+        let to = self
+            .clients
+            .iter()
+            .find(|(id, client)| **id != from && client.room == room)
+            .map(|(id, _)| *id);
+
+        match to {
+            Some(PeerId::Udp(addr)) => {
+                if let Err(err) = socket.send_to(packet, addr) {
+                    warn!("Couldn't send packet to {}: {}", addr, err);
+                }
+            }
+            Some(PeerId::WebSocket(id)) => {
+                if let Some(sender) = self.websockets.get(&id) {
+                    let _ = sender.send(packet.to_vec());
+                }
+            }
+            None => (),
+        }
+    }
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut ws_port = DEFAULT_WS_PORT;
+    let mut udp_port = DEFAULT_UDP_PORT;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let port = match arg.as_str() {
+            "--ws-port" => &mut ws_port,
+            "--udp-port" => &mut udp_port,
+            _ => exit(&format!("Unknown argument {}", arg)),
+        };
+        *port = args
+            .next()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(|| exit(&format!("Invalid value for {}", arg)));
+    }
+
+    let relay = Arc::new(Mutex::new(Relay::default()));
+    let socket = UdpSocket::bind(("0.0.0.0", udp_port))
+        .map(Arc::new)
+        .unwrap_or_else(|err| exit(&format!("Couldn't bind UDP port {}: {}", udp_port, err)));
+    let listener = TcpListener::bind(("0.0.0.0", ws_port)).unwrap_or_else(|err| {
+        exit(&format!(
+            "Couldn't bind WebSocket port {}: {}",
+            ws_port, err
+        ))
+    });
+
+    info!(
+        "Relaying WebSockets on port {} and UDP on port {}",
+        ws_port, udp_port
+    );
+
+    {
+        let relay = relay.clone();
+        let socket = socket.clone();
+        thread::spawn(move || udp_loop(&relay, &socket));
+    }
+
+    let next_id = AtomicU64::new(0);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let id = next_id.fetch_add(1, Ordering::Relaxed);
+                let relay = relay.clone();
+                let socket = socket.clone();
+                thread::spawn(move || {
+                    if let Err(err) = websocket_loop(&relay, &socket, stream, id) {
+                        warn!("WebSocket {} closed: {}", id, err);
+                    }
+                    relay.lock().unwrap().leave(PeerId::WebSocket(id));
+                });
+            }
+            Err(err) => warn!("Couldn't accept connection: {}", err),
+        }
+    }
+}
+
+/// Log an error and exit.
+fn exit(err: &str) -> ! {
+    error!("{}", err);
+    std::process::exit(1);
+}
+
+/// Receive packets from UDP clients and drop the ones that have gone quiet.
+fn udp_loop(relay: &Mutex<Relay>, socket: &UdpSocket) {
+    if let Err(err) = socket.set_read_timeout(Some(EXPIRE_INTERVAL)) {
+        error!("Couldn't set UDP read timeout: {}", err);
+    }
+
+    let mut buf = [0_u8; MAX_PACKET_SIZE];
+    let mut last_expired = Instant::now();
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, addr)) => {
+                relay
+                    .lock()
+                    .unwrap()
+                    .receive(socket, PeerId::Udp(addr), &buf[..len]);
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(err) => warn!("Couldn't receive packet: {}", err),
+        }
+
+        if last_expired.elapsed() >= EXPIRE_INTERVAL {
+            relay.lock().unwrap().expire();
+            last_expired = Instant::now();
+        }
+    }
+}
+
+/// Receive packets from a WebSocket client and send it packets forwarded from its peer.
+fn websocket_loop(
+    relay: &Mutex<Relay>,
+    socket: &UdpSocket,
+    stream: TcpStream,
+    id: u64,
+) -> Result<(), Box<tungstenite::Error>> {
+    stream.set_nodelay(true).map_err(tungstenite::Error::Io)?;
+    let mut websocket = accept(stream).map_err(|err| match err {
+        tungstenite::HandshakeError::Failure(err) => err,
+        tungstenite::HandshakeError::Interrupted(_) => {
+            tungstenite::Error::Io(io::ErrorKind::WouldBlock.into())
+        }
+    })?;
+    websocket
+        .get_ref()
+        .set_read_timeout(Some(POLL_INTERVAL))
+        .map_err(tungstenite::Error::Io)?;
+
+    let (sender, receiver) = mpsc::channel();
+    relay.lock().unwrap().websockets.insert(id, sender);
+
+    loop {
+        match websocket.read_message() {
+            Ok(Message::Binary(packet)) => {
+                relay
+                    .lock()
+                    .unwrap()
+                    .receive(socket, PeerId::WebSocket(id), &packet);
+            }
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(err))
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        while let Ok(packet) = receiver.try_recv() {
+            websocket.write_message(Message::Binary(packet))?;
+        }
+    }
+}